@@ -0,0 +1,157 @@
+// Panning and zooming by changing the viewBox, tooltips from the data-*
+// attributes written with --osm-tags, layer toggles and a search over names.
+(function () {
+  const svg = document.querySelector('#map svg');
+  const tooltip = document.getElementById('tooltip');
+  const base = svg.viewBox.baseVal;
+  const full = { x: base.x, y: base.y, width: base.width, height: base.height };
+  let view = Object.assign({}, full);
+
+  function show() {
+    svg.setAttribute('viewBox', [view.x, view.y, view.width, view.height].join(' '));
+  }
+
+  // Where a mouse event is, in map units.
+  function toMap(event) {
+    const point = svg.createSVGPoint();
+    point.x = event.clientX;
+    point.y = event.clientY;
+    return point.matrixTransform(svg.getScreenCTM().inverse());
+  }
+
+  svg.addEventListener('wheel', (event) => {
+    event.preventDefault();
+    const at = toMap(event);
+    let factor = Math.exp(event.deltaY * 0.0015);
+    // Between a thousandth of the map and four times it.
+    factor = Math.min(Math.max(factor, full.width / 1000 / view.width), (full.width * 4) / view.width);
+    view.x = at.x - (at.x - view.x) * factor;
+    view.y = at.y - (at.y - view.y) * factor;
+    view.width *= factor;
+    view.height *= factor;
+    show();
+  }, { passive: false });
+
+  let drag = null;
+  svg.addEventListener('pointerdown', (event) => {
+    drag = toMap(event);
+    svg.setPointerCapture(event.pointerId);
+    svg.classList.add('dragging');
+    tooltip.hidden = true;
+  });
+  svg.addEventListener('pointerup', () => {
+    drag = null;
+    svg.classList.remove('dragging');
+  });
+  svg.addEventListener('dblclick', () => {
+    view = Object.assign({}, full);
+    show();
+  });
+
+  // The name, address, any other kept tags and the OSM id of a feature.
+  function describe(element) {
+    const data = element.dataset;
+    const lines = [];
+    if (data.name) {
+      lines.push(data.name);
+    }
+    const address = [data.addrHousenumber, data.addrStreet].filter(Boolean).join(' ');
+    if (address) {
+      lines.push(address);
+    }
+    const shown = ['name', 'addrHousenumber', 'addrStreet', 'osmId', 'osmRelation'];
+    for (const key of Object.keys(data)) {
+      if (!shown.includes(key)) {
+        lines.push(key + ': ' + data[key]);
+      }
+    }
+    lines.push(data.osmId + (data.osmRelation ? ' (relation/' + data.osmRelation + ')' : ''));
+    return lines.join('\n');
+  }
+
+  svg.addEventListener('pointermove', (event) => {
+    if (drag) {
+      const at = toMap(event);
+      view.x -= at.x - drag.x;
+      view.y -= at.y - drag.y;
+      show();
+      return;
+    }
+    const feature = event.target.closest('[data-osm-id]');
+    if (!feature) {
+      tooltip.hidden = true;
+      return;
+    }
+    tooltip.textContent = describe(feature);
+    tooltip.style.left = event.clientX + 12 + 'px';
+    tooltip.style.top = event.clientY + 12 + 'px';
+    tooltip.hidden = false;
+  });
+  svg.addEventListener('pointerleave', () => {
+    tooltip.hidden = true;
+  });
+
+  // A checkbox for each layer with something on it, top layer first.
+  const layers = document.getElementById('layers');
+  const groups = Array.from(svg.querySelectorAll('g[data-layer]'));
+  for (const group of groups.reverse()) {
+    if (!group.childElementCount) {
+      continue;
+    }
+    const label = document.createElement('label');
+    const checkbox = document.createElement('input');
+    checkbox.type = 'checkbox';
+    checkbox.checked = true;
+    checkbox.addEventListener('change', () => {
+      group.style.display = checkbox.checked ? '' : 'none';
+    });
+    label.append(checkbox, ' ' + group.dataset.layer);
+    layers.append(label);
+  }
+
+  // Zooms to a feature, with some room around it, and highlights it.
+  let found = null;
+  function zoomTo(element) {
+    const box = element.getBBox();
+    const margin = Math.max(box.width, box.height, full.width / 200);
+    view = {
+      x: box.x - margin,
+      y: box.y - margin,
+      width: box.width + 2 * margin,
+      height: box.height + 2 * margin,
+    };
+    show();
+    if (found) {
+      found.classList.remove('found');
+    }
+    found = element;
+    found.classList.add('found');
+  }
+
+  const search = document.getElementById('search');
+  const results = document.getElementById('results');
+  search.addEventListener('input', () => {
+    results.replaceChildren();
+    const query = search.value.trim().toLowerCase();
+    if (query.length < 2) {
+      return;
+    }
+    const seen = new Set();
+    for (const element of svg.querySelectorAll('[data-name]')) {
+      const name = element.dataset.name;
+      if (!name.toLowerCase().includes(query) || seen.has(name)) {
+        continue;
+      }
+      seen.add(name);
+      const item = document.createElement('li');
+      const button = document.createElement('button');
+      button.textContent = name;
+      button.addEventListener('click', () => zoomTo(element));
+      item.append(button);
+      results.append(item);
+      if (seen.size === 50) {
+        break;
+      }
+    }
+  });
+})();
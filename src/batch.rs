@@ -0,0 +1,419 @@
+// `mapfun batch`: renders every job in a manifest, in parallel.
+//
+// A manifest is a JSON file like:
+//
+//     {
+//       "rules": "rules/default.rules",
+//       "jobs": [
+//         {
+//           "name": "midtown",
+//           "input": "nyc",
+//           "crop": [-74.01, 40.74, -73.96, 40.77],
+//           "theme": "gray",
+//           "formats": ["svg", "html"],
+//           "sizes": [1000, 4000],
+//           "overlays": ["data/geojson/subway_lines.pretty.geojson", "run.gpx"],
+//           "colour_by": "speed",
+//           "overlay_crs": "EPSG:2263",
+//           "terrain": {"dir": "data/srtm", "interval": 10, "hillshade": true},
+//           "buildings": "shadow",
+//           "sun": 225,
+//           "osm_tags": ["name", "building"],
+//           "geojson": {"split": true, "projected": false},
+//           "grid": {"metric": 1000},
+//           "furniture": {"title": "Midtown", "scale_bar": "bottom-left",
+//                         "legend": "top-right", "attribution": "bottom-right"}
+//         }
+//       ]
+//     }
+//
+// Only `name` and `input` are required.  `input` is a path or the name of an
+// extract in `data/osm`, and each input is parsed once however many jobs use
+// it.  Output goes to `data/svg/<name>.svg`, or `<name>-<size>.svg` when there
+// are several sizes, and GeoJSON to `data/export/<name>.geojson`.
+// `overlay_crs` is the coordinate system of overlays which don't declare their
+// own.
+//
+// A job which fails, or whose input or overlays are missing, is reported and
+// the other jobs still run.
+
+use buildings::Relief;
+use crs::Crs;
+use export::{self, GeojsonOptions};
+use furniture::Furniture;
+use gpx;
+use grid::Grid;
+use html;
+use osm_load::{self, Feature, Geometry, RangeIdx};
+use osm_xml::OSM;
+use rules::RuleSet;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use terrain::TerrainOptions;
+use theme;
+use timing;
+use {
+    default_overlays, kind_for, load_rules, process, process_grid, process_terrain, render,
+    Overlays, RenderOptions, DEFAULT_SUN, TARGET_HEIGHT,
+};
+
+#[derive(Deserialize)]
+pub struct Manifest {
+    pub rules: Option<String>,
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Deserialize)]
+pub struct Job {
+    pub name: String,
+    pub input: String,
+    // `[min_lon, min_lat, max_lon, max_lat]` in degrees.
+    pub crop: Option<[f64; 4]>,
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    #[serde(default = "default_formats")]
+    pub formats: Vec<String>,
+    // Heights of the rendered maps; empty means the map's own units.
+    #[serde(default)]
+    pub sizes: Vec<f64>,
+    pub overlays: Option<Vec<String>>,
+    // How to colour the tracks of GPX overlays.
+    pub colour_by: Option<gpx::ColourBy>,
+    pub overlay_crs: Option<String>,
+    // Contours and hillshade; `interval` defaults to 10 m.
+    pub terrain: Option<TerrainOptions>,
+    // Flat (the default), with shadows or extruded.
+    #[serde(default)]
+    pub buildings: Relief,
+    // Where the light for shadows comes from, in degrees clockwise from north.
+    pub sun: Option<f64>,
+    // Write OSM ids, plus the values of these tags, on features.
+    #[serde(default)]
+    pub osm_ids: bool,
+    #[serde(default)]
+    pub osm_tags: Vec<String>,
+    // Also write the processed features as GeoJSON.
+    pub geojson: Option<GeojsonOptions>,
+    // A graticule (`{"graticule": 0.01}`, in degrees) or metric grid
+    // (`{"metric": 1000}`, in metres).
+    pub grid: Option<Grid>,
+    // Title, scale bar, north arrow, legend and attribution.
+    #[serde(default)]
+    pub furniture: Furniture,
+}
+
+fn default_theme() -> String {
+    "gray".into()
+}
+
+fn default_formats() -> Vec<String> {
+    vec!["svg".into()]
+}
+
+pub fn input_path(input: &str) -> String {
+    if Path::new(input).exists() {
+        input.into()
+    } else {
+        format!("./data/osm/{}.osm", input)
+    }
+}
+
+// Where maps and exports are written, in `svg` and `export` below it.  Exports
+// are kept apart from `data/geojson`, where overlays are read from.
+const OUT_DIR: &str = "./data";
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Catches mistakes before any of the (slow) rendering starts.
+fn check(job: &Job) -> io::Result<()> {
+    if theme::by_name(&job.theme).is_none() {
+        return Err(invalid(format!(
+            "{}: unknown theme `{}`",
+            job.name, job.theme
+        )));
+    }
+    let supported = |format: &&String| *format == "svg" || *format == "html";
+    if let Some(format) = job.formats.iter().find(|format| !supported(format)) {
+        return Err(invalid(format!(
+            "{}: unsupported format `{}`",
+            job.name, format
+        )));
+    }
+    if let Some(name) = &job.overlay_crs {
+        Crs::by_name(name).map_err(|e| invalid(format!("{}: {}", job.name, e)))?;
+    }
+    if let Some([min_lon, min_lat, max_lon, max_lat]) = job.crop {
+        if min_lon >= max_lon || min_lat >= max_lat {
+            return Err(invalid(format!("{}: empty crop box", job.name)));
+        }
+    }
+    if let Some(Grid::Graticule(interval)) | Some(Grid::Metric(interval)) = job.grid {
+        if interval <= 0.0 {
+            return Err(invalid(format!(
+                "{}: grid intervals must be positive",
+                job.name
+            )));
+        }
+    }
+    let inputs =
+        std::iter::once(input_path(&job.input)).chain(job.overlays.iter().flatten().cloned());
+    for file in inputs {
+        if !Path::new(&file).is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no such file `{}`", job.name, file),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn run_job(job: &Job, doc: &OSM, rules: &RuleSet, out_dir: &str) -> io::Result<()> {
+    let filter = |feature: &Feature, range: RangeIdx| {
+        let rule = rules.find(feature)?;
+        kind_for(&rule.layer, feature, range)
+    };
+    let (mut geometry, results) =
+        Geometry::from_osm_cropped(doc, &filter, job.crop, TARGET_HEIGHT)?;
+    // HTML pages need some tags for their tooltips and search.
+    let html = job.formats.iter().any(|format| format == "html");
+    let osm_tags = match &job.osm_tags {
+        tags if tags.is_empty() && html => html::TOOLTIP_TAGS.iter().map(|&k| k.into()).collect(),
+        tags => tags.clone(),
+    };
+    if !osm_tags.is_empty() {
+        geometry.keep_tags(doc, &osm_tags);
+    }
+    let mut results = process(results, &geometry);
+    if let Some(terrain) = &job.terrain {
+        results.extend(process_terrain(&geometry, terrain)?);
+    }
+    if let Some(grid) = job.grid {
+        results.extend(process_grid(&geometry, grid));
+    }
+
+    if let Some(options) = job.geojson {
+        let out = if options.split {
+            format!("{}/export/{}", out_dir, job.name)
+        } else {
+            format!("{}/export/{}.geojson", out_dir, job.name)
+        };
+        export::export_to(&geometry, &results, options, &out)?;
+        println!("wrote {}", out);
+    }
+
+    let theme = theme::by_name(&job.theme).unwrap();
+    let overlays = Overlays {
+        files: job.overlays.clone().unwrap_or_else(default_overlays),
+        colour_by: job.colour_by,
+        // Checked before the jobs started.
+        crs: job
+            .overlay_crs
+            .as_deref()
+            .map(|name| Crs::by_name(name).unwrap()),
+    };
+    let sizes: Vec<Option<f64>> = if job.sizes.is_empty() {
+        vec![None]
+    } else {
+        job.sizes.iter().cloned().map(Some).collect()
+    };
+    fs::create_dir_all(format!("{}/svg", out_dir))?;
+    for format in &job.formats {
+        for &size in &sizes {
+            let out = match size {
+                Some(size) if sizes.len() > 1 => {
+                    format!("{}/svg/{}-{}.{}", out_dir, job.name, size, format)
+                }
+                _ => format!("{}/svg/{}.{}", out_dir, job.name, format),
+            };
+            let options = RenderOptions {
+                theme,
+                height: size,
+                relief: job.buildings,
+                sun: job.sun.unwrap_or(DEFAULT_SUN),
+                osm_ids: job.osm_ids || !osm_tags.is_empty(),
+                furniture: job.furniture.clone(),
+            };
+            render(&geometry, &results, &overlays, &options, &out)?;
+            println!("wrote {}", out);
+        }
+    }
+    Ok(())
+}
+
+pub fn run(manifest_file: &str) -> io::Result<()> {
+    let manifest: Manifest = serde_json::from_reader(std::fs::File::open(manifest_file)?)?;
+    let (rules, _) = load_rules(manifest.rules.as_deref())?;
+    match run_jobs(&manifest.jobs, &rules, OUT_DIR) {
+        0 => Ok(()),
+        n => Err(io::Error::other(format!(
+            "{} of {} jobs failed",
+            n,
+            manifest.jobs.len()
+        ))),
+    }
+}
+
+// Runs the jobs which pass `check`, returning how many jobs failed.
+fn run_jobs(all_jobs: &[Job], rules: &RuleSet, out_dir: &str) -> usize {
+    // Mistakes are caught before any of the (slow) rendering starts.
+    let jobs: Vec<&Job> = all_jobs
+        .iter()
+        .filter(|job| match check(job) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("{}", e);
+                false
+            }
+        })
+        .collect();
+    let failures = AtomicUsize::new(all_jobs.len() - jobs.len());
+
+    // Each extract is parsed by whichever job reaches it first, while any
+    // other jobs on the same extract wait for it.
+    let documents: HashMap<String, OnceLock<io::Result<OSM>>> = jobs
+        .iter()
+        .map(|job| (input_path(&job.input), OnceLock::new()))
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism().map_or(4, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..workers.min(jobs.len()) {
+            scope.spawn(|| {
+                while let Some(job) = jobs.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let path = input_path(&job.input);
+                    let result = match documents[&path].get_or_init(|| osm_load::read_osm(&path)) {
+                        Ok(doc) => run_job(job, doc, rules, out_dir),
+                        // Every job on an extract which can't be read fails.
+                        Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                    };
+                    if let Err(e) = result {
+                        eprintln!("{}: {}", job.name, e);
+                        failures.fetch_add(1, Ordering::SeqCst);
+                    }
+                    // Batches don't report timings, so don't keep them.
+                    timing::take();
+                }
+            });
+        }
+    });
+    failures.into_inner()
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("mapfun-batch-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// A one-building extract in `dir`.
+#[cfg(test)]
+fn tiny_extract(dir: &Path) -> String {
+    let path = dir.join("tiny.osm");
+    fs::write(
+        &path,
+        r#"<osm>
+  <bounds minlat="40.0" minlon="-74.0" maxlat="40.1" maxlon="-73.9"/>
+  <node id="1" lat="40.01" lon="-73.99"/>
+  <node id="2" lat="40.02" lon="-73.99"/>
+  <node id="3" lat="40.02" lon="-73.98"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="1"/>
+    <tag k="building" v="yes"/></way>
+</osm>"#,
+    )
+    .unwrap();
+    path.to_str().unwrap().into()
+}
+
+#[test]
+fn jobs_only_need_a_name_and_input() {
+    let dir = test_dir("defaults");
+    let json = format!(
+        r#"{{"jobs": [{{"name": "dc", "input": "{}"}}]}}"#,
+        tiny_extract(&dir)
+    );
+    let manifest: Manifest = serde_json::from_str(&json).unwrap();
+    let job = &manifest.jobs[0];
+    assert_eq!("gray", job.theme);
+    assert_eq!(vec!["svg".to_string()], job.formats);
+    assert!(job.sizes.is_empty() && job.crop.is_none() && job.overlays.is_none());
+    assert!(check(job).is_ok());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn bad_jobs_are_rejected() {
+    let dir = test_dir("checks");
+    let input = tiny_extract(&dir);
+    let job = |extra: &str| -> Job {
+        let json = format!(r#"{{"name": "dc", "input": "{}", {}}}"#, input, extra);
+        serde_json::from_str(&json).unwrap()
+    };
+    assert!(check(&job(r#""theme": "neon""#)).is_err());
+    assert!(check(&job(r#""formats": ["svg", "png"]"#)).is_err());
+    assert!(check(&job(r#""formats": ["svg", "html"]"#)).is_ok());
+    assert!(check(&job(r#""crop": [1, 1, 0, 2]"#)).is_err());
+    assert!(check(&job(r#""overlay_crs": "EPSG:9999""#)).is_err());
+    assert!(check(&job(r#""grid": {"metric": 0}"#)).is_err());
+    assert!(check(&job(r#""grid": {"graticule": -1}"#)).is_err());
+    assert!(check(&job(r#""grid": {"graticule": 0.01}"#)).is_ok());
+    assert!(check(&job(r#""overlays": ["no/such/overlay.geojson"]"#)).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn bad_inputs_only_fail_their_own_jobs() {
+    let dir = test_dir("inputs");
+    let broken = dir.join("broken.osm");
+    fs::write(&broken, "<osm><node").unwrap();
+    // Extracts without bounds are framed on their nodes, if they have any.
+    let empty = dir.join("empty.osm");
+    fs::write(&empty, "<osm></osm>").unwrap();
+    let unbounded = dir.join("unbounded.osm");
+    let tiny = tiny_extract(&dir);
+    let text = fs::read_to_string(&tiny).unwrap();
+    let text: Vec<_> = text
+        .lines()
+        .filter(|line| !line.contains("<bounds"))
+        .collect();
+    fs::write(&unbounded, text.join("\n")).unwrap();
+    let jobs = [
+        (
+            "missing",
+            dir.join("missing.osm").to_str().unwrap().to_string(),
+        ),
+        ("broken", broken.to_str().unwrap().into()),
+        ("empty", empty.to_str().unwrap().into()),
+        ("unbounded", unbounded.to_str().unwrap().into()),
+        ("tiny", tiny),
+    ];
+    let jobs: Vec<Job> = jobs
+        .iter()
+        .map(|(name, input)| {
+            let json = format!(
+                r#"{{"name": "{}", "input": "{}", "overlays": [], "geojson": {{}}}}"#,
+                name, input
+            );
+            serde_json::from_str(&json).unwrap()
+        })
+        .collect();
+    let rules = load_rules(None).unwrap().0;
+    let out_dir = dir.to_str().unwrap();
+
+    assert_eq!(3, run_jobs(&jobs, &rules, out_dir));
+    assert!(dir.join("svg/tiny.svg").is_file());
+    assert!(dir.join("svg/unbounded.svg").is_file());
+    assert!(dir.join("export/tiny.geojson").is_file());
+    assert!(!dir.join("svg/broken.svg").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,191 @@
+// Building heights from OSM tags, and the shapes which give buildings depth on
+// the map: shadows cast away from the sun, or walls and raised roofs for an
+// oblique, pseudo-3D view.
+
+use linemath::{self, Point};
+use osm_load::{find_tag, Tag};
+use roads::parse_width;
+use serde::{Deserialize, Serialize};
+
+const LEVEL_HEIGHT: f64 = 3.0;
+// Assumed for buildings with neither a height nor a number of levels.
+const DEFAULT_LEVELS: f64 = 2.0;
+// Shadows are cast by a sun this high, so they're as long as the building is tall.
+const SUN_ALTITUDE: f64 = 45.0;
+// How far up the map roofs are raised in the oblique view, per metre of height.
+const EXTRUSION: f64 = 0.7;
+
+// The tags which say how tall a building is.  Heights are in metres.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Building {
+    pub height: Option<f64>,
+    pub min_height: Option<f64>,
+    pub levels: Option<f64>,
+    pub roof_shape: Option<String>,
+    pub roof_height: Option<f64>,
+}
+
+pub fn building(tags: &[Tag]) -> Building {
+    let length = |key| find_tag(tags, key).and_then(parse_width);
+    let levels = |key| find_tag(tags, key).and_then(|value| value.trim().parse::<f64>().ok());
+    Building {
+        height: length("height"),
+        min_height: length("min_height")
+            .or_else(|| Some(levels("building:min_level")? * LEVEL_HEIGHT)),
+        levels: levels("building:levels"),
+        roof_shape: find_tag(tags, "roof:shape").map(String::from),
+        roof_height: length("roof:height").or_else(|| Some(levels("roof:levels")? * LEVEL_HEIGHT)),
+    }
+}
+
+impl Building {
+    // To the top of the roof.
+    pub fn height(&self) -> f64 {
+        self.height.unwrap_or_else(|| {
+            let levels = self.levels.unwrap_or(DEFAULT_LEVELS);
+            levels * LEVEL_HEIGHT + self.roof_height.unwrap_or(0.0)
+        })
+    }
+
+    // To the top of the walls, below any pitched roof.
+    pub fn wall_height(&self) -> f64 {
+        let roof = match self.roof_shape.as_deref() {
+            None | Some("flat") => 0.0,
+            Some(_) => self.roof_height.unwrap_or(LEVEL_HEIGHT),
+        };
+        (self.height() - roof).max(self.min_height.unwrap_or(0.0))
+    }
+}
+
+// How buildings are drawn.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relief {
+    #[default]
+    Flat,
+    // With shadows cast away from the sun.
+    Shadow,
+    // Walls and raised roofs, as if seen from the south.
+    Extrude,
+}
+
+impl Relief {
+    pub fn by_name(name: &str) -> Option<Relief> {
+        match name {
+            "flat" => Some(Relief::Flat),
+            "shadow" => Some(Relief::Shadow),
+            "extrude" => Some(Relief::Extrude),
+            _ => None,
+        }
+    }
+}
+
+fn offset(points: &[Point], dx: f64, dy: f64) -> Vec<Point> {
+    points
+        .iter()
+        .map(|p| Point::new(p.x() + dx, p.y() + dy))
+        .collect()
+}
+
+// The face swept out by moving the edge from `a` to `b` by (dx, dy).
+fn face(a: Point, b: Point, dx: f64, dy: f64) -> Vec<Point> {
+    let moved = offset(&[a, b], dx, dy);
+    vec![a, b, moved[1], moved[0], a]
+}
+
+fn signed_area(ring: &[Point]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0].x() * w[1].y() - w[1].x() * w[0].y())
+        .sum::<f64>()
+        / 2.0
+}
+
+// The shadow of a building with a closed `footprint`, `height` metres tall, in
+// sunlight from `azimuth` degrees clockwise from north.
+pub fn shadow(
+    footprint: &[Point],
+    height: f64,
+    azimuth: f64,
+    units_per_metre: f64,
+) -> Vec<Vec<Vec<Point>>> {
+    let length = height / SUN_ALTITUDE.to_radians().tan() * units_per_metre;
+    let (dx, dy) = (
+        -azimuth.to_radians().sin() * length,
+        -azimuth.to_radians().cos() * length,
+    );
+    let mut rings = vec![footprint.to_vec(), offset(footprint, dx, dy)];
+    rings.extend(footprint.windows(2).map(|w| face(w[0], w[1], dx, dy)));
+    // All the same way round, so that overlaps add up rather than cancel.
+    for ring in &mut rings {
+        if signed_area(ring) < 0.0 {
+            ring.reverse();
+        }
+    }
+    linemath::union(rings)
+}
+
+// The walls of a building with a closed `footprint`, raised `height` metres in
+// the oblique view, farthest first, followed by its roof.
+pub fn extrude(footprint: &[Point], height: f64, units_per_metre: f64) -> Vec<Vec<Point>> {
+    let rise = height * EXTRUSION * units_per_metre;
+    // By the middle of their base, furthest north first.
+    let mut edges: Vec<&[Point]> = footprint.windows(2).collect();
+    let middle = |edge: &[Point]| (edge[0].y() + edge[1].y()) / 2.0;
+    edges.sort_by(|a, b| middle(b).total_cmp(&middle(a)));
+    let mut walls: Vec<Vec<Point>> = edges
+        .into_iter()
+        .map(|edge| face(edge[0], edge[1], 0.0, rise))
+        .collect();
+    walls.push(offset(footprint, 0.0, rise));
+    walls
+}
+
+// Sorting by this, largest first, draws things back to front in the oblique
+// view: the further north, the further away.
+pub fn depth(points: &[Point]) -> f64 {
+    points.iter().map(|p| p.y()).fold(f64::INFINITY, f64::min)
+}
+
+#[test]
+fn heights_from_tags() {
+    use roads::tags;
+
+    let tagged = building(&tags(&[("height", "30 m"), ("roof:shape", "gabled")]));
+    assert_eq!(30.0, tagged.height());
+    assert_eq!(27.0, tagged.wall_height());
+
+    let levels = building(&tags(&[("building:levels", "4"), ("roof:levels", "1")]));
+    assert_eq!(15.0, levels.height());
+    assert_eq!(15.0, levels.wall_height());
+
+    let untagged = building(&tags(&[("building", "yes")]));
+    assert_eq!(DEFAULT_LEVELS * LEVEL_HEIGHT, untagged.height());
+    assert_eq!(None, untagged.min_height);
+}
+
+#[test]
+fn shadows_fall_away_from_the_sun() {
+    let square: Vec<Point> = [
+        (0.0, 0.0),
+        (10.0, 0.0),
+        (10.0, 10.0),
+        (0.0, 10.0),
+        (0.0, 0.0),
+    ]
+    .iter()
+    .map(|&(x, y)| Point::new(x, y))
+    .collect();
+    // With the sun in the south, a 5 m building casts a 5 m shadow north.
+    let shadow = shadow(&square, 5.0, 180.0, 1.0);
+    assert_eq!(1, shadow.len());
+    let area = signed_area(&shadow[0][0]).abs();
+    assert!((area - 150.0).abs() < 1e-6);
+    assert!((depth(&shadow[0][0]) - 0.0).abs() < 1e-6);
+
+    let faces = extrude(&square, 10.0, 1.0);
+    assert_eq!(5, faces.len());
+    // The north wall is drawn first and the south wall last, before the roof.
+    assert_eq!(10.0, depth(&faces[0]));
+    assert_eq!(0.0, depth(&faces[3]));
+    assert_eq!(7.0, depth(&faces[4]));
+}
@@ -0,0 +1,157 @@
+// On-disk cache of loaded geometry and the features classified from it, so that
+// re-rendering an extract can skip parsing and projection.
+
+use bincode;
+#[cfg(test)]
+use linemath::Point;
+use osm_load::{self, Geometry};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+// Bump whenever the cached types or the way features are classified change.
+const VERSION: u64 = 5;
+const CACHE_DIR: &str = "./data/cache";
+
+// FNV-1a, which unlike std's hasher is stable between builds.
+fn hash(mut state: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        state ^= u64::from(byte);
+        state = state.wrapping_mul(0x0100_0000_01b3);
+    }
+    state
+}
+
+// Identifies everything that goes into `Geometry::from_osm`, and the tags
+// `Geometry::keep_tags` kept.
+pub fn key(input: &[u8], rules: &str, tags: &[String], target_h: f64) -> u64 {
+    let mut state = hash(0xcbf2_9ce4_8422_2325, &VERSION.to_le_bytes());
+    let tags = tags.join("\n");
+    for part in &[
+        input,
+        rules.as_bytes(),
+        tags.as_bytes(),
+        osm_load::PROJECTION.as_bytes(),
+    ] {
+        state = hash(state, &(part.len() as u64).to_le_bytes());
+        state = hash(state, part);
+    }
+    hash(state, &target_h.to_bits().to_le_bytes())
+}
+
+fn file_name(name: &str, key: u64) -> String {
+    format!("{}-{:016x}.bin", name, key)
+}
+
+pub fn load<T: DeserializeOwned>(name: &str, key: u64) -> Option<(Geometry, Vec<T>)> {
+    load_from(Path::new(CACHE_DIR), name, key)
+}
+
+pub fn store<T: Serialize>(
+    name: &str,
+    key: u64,
+    geometry: &Geometry,
+    kinds: &[T],
+) -> io::Result<()> {
+    store_in(Path::new(CACHE_DIR), name, key, geometry, kinds)
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn load_from<T: DeserializeOwned>(dir: &Path, name: &str, key: u64) -> Option<(Geometry, Vec<T>)> {
+    let file = File::open(dir.join(file_name(name, key))).ok()?;
+    bincode::deserialize_from(BufReader::new(file)).ok()
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn store_in<T: Serialize>(
+    dir: &Path,
+    name: &str,
+    key: u64,
+    geometry: &Geometry,
+    kinds: &[T],
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    // Caches for older versions of the same input are no use any more.
+    let prefix = format!("{}-", name);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?.file_name();
+        let entry = entry.to_string_lossy();
+        let stale = entry.starts_with(&prefix)
+            && entry.ends_with(".bin")
+            && entry[prefix.len()..entry.len() - 4].len() == 16;
+        if stale {
+            fs::remove_file(dir.join(&*entry))?;
+        }
+    }
+
+    // Write to a temporary file first so that an interrupted run can't leave a
+    // truncated cache behind.
+    let path = dir.join(file_name(name, key));
+    let temporary = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&temporary)?);
+    bincode::serialize_into(&mut out, &(geometry, kinds)).map_err(io::Error::other)?;
+    drop(out);
+    fs::rename(temporary, path)
+}
+
+#[cfg(test)]
+fn test_geometry() -> Geometry {
+    Geometry {
+        bounds: osm_load::Bounds {
+            width: 2.0,
+            height: 1.0,
+            min_lon: 0.0,
+            min_lat: 0.0,
+            max_lon: 4.0,
+            max_lat: 2.0,
+            scale_x: 0.5,
+            scale_y: 0.5,
+        },
+        coords: vec![
+            Point::new(0.0, 0.0),
+            Point::new(4.0, 2.0),
+            Point::new(1.5, -0.25),
+        ],
+        node_ids: vec![1, 2, 3],
+        polys: vec![0..2, 2..3],
+        inner: vec![false, true],
+        sources: vec![],
+    }
+}
+
+#[test]
+fn key_depends_on_every_input() {
+    let base = key(b"<osm/>", "road : highway", &[], 1000.0);
+    assert_eq!(base, key(b"<osm/>", "road : highway", &[], 1000.0));
+    assert_ne!(base, key(b"<osm />", "road : highway", &[], 1000.0));
+    assert_ne!(base, key(b"<osm/>", "road : highway=*", &[], 1000.0));
+    assert_ne!(
+        base,
+        key(b"<osm/>", "road : highway", &["name".into()], 1000.0)
+    );
+    assert_ne!(base, key(b"<osm/>", "road : highway", &[], 500.0));
+    // Moving bytes between the input and the rules must change the key too.
+    assert_ne!(key(b"ab", "c", &[], 1.0), key(b"a", "bc", &[], 1.0));
+}
+
+#[test]
+fn round_trip_replaces_stale_entries() {
+    let dir = std::env::temp_dir().join(format!("mapfun-cache-test-{}", std::process::id()));
+    let geometry = test_geometry();
+
+    store_in(&dir, "city", 1, &geometry, &[10u32, 20]).unwrap();
+    store_in(&dir, "city", 2, &geometry, &[30u32]).unwrap();
+    assert!(load_from::<u32>(&dir, "city", 1).is_none());
+
+    let (loaded, kinds) = load_from::<u32>(&dir, "city", 2).unwrap();
+    assert_eq!(vec![30], kinds);
+    assert_eq!(geometry.coords, loaded.coords);
+    assert_eq!(geometry.polys, loaded.polys);
+    assert_eq!(geometry.inner, loaded.inner);
+    assert_eq!(geometry.bounds.scale_x, loaded.bounds.scale_x);
+
+    fs::remove_dir_all(dir).unwrap();
+}
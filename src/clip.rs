@@ -0,0 +1,202 @@
+use aabb_quadtree::{ItemId, QuadTree};
+use euclid::{TypedRect, UnknownUnit};
+use i_overlay::core::fill_rule::FillRule;
+use i_overlay::core::overlay_rule::OverlayRule;
+use i_overlay::float::clip::FloatClip;
+use i_overlay::float::single::SingleFloatOverlay;
+use i_overlay::string::clip::ClipRule;
+use linemath::{self, Point};
+
+type Rect = TypedRect<f32, UnknownUnit>;
+type Polygon = Vec<Vec<Point>>;
+
+// Clips polygons and polylines against a set of (possibly overlapping) polygons,
+// using a quadtree so that each clip only considers the polygons nearby.
+pub struct Clipper {
+    // The quadtree stores f32 boxes, so coordinates are made relative to `origin`
+    // to keep them precise.
+    origin: (f64, f64),
+    polygons: Vec<Vec<Vec<[f64; 2]>>>,
+    tree: QuadTree<usize, UnknownUnit, [(ItemId, Rect); 4]>,
+}
+
+fn bounding_box(origin: (f64, f64), points: &[Point]) -> Option<Rect> {
+    let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+    let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for point in points {
+        let (x, y) = (point.x(), point.y());
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    if min_x > max_x {
+        return None;
+    }
+    // f32 rounding can shrink a box by a hair, so pad it out a little.
+    let pad = 1.0;
+    Some(euclid::rect(
+        (min_x - origin.0 - pad) as f32,
+        (min_y - origin.1 - pad) as f32,
+        (max_x - min_x + 2.0 * pad) as f32,
+        (max_y - min_y + 2.0 * pad) as f32,
+    ))
+}
+
+fn to_contour(ring: &[Point]) -> Vec<[f64; 2]> {
+    ring.iter().map(|point| [point.x(), point.y()]).collect()
+}
+
+fn to_ring(contour: Vec<[f64; 2]>, close: bool) -> Vec<Point> {
+    let mut ring: Vec<Point> = contour.into_iter().map(|[x, y]| Point::new(x, y)).collect();
+    if close && !ring.is_empty() {
+        let first = ring[0];
+        ring.push(first);
+    }
+    ring
+}
+
+impl Clipper {
+    // `clip_rings` may overlap each other; they are unioned before use.
+    #[cfg_attr(feature = "profiling", flame)]
+    pub fn new(clip_rings: Vec<Vec<Point>>) -> Clipper {
+        let polygons = linemath::union(clip_rings);
+
+        let all_points: Vec<Point> = polygons
+            .iter()
+            .flat_map(|polygon| polygon[0].iter().cloned())
+            .collect();
+        let origin = all_points
+            .iter()
+            .fold((f64::INFINITY, f64::INFINITY), |(ax, ay), point| {
+                (ax.min(point.x()), ay.min(point.y()))
+            });
+        let extent =
+            bounding_box(origin, &all_points).unwrap_or_else(|| euclid::rect(0.0, 0.0, 1.0, 1.0));
+        let extent = extent.inflate(1.0, 1.0);
+
+        let mut tree = QuadTree::default(extent, polygons.len());
+        let mut contours = Vec::with_capacity(polygons.len());
+        for (i, polygon) in polygons.into_iter().enumerate() {
+            if let Some(aabb) = bounding_box(origin, &polygon[0]) {
+                tree.insert_with_box(i, aabb);
+            }
+            contours.push(polygon.iter().map(|ring| to_contour(ring)).collect());
+        }
+
+        Clipper {
+            origin,
+            polygons: contours,
+            tree,
+        }
+    }
+
+    fn nearby(&self, points: &[Point]) -> Vec<Vec<[f64; 2]>> {
+        let aabb = match bounding_box(self.origin, points) {
+            Some(aabb) => aabb,
+            None => return vec![],
+        };
+        let mut found = vec![];
+        let _ = self.tree.custom_query::<(), _>(aabb, &mut |id, _| {
+            found.push(*self.tree.get(id).unwrap());
+            Ok(())
+        });
+        found.sort();
+        found.dedup();
+        found
+            .into_iter()
+            .flat_map(|i| self.polygons[i].iter().cloned())
+            .collect()
+    }
+
+    // The parts of `polygon` (an outer ring followed by its holes) which lie
+    // inside the clip polygons.
+    pub fn clip_polygon(&self, polygon: &[Vec<Point>]) -> Vec<Polygon> {
+        if polygon.is_empty() {
+            return vec![];
+        }
+        let clip = self.nearby(&polygon[0]);
+        if clip.is_empty() {
+            return vec![];
+        }
+        let subject: Vec<Vec<[f64; 2]>> = polygon.iter().map(|ring| to_contour(ring)).collect();
+        subject
+            .overlay(&clip, OverlayRule::Intersect, FillRule::NonZero)
+            .into_iter()
+            .map(|shape| shape.into_iter().map(|c| to_ring(c, true)).collect())
+            .collect()
+    }
+
+    // The pieces of `polyline` which lie inside the clip polygons.
+    pub fn clip_polyline(&self, polyline: &[Point]) -> Vec<Vec<Point>> {
+        let clip = self.nearby(polyline);
+        if clip.is_empty() || polyline.len() < 2 {
+            return vec![];
+        }
+        let rule = ClipRule {
+            invert: false,
+            boundary_included: false,
+        };
+        to_contour(polyline)
+            .clip_by(&clip, FillRule::NonZero, rule)
+            .into_iter()
+            .map(|path| to_ring(path, false))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn area(polygon: &[Vec<Point>]) -> f64 {
+    let ring_area = |ring: &Vec<Point>| {
+        ring.windows(2)
+            .map(|w| w[0].x() * w[1].y() - w[1].x() * w[0].y())
+            .sum::<f64>()
+            / 2.0
+    };
+    polygon.iter().map(ring_area).sum::<f64>().abs()
+}
+
+#[cfg(test)]
+fn square(x: f64, y: f64, size: f64) -> Vec<Point> {
+    vec![
+        Point::new(x, y),
+        Point::new(x + size, y),
+        Point::new(x + size, y + size),
+        Point::new(x, y + size),
+        Point::new(x, y),
+    ]
+}
+
+#[test]
+fn polygon_partially_inside() {
+    let clipper = Clipper::new(vec![square(0.0, 0.0, 10.0)]);
+    let clipped = clipper.clip_polygon(&[square(5.0, 5.0, 10.0)]);
+    assert_eq!(1, clipped.len());
+    assert!((area(&clipped[0]) - 25.0).abs() < 1e-6);
+}
+
+#[test]
+fn polygon_far_away_is_dropped() {
+    let clipper = Clipper::new(vec![square(0.0, 0.0, 10.0), square(100.0, 100.0, 10.0)]);
+    assert!(clipper.clip_polygon(&[square(50.0, 50.0, 10.0)]).is_empty());
+}
+
+#[test]
+fn overlapping_clip_polygons_are_not_cancelled() {
+    let clipper = Clipper::new(vec![square(0.0, 0.0, 10.0), square(5.0, 0.0, 10.0)]);
+    let clipped = clipper.clip_polygon(&[square(0.0, 0.0, 20.0)]);
+    let total: f64 = clipped.iter().map(|p| area(p)).sum();
+    assert!((total - 150.0).abs() < 1e-6);
+}
+
+#[test]
+fn polyline_crossing_two_polygons() {
+    let clipper = Clipper::new(vec![square(0.0, 0.0, 10.0), square(20.0, 0.0, 10.0)]);
+    let clipped = clipper.clip_polyline(&[Point::new(-5.0, 5.0), Point::new(35.0, 5.0)]);
+    assert_eq!(2, clipped.len());
+    let length: f64 = clipped
+        .iter()
+        .map(|line| (line[line.len() - 1].x() - line[0].x()).abs())
+        .sum();
+    assert!((length - 20.0).abs() < 1e-6);
+}
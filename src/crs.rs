@@ -0,0 +1,518 @@
+// Coordinate systems overlays may come in.  Open data is often published in
+// a projected system like State Plane, so those coordinates are turned back
+// into longitude and latitude before being projected onto the map.
+//
+// Datum shifts (NAD83 to WGS84 is a metre or two) are ignored.
+
+use geojson::Geojson;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+// Semi-major axis in metres and inverse flattening.
+const GRS80: (f64, f64) = (6_378_137.0, 298.257_222_101);
+const WGS84: (f64, f64) = (6_378_137.0, 298.257_223_563);
+
+// Metres per US survey foot.
+const US_FOOT: f64 = 1200.0 / 3937.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    // With its two standard parallels in degrees, which are both the latitude
+    // of origin for the one-parallel variant.
+    LambertConformalConic { lat_1: f64, lat_2: f64 },
+    TransverseMercator,
+    // Spherical Mercator, as used by web maps.
+    WebMercator,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    method: Method,
+    // Semi-major axis in metres and inverse flattening (0 for a sphere).
+    ellipsoid: (f64, f64),
+    // Latitude and longitude of the origin, in degrees.
+    lat_0: f64,
+    lon_0: f64,
+    scale_factor: f64,
+    // Added to eastings and northings, in the projection's units.
+    false_easting: f64,
+    false_northing: f64,
+    // Metres per unit.
+    unit: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crs {
+    LonLat,
+    Projected(Projection),
+}
+
+// A State Plane zone on the GRS 1980 ellipsoid, with standard parallels and
+// origin in degrees and its false easting in metres.
+fn lambert(parallels: (f64, f64), origin: (f64, f64), false_easting: f64, unit: f64) -> Crs {
+    Crs::Projected(Projection {
+        method: Method::LambertConformalConic {
+            lat_1: parallels.0,
+            lat_2: parallels.1,
+        },
+        ellipsoid: GRS80,
+        lat_0: origin.0,
+        lon_0: origin.1,
+        scale_factor: 1.0,
+        false_easting: false_easting / unit,
+        false_northing: 0.0,
+        unit,
+    })
+}
+
+fn utm(zone: u32, south: bool, ellipsoid: (f64, f64)) -> Crs {
+    Crs::Projected(Projection {
+        method: Method::TransverseMercator,
+        ellipsoid,
+        lat_0: 0.0,
+        lon_0: zone as f64 * 6.0 - 183.0,
+        scale_factor: 0.9996,
+        false_easting: 500_000.0,
+        false_northing: if south { 10_000_000.0 } else { 0.0 },
+        unit: 1.0,
+    })
+}
+
+impl Crs {
+    // A system by name, as written in GeoJSON's `crs` member or given on the
+    // command line: `EPSG:2263`, `urn:ogc:def:crs:EPSG::2263` and so on.
+    pub fn by_name(name: &str) -> Result<Crs, String> {
+        let unknown = || format!("unsupported coordinate system `{}`", name);
+        if name.ends_with("CRS84") {
+            return Ok(Crs::LonLat);
+        }
+        if !name.to_uppercase().contains("EPSG") {
+            return Err(unknown());
+        }
+        let code: u32 = name
+            .rsplit(':')
+            .next()
+            .unwrap()
+            .parse()
+            .map_err(|_| unknown())?;
+        Ok(match code {
+            4326 | 4269 => Crs::LonLat,
+            // NAD83 / New York Long Island and NAD83 / Maryland (which also
+            // covers Washington, DC), in US feet and in metres.
+            2263 | 32118 => lambert(
+                (41.0 + 2.0 / 60.0, 40.0 + 40.0 / 60.0),
+                (40.0 + 10.0 / 60.0, -74.0),
+                300_000.0,
+                if code == 2263 { US_FOOT } else { 1.0 },
+            ),
+            2248 | 26985 => lambert(
+                (39.45, 38.3),
+                (37.0 + 40.0 / 60.0, -77.0),
+                400_000.0,
+                if code == 2248 { US_FOOT } else { 1.0 },
+            ),
+            3857 => Crs::Projected(Projection {
+                method: Method::WebMercator,
+                ellipsoid: (WGS84.0, 0.0),
+                lat_0: 0.0,
+                lon_0: 0.0,
+                scale_factor: 1.0,
+                false_easting: 0.0,
+                false_northing: 0.0,
+                unit: 1.0,
+            }),
+            32601..=32660 => utm(code - 32600, false, WGS84),
+            32701..=32760 => utm(code - 32700, true, WGS84),
+            26901..=26923 => utm(code - 26900, false, GRS80),
+            _ => return Err(unknown()),
+        })
+    }
+
+    // The system described by the well-known text of a Shapefile's `.prj`.
+    pub fn from_wkt(wkt: &str) -> Result<Crs, String> {
+        let wkt = wkt.trim_start();
+        if wkt.starts_with("GEOGCS[") || wkt.starts_with("GEOGCRS[") {
+            return Ok(Crs::LonLat);
+        }
+        if !wkt.starts_with("PROJCS[") {
+            return Err("unrecognised coordinate system".into());
+        }
+        let projection = wkt_items(wkt, "PROJECTION")
+            .pop()
+            .map(|(name, _)| name.to_lowercase())
+            .unwrap_or_default();
+        let parameters = wkt_items(wkt, "PARAMETER");
+        let parameter = |names: &[&str]| {
+            parameters
+                .iter()
+                .find(|(name, _)| names.contains(&name.to_lowercase().as_str()))
+                .and_then(|(_, values)| values.first().cloned())
+        };
+        let lat_0 = parameter(&["latitude_of_origin"]).unwrap_or(0.0);
+        let method = match projection.as_str() {
+            "lambert_conformal_conic"
+            | "lambert_conformal_conic_1sp"
+            | "lambert_conformal_conic_2sp" => {
+                let lat_1 = parameter(&["standard_parallel_1"]).unwrap_or(lat_0);
+                let lat_2 = parameter(&["standard_parallel_2"]).unwrap_or(lat_1);
+                Method::LambertConformalConic { lat_1, lat_2 }
+            }
+            "transverse_mercator" => Method::TransverseMercator,
+            "mercator_auxiliary_sphere" | "popular_visualisation_pseudo_mercator" => {
+                Method::WebMercator
+            }
+            _ => return Err(format!("unsupported projection `{}`", projection)),
+        };
+        let ellipsoid = match wkt_items(wkt, "SPHEROID").pop() {
+            Some((_, values)) if values.len() >= 2 => (values[0], values[1]),
+            _ => GRS80,
+        };
+        // The last unit is the projection's; earlier ones belong to its datum.
+        let unit = match wkt_items(wkt, "UNIT").pop() {
+            Some((_, values)) if !values.is_empty() => values[0],
+            _ => 1.0,
+        };
+        Ok(Crs::Projected(Projection {
+            method,
+            ellipsoid: if method == Method::WebMercator {
+                (ellipsoid.0, 0.0)
+            } else {
+                ellipsoid
+            },
+            lat_0,
+            lon_0: parameter(&["central_meridian", "longitude_of_origin"]).unwrap_or(0.0),
+            scale_factor: parameter(&["scale_factor"]).unwrap_or(1.0),
+            false_easting: parameter(&["false_easting"]).unwrap_or(0.0),
+            false_northing: parameter(&["false_northing"]).unwrap_or(0.0),
+            unit,
+        }))
+    }
+
+    // Longitude and latitude of a point in this system.
+    pub fn to_lon_lat(self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match self {
+            Crs::LonLat => [x, y],
+            Crs::Projected(projection) => projection.inverse(x, y),
+        }
+    }
+
+    // A point in this system from its longitude and latitude; the inverse of
+    // `to_lon_lat`.
+    pub fn project_lon_lat(self, [lon, lat]: [f64; 2]) -> [f64; 2] {
+        match self {
+            Crs::LonLat => [lon, lat],
+            Crs::Projected(projection) => projection.forward(lon, lat),
+        }
+    }
+
+    // The WGS 84 UTM zone a point falls in, ignoring the exceptions around
+    // Norway and Svalbard.
+    pub fn utm_zone([lon, lat]: [f64; 2]) -> Crs {
+        let zone = (((lon + 180.0) / 6.0).floor() as u32).min(59) + 1;
+        utm(zone, lat < 0.0, WGS84)
+    }
+
+    // Rewrites every coordinate of `geojson` as longitude and latitude.
+    pub fn unproject_geojson(self, geojson: &mut Geojson) {
+        if self == Crs::LonLat {
+            return;
+        }
+        for feature in &mut geojson.features {
+            feature
+                .geometry
+                .for_each_coordinate(|coordinate| *coordinate = self.to_lon_lat(*coordinate));
+        }
+        geojson.crs = None;
+    }
+}
+
+// The name and numbers of each `KEYWORD["name",1,2,...]` in `wkt`.
+fn wkt_items(wkt: &str, keyword: &str) -> Vec<(String, Vec<f64>)> {
+    let opening = format!("{}[\"", keyword);
+    let mut items = vec![];
+    let mut rest = wkt;
+    while let Some(start) = rest.find(&opening) {
+        rest = &rest[start + opening.len()..];
+        let name_len = match rest.find('"') {
+            Some(len) => len,
+            None => break,
+        };
+        let name = rest[..name_len].to_string();
+        let values = rest[name_len + 1..]
+            .split([',', ']'])
+            .skip(1)
+            .map_while(|value| value.trim().parse::<f64>().ok())
+            .collect();
+        items.push((name, values));
+    }
+    items
+}
+
+impl Projection {
+    fn eccentricity(&self) -> f64 {
+        let (_, inverse_flattening) = self.ellipsoid;
+        if inverse_flattening == 0.0 {
+            return 0.0;
+        }
+        let f = 1.0 / inverse_flattening;
+        (2.0 * f - f * f).sqrt()
+    }
+
+    fn forward(&self, lon: f64, lat: f64) -> [f64; 2] {
+        let (lon, lat) = ((lon - self.lon_0).to_radians(), lat.to_radians());
+        let (x, y) = match self.method {
+            Method::LambertConformalConic { lat_1, lat_2 } => {
+                self.forward_lambert(lon, lat, lat_1, lat_2)
+            }
+            Method::TransverseMercator => self.forward_transverse_mercator(lon, lat),
+            Method::WebMercator => {
+                let a = self.ellipsoid.0;
+                (a * lon, a * (FRAC_PI_4 + lat / 2.0).tan().ln())
+            }
+        };
+        [
+            x / self.unit + self.false_easting,
+            y / self.unit + self.false_northing,
+        ]
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> [f64; 2] {
+        let x = (x - self.false_easting) * self.unit;
+        let y = (y - self.false_northing) * self.unit;
+        let (lon, lat) = match self.method {
+            Method::LambertConformalConic { lat_1, lat_2 } => {
+                self.inverse_lambert(x, y, lat_1, lat_2)
+            }
+            Method::TransverseMercator => self.inverse_transverse_mercator(x, y),
+            Method::WebMercator => {
+                let a = self.ellipsoid.0;
+                (x / a, (y / a).sinh().atan())
+            }
+        };
+        [self.lon_0 + lon.to_degrees(), lat.to_degrees()]
+    }
+
+    fn conformal(&self, lat: f64) -> f64 {
+        let e = self.eccentricity();
+        ((1.0 - e * lat.sin()) / (1.0 + e * lat.sin())).powf(e / 2.0)
+    }
+
+    // The cone's constant n, the radius scale (a F k0) and the radius at the
+    // latitude of origin, from standard parallels in degrees.
+    fn lambert_cone(&self, lat_1: f64, lat_2: f64) -> (f64, f64, f64) {
+        let a = self.ellipsoid.0;
+        let e = self.eccentricity();
+        let m = |lat: f64| lat.cos() / (1.0 - (e * lat.sin()).powi(2)).sqrt();
+        let t = |lat: f64| (FRAC_PI_4 - lat / 2.0).tan() / self.conformal(lat);
+
+        let (lat_0, lat_1, lat_2) = (
+            self.lat_0.to_radians(),
+            lat_1.to_radians(),
+            lat_2.to_radians(),
+        );
+        let n = if (lat_1 - lat_2).abs() > 1e-12 {
+            (m(lat_1).ln() - m(lat_2).ln()) / (t(lat_1).ln() - t(lat_2).ln())
+        } else {
+            lat_1.sin()
+        };
+        let big_f = m(lat_1) / (n * t(lat_1).powf(n));
+        let scale = a * big_f * self.scale_factor;
+        (n, scale, scale * t(lat_0).powf(n))
+    }
+
+    // Takes the longitude relative to the central meridian, and the latitude,
+    // in radians.  Formulas from EPSG Guidance Note 7-2.
+    fn forward_lambert(&self, lon: f64, lat: f64, lat_1: f64, lat_2: f64) -> (f64, f64) {
+        let (n, scale, r_0) = self.lambert_cone(lat_1, lat_2);
+        let t = (FRAC_PI_4 - lat / 2.0).tan() / self.conformal(lat);
+        let r = scale * t.powf(n);
+        let theta = n * lon;
+        (r * theta.sin(), r_0 - r * theta.cos())
+    }
+
+    // Returns the longitude relative to the central meridian, and the
+    // latitude, in radians.  Formulas from EPSG Guidance Note 7-2.
+    fn inverse_lambert(&self, x: f64, y: f64, lat_1: f64, lat_2: f64) -> (f64, f64) {
+        let (n, scale, r_0) = self.lambert_cone(lat_1, lat_2);
+        let conformal = |lat: f64| self.conformal(lat);
+
+        let dy = r_0 - y;
+        let r = n.signum() * x.hypot(dy);
+        let t_point = (r / scale).powf(1.0 / n);
+        let theta = if n > 0.0 {
+            x.atan2(dy)
+        } else {
+            (-x).atan2(-dy)
+        };
+
+        let mut lat = FRAC_PI_2 - 2.0 * t_point.atan();
+        for _ in 0..15 {
+            let next = FRAC_PI_2 - 2.0 * (t_point * conformal(lat)).atan();
+            let done = (next - lat).abs() < 1e-12;
+            lat = next;
+            if done {
+                break;
+            }
+        }
+        (theta / n, lat)
+    }
+
+    // The distance along the meridian from the equator to `lat` (in radians).
+    fn meridian_arc(&self, lat: f64) -> f64 {
+        let a = self.ellipsoid.0;
+        let e2 = self.eccentricity().powi(2);
+        let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+        a * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * lat
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * lat).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * lat).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * lat).sin())
+    }
+
+    // As `forward_lambert`, with the USGS series formulas (EPSG Guidance Note
+    // 7-2), which are good to a millimetre within a few degrees of the
+    // central meridian.
+    fn forward_transverse_mercator(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let a = self.ellipsoid.0;
+        let e2 = self.eccentricity().powi(2);
+        let ep2 = e2 / (1.0 - e2);
+        let k_0 = self.scale_factor;
+
+        let t = lat.tan().powi(2);
+        let c = ep2 * lat.cos().powi(2);
+        let big_a = lon * lat.cos();
+        let nu = a / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+        let m = self.meridian_arc(lat) - self.meridian_arc(self.lat_0.to_radians());
+
+        let x = k_0
+            * nu
+            * (big_a
+                + (1.0 - t + c) * big_a.powi(3) / 6.0
+                + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * big_a.powi(5) / 120.0);
+        let y = k_0
+            * (m + nu
+                * lat.tan()
+                * (big_a.powi(2) / 2.0
+                    + (5.0 - t + 9.0 * c + 4.0 * c * c) * big_a.powi(4) / 24.0
+                    + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * big_a.powi(6) / 720.0));
+        (x, y)
+    }
+
+    // As `inverse_lambert`, with the same series as
+    // `forward_transverse_mercator`.
+    fn inverse_transverse_mercator(&self, x: f64, y: f64) -> (f64, f64) {
+        let a = self.ellipsoid.0;
+        let e2 = self.eccentricity().powi(2);
+        let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+        let ep2 = e2 / (1.0 - e2);
+        let k_0 = self.scale_factor;
+
+        let m_1 = self.meridian_arc(self.lat_0.to_radians()) + y / k_0;
+        let mu = m_1 / (a * (1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0));
+        let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+        let lat_1 = mu
+            + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+            + (21.0 * e1.powi(2) / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+            + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+            + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+        let sin2 = lat_1.sin().powi(2);
+        let nu = a / (1.0 - e2 * sin2).sqrt();
+        let rho = a * (1.0 - e2) / (1.0 - e2 * sin2).powf(1.5);
+        let t = lat_1.tan().powi(2);
+        let c = ep2 * lat_1.cos().powi(2);
+        let d = x / (nu * k_0);
+
+        let lat = lat_1
+            - (nu * lat_1.tan() / rho)
+                * (d.powi(2) / 2.0
+                    - (5.0 + 3.0 * t + 10.0 * c - 4.0 * c * c - 9.0 * ep2) * d.powi(4) / 24.0
+                    + (61.0 + 90.0 * t + 298.0 * c + 45.0 * t * t - 252.0 * ep2 - 3.0 * c * c)
+                        * d.powi(6)
+                        / 720.0);
+        let lon = (d - (1.0 + 2.0 * t + c) * d.powi(3) / 6.0
+            + (5.0 - 2.0 * c + 28.0 * t - 3.0 * c * c + 8.0 * ep2 + 24.0 * t * t) * d.powi(5)
+                / 120.0)
+            / lat_1.cos();
+        (lon, lat)
+    }
+}
+
+#[cfg(test)]
+fn assert_near(expected: [f64; 2], actual: [f64; 2]) {
+    // About 1 cm.
+    let close = (expected[0] - actual[0]).abs() < 1e-7 && (expected[1] - actual[1]).abs() < 1e-7;
+    assert!(close, "expected {:?}, got {:?}", expected, actual);
+}
+
+// Within a centimetre, for the projected side of the worked examples.
+#[cfg(test)]
+fn assert_metres(expected: [f64; 2], actual: [f64; 2]) {
+    let close = (expected[0] - actual[0]).abs() < 0.01 && (expected[1] - actual[1]).abs() < 0.01;
+    assert!(close, "expected {:?}, got {:?}", expected, actual);
+}
+
+#[test]
+fn lambert_conformal_conic() {
+    // The worked example from EPSG Guidance Note 7-2: NAD27 / Texas South
+    // Central, in US feet.
+    let wkt = r#"PROJCS["NAD_1927_StatePlane_Texas_South_Central_FIPS_4204",
+        GEOGCS["GCS_North_American_1927",DATUM["D_North_American_1927",
+        SPHEROID["Clarke_1866",6378206.4,294.9786982]],UNIT["Degree",0.0174532925199433]],
+        PROJECTION["Lambert_Conformal_Conic"],PARAMETER["False_Easting",2000000.0],
+        PARAMETER["False_Northing",0.0],PARAMETER["Central_Meridian",-99.0],
+        PARAMETER["Standard_Parallel_1",28.38333333333333],
+        PARAMETER["Standard_Parallel_2",30.28333333333333],
+        PARAMETER["Latitude_Of_Origin",27.83333333333333],UNIT["Foot_US",0.3048006096012192]]"#;
+    let crs = Crs::from_wkt(wkt).unwrap();
+    assert_near([-96.0, 28.5], crs.to_lon_lat([2963503.91, 254759.80]));
+    assert_metres([2963503.91, 254759.80], crs.project_lon_lat([-96.0, 28.5]));
+
+    // The origin of New York Long Island.
+    let crs = Crs::by_name("urn:ogc:def:crs:EPSG::2263").unwrap();
+    assert_near([-74.0, 40.0 + 10.0 / 60.0], crs.to_lon_lat([984250.0, 0.0]));
+    let metres = Crs::by_name("EPSG:32118").unwrap();
+    assert_near(
+        crs.to_lon_lat([1_000_000.0, 200_000.0]),
+        metres.to_lon_lat([1_000_000.0 * US_FOOT, 200_000.0 * US_FOOT]),
+    );
+}
+
+#[test]
+fn transverse_mercator() {
+    // The worked example from EPSG Guidance Note 7-2: the British National Grid.
+    let crs = Crs::Projected(Projection {
+        method: Method::TransverseMercator,
+        ellipsoid: (6_377_563.396, 299.324_964_6),
+        lat_0: 49.0,
+        lon_0: -2.0,
+        scale_factor: 0.999_601_271_7,
+        false_easting: 400_000.0,
+        false_northing: -100_000.0,
+        unit: 1.0,
+    });
+    assert_near([0.5, 50.5], crs.to_lon_lat([577274.99, 69740.50]));
+    assert_metres([577274.99, 69740.50], crs.project_lon_lat([0.5, 50.5]));
+
+    // The central meridian of UTM zone 18.
+    let utm = Crs::by_name("EPSG:32618").unwrap();
+    assert_near([-75.0, 0.0], utm.to_lon_lat([500_000.0, 0.0]));
+    assert_eq!(utm, Crs::utm_zone([-74.0, 40.7]));
+    let there = utm.project_lon_lat([-74.0, 40.7]);
+    assert_near([-74.0, 40.7], utm.to_lon_lat(there));
+}
+
+#[test]
+fn names_and_wkt() {
+    assert_eq!(
+        Ok(Crs::LonLat),
+        Crs::by_name("urn:ogc:def:crs:OGC:1.3:CRS84")
+    );
+    assert_eq!(Ok(Crs::LonLat), Crs::by_name("EPSG:4326"));
+    assert!(Crs::by_name("EPSG:1234").is_err());
+    assert!(Crs::by_name("nonsense").is_err());
+    assert_eq!(Ok(Crs::LonLat), Crs::from_wkt(r#"GEOGCS["GCS_WGS_1984"]"#));
+    assert!(Crs::from_wkt(r#"PROJCS["x",PROJECTION["Albers"]]"#).is_err());
+    assert_near(
+        [0.0, 0.0],
+        Crs::by_name("EPSG:3857").unwrap().to_lon_lat([0.0, 0.0]),
+    );
+}
@@ -0,0 +1,270 @@
+// GeoJSON export of the processed features, so that the intermediate results
+// (deduplicated coastlines, unioned roads, cut-out water and so on) can be
+// checked and edited in a GIS.  Features are written either as longitude and
+// latitude or in the map's own projected coordinates, which are Mercator
+// metres as `osm_load::project` computes them (close to, but not exactly, any
+// EPSG system, so no `crs` is claimed).
+
+use geojson::{self, Coordinate, Geojson};
+use linemath::Point;
+use osm_load::{self, Geometry};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use {Kind, Layer};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct GeojsonOptions {
+    // One file per layer, in a directory, rather than one combined file.
+    #[serde(default)]
+    pub split: bool,
+    // Keep projected coordinates rather than going back to longitude and
+    // latitude.
+    #[serde(default)]
+    pub projected: bool,
+}
+
+// Such as `building-shadow`, as used for file names and the `layer` property.
+fn layer_name(layer: Layer) -> String {
+    let mut name = String::new();
+    for c in format!("{:?}", layer).chars() {
+        if c.is_uppercase() && !name.is_empty() {
+            name.push('-');
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+fn ring(points: &[Point], coordinate: &dyn Fn(Point) -> Coordinate) -> Vec<Coordinate> {
+    points.iter().map(|&point| coordinate(point)).collect()
+}
+
+fn feature_geometry(
+    kind: &Kind,
+    geometry: &Geometry,
+    coordinate: &dyn Fn(Point) -> Coordinate,
+) -> Option<geojson::Geometry> {
+    if let Some(rings) = kind.polygon() {
+        let coordinates = rings
+            .iter()
+            .map(|points| ring(points, coordinate))
+            .collect();
+        return Some(geojson::Geometry::Polygon { coordinates });
+    }
+    let points = kind.resolve_coords(geometry);
+    match kind {
+        // Exports are of the processed shapes, which these were merged into.
+        Kind::Hillshade(_) | Kind::Element(..) => None,
+        Kind::Station(_)
+        | Kind::OverlayPoint(..)
+        | Kind::GpxWaypoint(_)
+        | Kind::ItineraryMarker(..)
+        | Kind::ContourLabel(..)
+        | Kind::GridLabel(..) => Some(geojson::Geometry::Point {
+            coordinates: coordinate(*points.first()?),
+        }),
+        _ if points.len() < 2 => None,
+        Kind::Building(..)
+        | Kind::Park(_)
+        | Kind::Water(_)
+        | Kind::Wetland(_)
+        | Kind::Landuse(..)
+        | Kind::ProcessedPark(_)
+            if points.len() > 3 && points[0] == points[points.len() - 1] =>
+        {
+            Some(geojson::Geometry::Polygon {
+                coordinates: vec![ring(points, coordinate)],
+            })
+        }
+        _ => Some(geojson::Geometry::LineString {
+            coordinates: ring(points, coordinate),
+        }),
+    }
+}
+
+// The layer, what the kind knows beyond its shape, and the OSM element it was
+// drawn from along with any tags kept from it.
+fn properties(kind: &Kind, geometry: &Geometry) -> Map<String, Value> {
+    let mut properties = Map::new();
+    let mut insert = |key: &str, value: Value| {
+        properties.insert(key.into(), value);
+    };
+    insert("layer", layer_name(kind.to_layer()).into());
+    fn json<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap_or(Value::Null)
+    }
+    match kind {
+        Kind::Building(_, building) => {
+            if let Value::Object(building) = json(building) {
+                for (key, value) in building.into_iter().filter(|(_, v)| !v.is_null()) {
+                    insert(&key, value);
+                }
+            }
+        }
+        Kind::Road(_, profile, access) => {
+            insert("profile", json(profile));
+            insert("access", json(access));
+        }
+        Kind::Waterway(_, width) => insert("width", (*width).into()),
+        Kind::RouteMember(_, route) | Kind::ProcessedRoute(_, route) => {
+            insert("route", json(route))
+        }
+        Kind::OverlayArea(_, style)
+        | Kind::OverlayLine(_, style)
+        | Kind::OverlayPoint(_, style)
+            if !style.is_empty() =>
+        {
+            insert("style", style.as_str().into())
+        }
+        Kind::GpxBand(_, band) => insert("band", (*band).into()),
+        Kind::ContourLabel(_, angle, text) => {
+            insert("text", text.as_str().into());
+            insert("angle", (*angle).into());
+        }
+        Kind::GridLabel(_, text) => insert("text", text.as_str().into()),
+        _ => (),
+    }
+    let source = kind.range().and_then(|r| geometry.source(r));
+    if let Some(source) = source {
+        insert("osm_id", source.name().into());
+        if let Some(relation) = source.relation {
+            insert("osm_relation", relation.into());
+        }
+    }
+    // Kept tags don't replace the properties above: a bridge's `layer=1` is
+    // written as `tag:layer`.
+    for (key, value) in source.iter().flat_map(|source| &source.tags) {
+        let key = if properties.contains_key(key) {
+            format!("tag:{}", key)
+        } else {
+            key.clone()
+        };
+        properties.insert(key, value.as_str().into());
+    }
+    properties
+}
+
+// Each feature and the name of its layer.
+fn features(
+    geometry: &Geometry,
+    results: &[Kind],
+    options: GeojsonOptions,
+) -> Vec<(String, geojson::Feature)> {
+    let coordinate = |point: Point| {
+        if options.projected {
+            [point.x(), point.y()]
+        } else {
+            let (lon, lat) = osm_load::unproject(point);
+            [lon, lat]
+        }
+    };
+    results
+        .iter()
+        .filter_map(|kind| {
+            let feature = geojson::Feature {
+                geometry: feature_geometry(kind, geometry, &coordinate)?,
+                properties: Value::Object(properties(kind, geometry)),
+            };
+            Some((layer_name(kind.to_layer()), feature))
+        })
+        .collect()
+}
+
+fn write_file(path: &Path, features: Vec<geojson::Feature>) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(file, &Geojson::new(features))?;
+    Ok(())
+}
+
+// Writes the processed features to `out`: a `.geojson` file, or a directory
+// of them named after their layers when `split`.
+pub fn export_to(
+    geometry: &Geometry,
+    results: &[Kind],
+    options: GeojsonOptions,
+    out: &str,
+) -> io::Result<()> {
+    let features = features(geometry, results, options);
+    if !options.split {
+        if let Some(dir) = Path::new(out).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        return write_file(
+            Path::new(out),
+            features.into_iter().map(|(_, f)| f).collect(),
+        );
+    }
+    let mut layers: BTreeMap<String, Vec<geojson::Feature>> = BTreeMap::new();
+    for (layer, feature) in features {
+        layers.entry(layer).or_default().push(feature);
+    }
+    fs::create_dir_all(out)?;
+    for (layer, features) in layers {
+        write_file(&Path::new(out).join(format!("{}.geojson", layer)), features)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn processed_kinds_as_features() {
+    let mut geometry = ::network::test_geometry(&[&[1, 2, 3, 1], &[4]]);
+    geometry.sources = vec![
+        osm_load::Source {
+            element: osm_load::ElementType::Way,
+            id: 7,
+            relation: None,
+            tags: vec![("name".into(), "Hall".into()), ("layer".into(), "1".into())],
+        },
+        osm_load::Source {
+            element: osm_load::ElementType::Node,
+            id: 4,
+            relation: None,
+            tags: vec![],
+        },
+    ];
+    let square = vec![
+        Point::new(0.0, 0.0),
+        Point::new(1.0, 0.0),
+        Point::new(1.0, 1.0),
+        Point::new(0.0, 0.0),
+    ];
+    let results = vec![
+        Kind::Building(0, Default::default()),
+        Kind::Station(1),
+        Kind::ProcessedRoad(vec![square]),
+        Kind::Hillshade(String::new()),
+    ];
+    let projected = GeojsonOptions {
+        split: true,
+        projected: true,
+    };
+
+    let exported = features(&geometry, &results, projected);
+    let layers: Vec<_> = exported.iter().map(|(layer, _)| layer.as_str()).collect();
+    assert_eq!(vec!["building", "station", "road"], layers);
+    let json: Vec<Value> = exported
+        .iter()
+        .map(|(_, feature)| serde_json::to_value(feature).unwrap())
+        .collect();
+    assert_eq!("Polygon", json[0]["geometry"]["type"]);
+    assert_eq!("way/7", json[0]["properties"]["osm_id"]);
+    assert_eq!("Hall", json[0]["properties"]["name"]);
+    assert_eq!("building", json[0]["properties"]["layer"]);
+    assert_eq!("1", json[0]["properties"]["tag:layer"]);
+    assert_eq!(
+        serde_json::json!([4.0, 1.0]),
+        json[1]["geometry"]["coordinates"]
+    );
+    assert_eq!("Polygon", json[2]["geometry"]["type"]);
+
+    // Back to longitude and latitude, which at the equator are nearly
+    // proportional to metres.
+    let exported = features(&geometry, &results, GeojsonOptions::default());
+    let json = serde_json::to_value(&exported[1].1).unwrap();
+    let lon = json["geometry"]["coordinates"][0].as_f64().unwrap();
+    assert!((lon - 4.0 / 111_319.49).abs() < 1e-6);
+}
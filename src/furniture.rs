@@ -0,0 +1,370 @@
+// Map furniture for finished posters: a title block, scale bar, north arrow,
+// legend and the OpenStreetMap attribution, each on a panel in a corner of the
+// map.  Furniture is drawn in the SVG's own units rather than projected
+// coordinates, and several pieces in one corner are stacked away from it.
+
+use osm_load::Bounds;
+use serde::Deserialize;
+use svg_exporter::{escape, Svg};
+use Layer;
+
+// Required by the ODbL wherever OSM data is shown.
+const ATTRIBUTION: &str = "© OpenStreetMap contributors";
+// Space inside each panel and between stacked panels.
+const PADDING: f64 = 8.0;
+// The scale bar covers at most this fraction of the map's width.
+const SCALE_BAR_FRACTION: f64 = 0.25;
+const SCALE_BAR_SEGMENTS: usize = 4;
+// Layers which only make sense alongside another one, or have no shape to
+// show in a swatch.
+const NOT_IN_LEGEND: &[Layer] = &[
+    Layer::RailHatching,
+    Layer::Hillshade,
+    Layer::ContourLabel,
+    Layer::GridLabel,
+];
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub fn by_name(name: &str) -> Option<Corner> {
+        match name {
+            "top-left" => Some(Corner::TopLeft),
+            "top-right" => Some(Corner::TopRight),
+            "bottom-left" => Some(Corner::BottomLeft),
+            "bottom-right" => Some(Corner::BottomRight),
+            _ => None,
+        }
+    }
+}
+
+// Which pieces to draw, and where.  Only the attribution, which the ODbL
+// requires, is drawn by default; setting it to None (`null` in a batch
+// manifest) leaves it off.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Furniture {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub title_corner: Corner,
+    pub scale_bar: Option<Corner>,
+    pub north_arrow: Option<Corner>,
+    pub legend: Option<Corner>,
+    pub attribution: Option<Corner>,
+    // Between the panels and the edge of the map, in SVG units.
+    pub margin: f64,
+}
+
+impl Default for Furniture {
+    fn default() -> Furniture {
+        Furniture {
+            title: None,
+            subtitle: None,
+            title_corner: Corner::TopLeft,
+            scale_bar: None,
+            north_arrow: None,
+            legend: None,
+            attribution: Some(Corner::BottomRight),
+            margin: 20.0,
+        }
+    }
+}
+
+// A piece of furniture drawn with its top left corner at the origin.
+struct Block {
+    width: f64,
+    height: f64,
+    markup: String,
+}
+
+// A rough width for sans-serif text, which is all the layout needs.
+fn text_width(text: &str, size: f64) -> f64 {
+    text.chars().count() as f64 * size * 0.55
+}
+
+fn text(x: f64, y: f64, size: f64, anchor: &str, content: &str) -> String {
+    format!(
+        r#"<text class="furniture" x="{:.1}" y="{:.1}" font-size="{}" text-anchor="{}">{}</text>"#,
+        x,
+        y,
+        size,
+        anchor,
+        escape(content)
+    )
+}
+
+fn title_block(title: &str, subtitle: Option<&str>) -> Block {
+    let (title_size, subtitle_size) = (24.0, 14.0);
+    let mut width = text_width(title, title_size);
+    let mut height = title_size;
+    let mut markup = text(PADDING, PADDING + height, title_size, "start", title);
+    if let Some(subtitle) = subtitle {
+        width = width.max(text_width(subtitle, subtitle_size));
+        height += subtitle_size + 6.0;
+        markup += &text(PADDING, PADDING + height, subtitle_size, "start", subtitle);
+    }
+    Block {
+        width: width + 2.0 * PADDING,
+        // Room for descenders.
+        height: height + 2.0 * PADDING + 4.0,
+        markup,
+    }
+}
+
+// The longest 1, 2 or 5 times a power of ten which is no more than `metres`.
+fn round_length(metres: f64) -> f64 {
+    let power = 10f64.powf(metres.log10().floor());
+    [5.0, 2.0, 1.0]
+        .iter()
+        .map(|step| step * power)
+        .find(|&length| length <= metres)
+        .unwrap_or(power)
+}
+
+fn length_label(metres: f64) -> String {
+    if metres >= 1000.0 {
+        format!("{} km", metres / 1000.0)
+    } else {
+        format!("{} m", metres)
+    }
+}
+
+// Metres on the ground become projected units by the projection's scale at
+// the centre of the map, and then SVG units by the map's scale.
+fn scale_bar(bounds: &Bounds) -> Block {
+    let svg_units_per_metre = bounds.units_per_metre() * bounds.scale_x;
+    let metres = round_length(bounds.width * SCALE_BAR_FRACTION / svg_units_per_metre);
+    let length = metres * svg_units_per_metre;
+    let (bar, size) = (6.0, 11.0);
+
+    let segment = length / SCALE_BAR_SEGMENTS as f64;
+    let mut markup = String::new();
+    for i in 0..SCALE_BAR_SEGMENTS {
+        let class = if i % 2 == 0 {
+            "furniture"
+        } else {
+            "furniture-panel"
+        };
+        markup += &format!(
+            r#"<rect class="{}" x="{:.1}" y="{:.1}" width="{:.1}" height="{}" />"#,
+            class,
+            PADDING + segment * i as f64,
+            PADDING + size + 4.0,
+            segment,
+            bar
+        );
+    }
+    markup += &text(PADDING, PADDING + size, size, "start", "0");
+    let label = length_label(metres);
+    markup += &text(PADDING + length, PADDING + size, size, "middle", &label);
+    Block {
+        width: length + 2.0 * PADDING + text_width(&label, size) / 2.0,
+        height: size + 4.0 + bar + 2.0 * PADDING,
+        markup,
+    }
+}
+
+// Mercator keeps north straight up everywhere on the map.
+fn north_arrow() -> Block {
+    let (width, height) = (20.0, 28.0);
+    let (x, y) = (PADDING, PADDING + 14.0);
+    let markup = format!(
+        r#"<path class="furniture" d="M{:.1},{:.1} L{:.1},{:.1} L{:.1},{:.1} L{:.1},{:.1} z" />{}"#,
+        x + width / 2.0,
+        y,
+        x + width,
+        y + height,
+        x + width / 2.0,
+        y + height * 0.7,
+        x,
+        y + height,
+        text(x + width / 2.0, PADDING + 11.0, 12.0, "middle", "N"),
+    );
+    Block {
+        width: width + 2.0 * PADDING,
+        height: height + 14.0 + 2.0 * PADDING,
+        markup,
+    }
+}
+
+// "index-contour" becomes "Index contour".
+fn legend_label(class: &str) -> String {
+    let label = class.replace('-', " ");
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => label,
+    }
+}
+
+// A swatch in each drawn layer's own class, top layer first.
+fn legend(svg: &Svg<Layer>, layer_order: &[Layer]) -> Option<Block> {
+    let classes: Vec<String> = layer_order
+        .iter()
+        .rev()
+        .filter(|layer| !NOT_IN_LEGEND.contains(layer) && svg.is_drawn(layer))
+        .filter_map(|layer| svg.class_of(layer))
+        .collect();
+    if classes.is_empty() {
+        return None;
+    }
+    let (row, size, swatch) = (16.0, 11.0, 18.0);
+    let mut markup = String::new();
+    let mut width: f64 = 0.0;
+    for (i, class) in classes.iter().enumerate() {
+        let y = PADDING + row * i as f64;
+        markup += &format!(
+            r#"<rect class="{}" x="{}" y="{:.1}" width="{}" height="10" />"#,
+            class,
+            PADDING,
+            y + 2.0,
+            swatch
+        );
+        let label = legend_label(class);
+        width = width.max(text_width(&label, size));
+        markup += &text(PADDING + swatch + 6.0, y + size, size, "start", &label);
+    }
+    Some(Block {
+        width: width + swatch + 6.0 + 2.0 * PADDING,
+        height: row * classes.len() as f64 + 2.0 * PADDING,
+        markup,
+    })
+}
+
+fn attribution() -> Block {
+    let size = 10.0;
+    Block {
+        width: text_width(ATTRIBUTION, size) + 2.0 * PADDING,
+        height: size + 2.0 * PADDING,
+        markup: text(PADDING, PADDING + size - 1.0, size, "start", ATTRIBUTION),
+    }
+}
+
+// Where each block's top left corner goes: at the margins of its corner, or
+// beyond the blocks already there.
+fn place(blocks: &[(Corner, &Block)], bounds: &Bounds, margin: f64) -> Vec<(f64, f64)> {
+    let mut taken = std::collections::HashMap::new();
+    blocks
+        .iter()
+        .map(|&(corner, block)| {
+            let offset = taken.entry(corner).or_insert(margin);
+            let x = match corner {
+                Corner::TopLeft | Corner::BottomLeft => margin,
+                Corner::TopRight | Corner::BottomRight => bounds.width - margin - block.width,
+            };
+            let y = match corner {
+                Corner::TopLeft | Corner::TopRight => *offset,
+                Corner::BottomLeft | Corner::BottomRight => bounds.height - *offset - block.height,
+            };
+            *offset += block.height + PADDING;
+            (x, y)
+        })
+        .collect()
+}
+
+pub fn draw(svg: &mut Svg<Layer>, furniture: &Furniture, bounds: &Bounds, layer_order: &[Layer]) {
+    let mut blocks = vec![];
+    if let Some(title) = &furniture.title {
+        let block = title_block(title, furniture.subtitle.as_deref());
+        blocks.push((furniture.title_corner, block));
+    }
+    if let Some(corner) = furniture.legend {
+        blocks.extend(legend(svg, layer_order).map(|block| (corner, block)));
+    }
+    if let Some(corner) = furniture.north_arrow {
+        blocks.push((corner, north_arrow()));
+    }
+    if let Some(corner) = furniture.scale_bar {
+        blocks.push((corner, scale_bar(bounds)));
+    }
+    if let Some(corner) = furniture.attribution {
+        blocks.push((corner, attribution()));
+    }
+
+    let placed: Vec<_> = blocks
+        .iter()
+        .map(|(corner, block)| (*corner, block))
+        .collect();
+    for ((_, block), (x, y)) in blocks.iter().zip(place(&placed, bounds, furniture.margin)) {
+        svg.add_furniture(&format!(
+            r#"<g transform="translate({:.1} {:.1})"><rect class="furniture-panel" width="{:.1}" height="{:.1}" rx="2" />{}</g>"#,
+            x, y, block.width, block.height, block.markup
+        ));
+    }
+}
+
+#[test]
+fn scale_bars_are_round_lengths() {
+    assert_eq!(500.0, round_length(730.0));
+    assert_eq!(200.0, round_length(200.0));
+    assert_eq!(1000.0, round_length(1999.0));
+    assert_eq!(0.5, round_length(0.9));
+    assert_eq!("2 km", length_label(2000.0));
+    assert_eq!("500 m", length_label(500.0));
+
+    // 1000 units across a map whose units are metres at the equator.
+    let bounds = Bounds {
+        width: 1000.0,
+        height: 500.0,
+        min_lon: 0.0,
+        min_lat: -250.0,
+        max_lon: 1000.0,
+        max_lat: 250.0,
+        scale_x: 1.0,
+        scale_y: 1.0,
+    };
+    let bar = scale_bar(&bounds);
+    assert!(bar.markup.contains(">200 m<"));
+}
+
+#[test]
+fn furniture_stacks_away_from_its_corner() {
+    let bounds = Bounds {
+        width: 1000.0,
+        height: 500.0,
+        min_lon: 0.0,
+        min_lat: 0.0,
+        max_lon: 1.0,
+        max_lat: 1.0,
+        scale_x: 1.0,
+        scale_y: 1.0,
+    };
+    let block = |width, height| Block {
+        width,
+        height,
+        markup: String::new(),
+    };
+    let (a, b, c) = (block(100.0, 40.0), block(50.0, 20.0), block(80.0, 30.0));
+    let placed = place(
+        &[
+            (Corner::BottomRight, &a),
+            (Corner::TopLeft, &c),
+            (Corner::BottomRight, &b),
+        ],
+        &bounds,
+        20.0,
+    );
+    assert_eq!(
+        vec![
+            (880.0, 440.0),
+            (20.0, 20.0),
+            (930.0, 500.0 - 20.0 - 40.0 - PADDING - 20.0)
+        ],
+        placed
+    );
+}
+
+#[test]
+fn attribution_is_drawn_unless_turned_off() {
+    let furniture: Furniture = serde_json::from_str("{}").unwrap();
+    assert_eq!(Some(Corner::BottomRight), furniture.attribution);
+    let furniture: Furniture = serde_json::from_str(r#"{"attribution": null}"#).unwrap();
+    assert_eq!(None, furniture.attribution);
+}
@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
-use serde_json::{Result, Value};
+use serde_json::Value;
 
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
 
 pub type Coordinate = [f64; 2];
 pub type LineCoordinates = Vec<Coordinate>;
@@ -38,20 +38,106 @@ pub struct Feature {
     pub properties: Value,
 }
 
+impl Geometry {
+    pub fn for_each_coordinate<F: FnMut(&mut Coordinate)>(&mut self, mut f: F) {
+        match self {
+            Geometry::Point { coordinates } => f(coordinates),
+            Geometry::LineString { coordinates } | Geometry::MultiPoint { coordinates } => {
+                coordinates.iter_mut().for_each(f)
+            }
+            Geometry::Polygon { coordinates } | Geometry::MultiLineString { coordinates } => {
+                coordinates.iter_mut().flatten().for_each(f)
+            }
+            Geometry::MultiPolygon { coordinates } => {
+                coordinates.iter_mut().flatten().flatten().for_each(f)
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", rename = "FeatureCollection")]
 pub struct Geojson {
     pub features: Vec<Feature>,
+    // The `crs` member of the 2008 GeoJSON spec, naming the coordinate system
+    // when it isn't longitude and latitude:
+    //
+    //     "crs": {"type": "name", "properties": {"name": "urn:ogc:def:crs:EPSG::2263"}}
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crs: Option<Value>,
+}
+
+impl Geojson {
+    pub fn new(features: Vec<Feature>) -> Geojson {
+        Geojson {
+            features,
+            crs: None,
+        }
+    }
+
+    // The name in the `crs` member, if there is one.
+    pub fn crs_name(&self) -> Option<&str> {
+        self.crs.as_ref()?["properties"]["name"].as_str()
+    }
 }
 
-pub fn from_file(path: &str) -> Result<Geojson> {
+pub fn from_file(path: &str) -> io::Result<Geojson> {
     println!("Attempting to load geojson from: {}", path);
-    let f = File::open(path).unwrap();
+    let f = File::open(path)?;
     let buf_reader = BufReader::new(f);
-    let v: Geojson = serde_json::from_reader(buf_reader).unwrap();
+    let v: Geojson = serde_json::from_reader(buf_reader)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
 
     println!("Loaded geojson");
     println!("Num features {}", v.features.len());
 
     Ok(v)
 }
+
+// An inline style from a feature's simplestyle properties (`stroke`, `fill`,
+// `marker-color` and so on), so overlays can be coloured by their attributes.
+// Names may be in upper case, as DBF field names often are.
+pub fn simplestyle(properties: &Value) -> String {
+    let properties = [
+        ("stroke", "stroke"),
+        ("stroke-width", "stroke-width"),
+        ("stroke-opacity", "stroke-opacity"),
+        ("fill", "fill"),
+        ("fill-opacity", "fill-opacity"),
+        ("marker-color", "fill"),
+    ]
+    .iter()
+    .filter_map(|&(key, css)| {
+        let value = properties
+            .get(key)
+            .or_else(|| properties.get(key.to_uppercase()));
+        let value = match value? {
+            Value::String(value) => value.clone(),
+            Value::Number(value) => value.to_string(),
+            _ => return None,
+        };
+        // Keep attribute values from breaking out of the declaration.
+        if value.contains([';', ':', '"']) {
+            return None;
+        }
+        Some(format!("{}:{}", css, value))
+    })
+    .collect::<Vec<_>>();
+    properties.join("; ")
+}
+
+#[test]
+fn simplestyle_properties() {
+    let properties = serde_json::json!({
+        "name": "Zone A",
+        "fill": "#ff0000",
+        "stroke-width": 2,
+        "stroke": "red; display:none",
+        "FILL-OPACITY": 0.5,
+    });
+    assert_eq!(
+        "stroke-width:2; fill:#ff0000; fill-opacity:0.5",
+        simplestyle(&properties)
+    );
+    assert_eq!("", simplestyle(&Value::Null));
+}
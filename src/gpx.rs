@@ -0,0 +1,286 @@
+// GPX files, as exported by GPS watches and bike computers: tracks, routes and
+// waypoints, drawn over the map like GeoJSON overlays.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use xml::reader::{EventReader, XmlEvent};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Waypoint {
+    pub lon: f64,
+    pub lat: f64,
+    // Metres above sea level.
+    pub elevation: Option<f64>,
+    // Seconds since the Unix epoch.
+    pub time: Option<f64>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Gpx {
+    // Each track segment separately, since a track can have gaps.
+    pub tracks: Vec<Vec<Waypoint>>,
+    pub routes: Vec<Vec<Waypoint>>,
+    pub waypoints: Vec<Waypoint>,
+}
+
+// What to colour track segments by, for `--colour-by`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColourBy {
+    Elevation,
+    Speed,
+}
+
+impl ColourBy {
+    pub fn by_name(name: &str) -> Option<ColourBy> {
+        match name {
+            "elevation" => Some(ColourBy::Elevation),
+            "speed" => Some(ColourBy::Speed),
+            _ => None,
+        }
+    }
+}
+
+pub fn from_file(path: &str) -> io::Result<Gpx> {
+    let gpx = parse(BufReader::new(File::open(path)?))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
+    println!(
+        "Loaded {}: {} track segments, {} routes, {} waypoints",
+        path,
+        gpx.tracks.len(),
+        gpx.routes.len(),
+        gpx.waypoints.len()
+    );
+    Ok(gpx)
+}
+
+pub fn parse<R: Read>(input: R) -> Result<Gpx, String> {
+    let mut gpx = Gpx::default();
+    let mut segment = vec![];
+    let mut route = vec![];
+    let mut point: Option<Waypoint> = None;
+    let mut text = String::new();
+
+    for event in EventReader::new(input) {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                text.clear();
+                if let "trkpt" | "rtept" | "wpt" = name.local_name.as_str() {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|a| a.name.local_name == key)
+                            .and_then(|a| a.value.trim().parse::<f64>().ok())
+                    };
+                    match (attribute("lon"), attribute("lat")) {
+                        (Some(lon), Some(lat)) => {
+                            point = Some(Waypoint {
+                                lon,
+                                lat,
+                                ..Waypoint::default()
+                            })
+                        }
+                        _ => return Err(format!("<{}> without lat and lon", name.local_name)),
+                    }
+                }
+            }
+            XmlEvent::Characters(chars) | XmlEvent::CData(chars) => text.push_str(&chars),
+            XmlEvent::EndElement { name } => {
+                let text = std::mem::take(&mut text);
+                match (name.local_name.as_str(), point.as_mut()) {
+                    ("ele", Some(point)) => point.elevation = text.trim().parse().ok(),
+                    ("time", Some(point)) => point.time = parse_time(text.trim()),
+                    ("name", Some(point)) => point.name = Some(text.trim().to_string()),
+                    ("trkpt", _) => segment.extend(point.take()),
+                    ("rtept", _) => route.extend(point.take()),
+                    ("wpt", _) => gpx.waypoints.extend(point.take()),
+                    ("trkseg", _) if !segment.is_empty() => {
+                        gpx.tracks.push(std::mem::take(&mut segment))
+                    }
+                    ("rte", _) if !route.is_empty() => gpx.routes.push(std::mem::take(&mut route)),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(gpx)
+}
+
+fn number(text: &str) -> Option<i64> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+// Days from 1970-01-01 to a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Parses the ISO 8601 timestamps GPX uses, like `2024-05-01T07:30:15Z` or
+// `2024-05-01T09:30:15.250+02:00`, into seconds since the Unix epoch.
+pub fn parse_time(text: &str) -> Option<f64> {
+    // Fields are sliced out by byte position.
+    if text.len() < 19 || !text.is_ascii() {
+        return None;
+    }
+    let (date_time, zone) = text.split_at(19);
+    let bytes = date_time.as_bytes();
+    if bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b'T' || bytes[13] != b':' {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| number(&date_time[range]);
+    let days = days_from_civil(field(0..4)?, field(5..7)?, field(8..10)?);
+    let mut seconds = (days * 86_400 + field(11..13)? * 3600 + field(14..16)? * 60) as f64;
+    seconds += field(17..19)? as f64;
+
+    // Fractional seconds, then the zone.
+    let fraction_len = zone
+        .find(|c: char| c != '.' && !c.is_ascii_digit())
+        .unwrap_or(zone.len());
+    let (fraction, zone) = zone.split_at(fraction_len);
+    if fraction.len() > 1 {
+        seconds += format!("0{}", fraction).parse::<f64>().ok()?;
+    }
+    match zone {
+        "" | "Z" => Some(seconds),
+        _ if zone.len() == 6 && zone.as_bytes()[3] == b':' => {
+            let sign = match &zone[..1] {
+                "+" => 1.0,
+                "-" => -1.0,
+                _ => return None,
+            };
+            let offset = number(&zone[1..3])? * 3600 + number(&zone[4..6])? * 60;
+            Some(seconds - sign * offset as f64)
+        }
+        _ => None,
+    }
+}
+
+// Great-circle distance in metres.
+fn distance(a: &Waypoint, b: &Waypoint) -> f64 {
+    const EARTH_RADIUS: f64 = 6_371_000.0;
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.lon - a.lon).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
+
+// The value each step of `segment` (from point i to i + 1) is coloured by.
+pub fn step_values(segment: &[Waypoint], colour_by: ColourBy) -> Vec<Option<f64>> {
+    segment
+        .windows(2)
+        .map(|pair| match colour_by {
+            ColourBy::Elevation => Some((pair[0].elevation? + pair[1].elevation?) / 2.0),
+            ColourBy::Speed => {
+                let seconds = pair[1].time? - pair[0].time?;
+                if seconds > 0.0 {
+                    Some(distance(&pair[0], &pair[1]) / seconds)
+                } else {
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Steps of the colour ramp; runs of steps in the same band are drawn as one line.
+pub const BANDS: usize = 12;
+
+// The band of `value` within `min..=max`.
+pub fn band(value: f64, min: f64, max: f64) -> usize {
+    if max <= min {
+        return 0;
+    }
+    (((value - min) / (max - min)) * BANDS as f64).clamp(0.0, BANDS as f64 - 1.0) as usize
+}
+
+// Blue through green and yellow to red.
+pub fn band_colour(band: usize) -> String {
+    let t = band as f64 / (BANDS - 1) as f64;
+    let hue = 240.0 * (1.0 - t);
+    format!("hsl({:.0},90%,45%)", hue)
+}
+
+#[cfg(test)]
+const TEST_GPX: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="40.7" lon="-74.0"><name>Start</name></wpt>
+  <rte><rtept lat="40.7" lon="-74.0"/><rtept lat="40.71" lon="-74.0"/></rte>
+  <trk><name>Morning run</name>
+    <trkseg>
+      <trkpt lat="40.700" lon="-74.000"><ele>10</ele><time>2024-05-01T07:30:00Z</time></trkpt>
+      <trkpt lat="40.701" lon="-74.000"><ele>12.5</ele><time>2024-05-01T07:30:40Z</time></trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="40.702" lon="-74.000"/>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+#[test]
+fn reads_tracks_routes_and_waypoints() {
+    let gpx = parse(TEST_GPX.as_bytes()).unwrap();
+    assert_eq!(2, gpx.tracks.len());
+    assert_eq!(2, gpx.tracks[0].len());
+    assert_eq!(Some(12.5), gpx.tracks[0][1].elevation);
+    assert_eq!(1, gpx.routes.len());
+    assert_eq!(Some("Start".to_string()), gpx.waypoints[0].name);
+    // The track's own name isn't mistaken for a point's.
+    assert_eq!(None, gpx.tracks[0][0].name);
+
+    let speeds = step_values(&gpx.tracks[0], ColourBy::Speed);
+    // 0.001 degrees of latitude is about 111 m, covered in 40 s.
+    assert!((speeds[0].unwrap() - 111.2 / 40.0).abs() < 0.01);
+    assert_eq!(
+        vec![Some(11.25)],
+        step_values(&gpx.tracks[0], ColourBy::Elevation)
+    );
+}
+
+#[test]
+fn timestamps() {
+    assert_eq!(Some(0.0), parse_time("1970-01-01T00:00:00Z"));
+    assert_eq!(
+        Some(1_714_548_615.25),
+        parse_time("2024-05-01T07:30:15.25Z")
+    );
+    assert_eq!(
+        parse_time("2024-05-01T07:30:15Z"),
+        parse_time("2024-05-01T09:30:15+02:00")
+    );
+    assert_eq!(None, parse_time("2024-05-01 07:30"));
+    assert_eq!(None, parse_time("2024-05-01T07:30:15 PST"));
+    assert_eq!(None, parse_time("2024-05-01T07:3é15Z"));
+    assert_eq!(None, parse_time("2024-05-01T07:30:15+é:00"));
+}
+
+#[test]
+fn upper_case_extensions_are_gpx() {
+    let dir = std::env::temp_dir().join(format!("mapfun-gpx-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let track = dir.join("RUN.GPX");
+    std::fs::write(&track, TEST_GPX).unwrap();
+
+    let geometry = ::network::test_geometry(&[&[1, 2]]);
+    let overlays = ::Overlays::new(vec![track.to_str().unwrap().into()]);
+    let options = ::RenderOptions::new(::theme::gray_theme);
+    let out = dir.join("map.svg");
+    ::render(&geometry, &[], &overlays, &options, out.to_str().unwrap()).unwrap();
+    let svg = std::fs::read_to_string(&out).unwrap();
+    assert!(svg.contains("gpx-track") && svg.contains("gpx-waypoint"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
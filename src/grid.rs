@@ -0,0 +1,228 @@
+// Coordinate grids for navigation and field maps: a graticule of meridians and
+// parallels, or a metric grid in the UTM zone at the centre of the map.  Lines
+// are traced through the projection point by point, so a grid in one system
+// curves as it should on a map in another, and are clipped to the map frame
+// with a label where each one meets the edge.
+
+use clip::Clipper;
+use crs::Crs;
+use linemath::Point;
+use osm_load::{self, Bounds};
+use serde::Deserialize;
+
+// Points along each line, enough for curves to look smooth.
+const STEPS: usize = 64;
+// Labels sit this far (in SVG units) inside the frame.
+const LABEL_INSET: f64 = 4.0;
+// The font size grid labels are styled with, for keeping them on the map.
+const LABEL_SIZE: f64 = 7.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Grid {
+    // Meridians and parallels every so many degrees.
+    Graticule(f64),
+    // UTM eastings and northings every so many metres.
+    Metric(f64),
+}
+
+pub struct GridLine {
+    pub points: Vec<Point>,
+}
+
+pub struct GridLabel {
+    pub point: Point,
+    pub text: String,
+}
+
+// Multiples of `interval` from `min` to `max`.
+fn steps(min: f64, max: f64, interval: f64) -> impl Iterator<Item = f64> {
+    let first = (min / interval).ceil() as i64;
+    let last = (max / interval).floor() as i64;
+    (first..=last).map(move |i| i as f64 * interval)
+}
+
+// Enough decimal places to write multiples of `interval` exactly.
+fn decimals(interval: f64) -> usize {
+    (0..6)
+        .find(|&places| {
+            let scaled = interval * 10f64.powi(places);
+            (scaled - scaled.round()).abs() < 1e-9 * scaled.max(1.0)
+        })
+        .unwrap_or(6) as usize
+}
+
+fn degrees(value: f64, interval: f64, positive: char, negative: char) -> String {
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    format!("{:.*}°{}", decimals(interval), value.abs(), hemisphere)
+}
+
+fn kilometres(value: f64, interval: f64) -> String {
+    format!("{:.*}", decimals(interval / 1000.0), value / 1000.0)
+}
+
+// The map frame in projected coordinates, from its corners on screen.
+fn frame(bounds: &Bounds) -> Vec<Point> {
+    [
+        (0.0, 0.0),
+        (bounds.width, 0.0),
+        (bounds.width, bounds.height),
+        (0.0, bounds.height),
+        (0.0, 0.0),
+    ]
+    .iter()
+    .map(|&(x, y)| bounds.transform_screen_coordinate_to_lat_lon(Point::new(x, y)))
+    .collect()
+}
+
+// Points around the frame, as longitude and latitude.
+fn frame_lon_lats(frame: &[Point]) -> Vec<[f64; 2]> {
+    frame
+        .windows(2)
+        .flat_map(|edge| {
+            (0..STEPS).map(move |i| {
+                let t = i as f64 / STEPS as f64;
+                let x = edge[0].x() + (edge[1].x() - edge[0].x()) * t;
+                let y = edge[0].y() + (edge[1].y() - edge[0].y()) * t;
+                let (lon, lat) = osm_load::unproject(Point::new(x, y));
+                [lon, lat]
+            })
+        })
+        .collect()
+}
+
+fn extent(points: &[[f64; 2]]) -> ([f64; 2], [f64; 2]) {
+    let mut min = [f64::INFINITY; 2];
+    let mut max = [f64::NEG_INFINITY; 2];
+    for point in points {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    (min, max)
+}
+
+// A line of constant `value` on `axis` of `crs`, across `min..max` on the
+// other axis, traced onto the map.
+fn trace(crs: Crs, axis: usize, value: f64, min: f64, max: f64) -> Vec<Point> {
+    let lon_lats = (0..=STEPS).map(|i| {
+        let along = min + (max - min) * i as f64 / STEPS as f64;
+        let mut point = [along; 2];
+        point[axis] = value;
+        let [lon, lat] = crs.to_lon_lat(point);
+        (lon, lat)
+    });
+    osm_load::project(
+        lon_lats
+            .filter(|&lon_lat| osm_load::is_projectable(lon_lat))
+            .collect(),
+    )
+}
+
+// A label just inside the frame where a line ends on it, kept clear of the
+// edge it's on.
+fn label(bounds: &Bounds, end: Point, text: &str) -> GridLabel {
+    let screen = bounds.transform_lat_lon_to_screen_coordinate(end);
+    let half_width = text.chars().count() as f64 * LABEL_SIZE * 0.55 / 2.0;
+    let (x, y) = (screen.x(), screen.y());
+    let distances = [x, bounds.width - x, y, bounds.height - y];
+    let nearest = (0..4)
+        .min_by(|&a, &b| distances[a].total_cmp(&distances[b]))
+        .unwrap();
+    let (x, y) = match nearest {
+        0 => (LABEL_INSET + half_width, y),
+        1 => (bounds.width - LABEL_INSET - half_width, y),
+        2 => (x, LABEL_INSET + LABEL_SIZE / 2.0),
+        _ => (x, bounds.height - LABEL_INSET - LABEL_SIZE / 2.0),
+    };
+    // Not `clamp`, which panics on maps narrower than the label.
+    let x = x.max(half_width).min(bounds.width - half_width);
+    GridLabel {
+        point: bounds.transform_screen_coordinate_to_lat_lon(Point::new(x, y)),
+        text: text.into(),
+    }
+}
+
+// The grid's lines, clipped to the map frame, and a label at each end.
+pub fn grid(bounds: &Bounds, grid: Grid) -> (Vec<GridLine>, Vec<GridLabel>) {
+    let frame = frame(bounds);
+    let around = frame_lon_lats(&frame);
+    let center = osm_load::unproject(bounds.center());
+    let (crs, interval) = match grid {
+        Grid::Graticule(interval) => (Crs::LonLat, interval),
+        Grid::Metric(interval) => (Crs::utm_zone([center.0, center.1]), interval),
+    };
+    let in_crs: Vec<[f64; 2]> = around.iter().map(|&p| crs.project_lon_lat(p)).collect();
+    let (min, max) = extent(&in_crs);
+
+    let format = |axis: usize, value: f64| match grid {
+        Grid::Graticule(_) if axis == 0 => degrees(value, interval, 'E', 'W'),
+        Grid::Graticule(_) => degrees(value, interval, 'N', 'S'),
+        Grid::Metric(_) => kilometres(value, interval),
+    };
+    let clipper = Clipper::new(vec![frame]);
+    let (mut lines, mut labels) = (vec![], vec![]);
+    for axis in 0..2 {
+        let other = 1 - axis;
+        for value in steps(min[axis], max[axis], interval) {
+            let traced = trace(crs, axis, value, min[other], max[other]);
+            let text = format(axis, value);
+            for points in clipper.clip_polyline(&traced) {
+                labels.push(label(bounds, points[0], &text));
+                labels.push(label(bounds, points[points.len() - 1], &text));
+                lines.push(GridLine { points });
+            }
+        }
+    }
+    (lines, labels)
+}
+
+#[test]
+fn graticule_lines_end_on_the_frame() {
+    let bounds = {
+        let corners = osm_load::project(vec![(-74.02, 40.70), (-74.0, 40.72)]);
+        let (min, max) = (corners[0], corners[1]);
+        let scale = 1000.0 / (max.y() - min.y());
+        Bounds {
+            width: (max.x() - min.x()) * scale,
+            height: 1000.0,
+            min_lon: min.x(),
+            min_lat: min.y(),
+            max_lon: max.x(),
+            max_lat: max.y(),
+            scale_x: scale,
+            scale_y: scale,
+        }
+    };
+    let (lines, labels) = grid(&bounds, Grid::Graticule(0.005));
+    // Three meridians and three parallels strictly inside the frame.
+    assert_eq!(6, lines.len());
+    assert_eq!(12, labels.len());
+    assert!(labels.iter().any(|label| label.text == "74.015°W"));
+    assert!(labels.iter().any(|label| label.text == "40.710°N"));
+    for label in &labels {
+        let screen = bounds.transform_lat_lon_to_screen_coordinate(label.point);
+        assert!(screen.x() > 0.0 && screen.x() < bounds.width);
+        assert!(screen.y() > 0.0 && screen.y() < bounds.height);
+    }
+
+    assert_eq!(
+        (3, 2, 0),
+        (decimals(0.005), decimals(0.25), decimals(1000.0))
+    );
+
+    // Kilometre lines over two kilometres or so.
+    let (lines, labels) = grid(&bounds, Grid::Metric(1000.0));
+    assert!(lines.len() >= 2 && lines.len() <= 6);
+    assert!(labels.iter().all(|label| label.text.parse::<f64>().is_ok()));
+
+    // A sliver of a map, narrower than any label.
+    let narrow = Bounds {
+        width: 10.0,
+        scale_x: 10.0 / (bounds.max_lon - bounds.min_lon),
+        ..bounds
+    };
+    let (lines, labels) = grid(&narrow, Grid::Graticule(0.005));
+    assert_eq!(2 * lines.len(), labels.len());
+}
@@ -0,0 +1,116 @@
+// A single, self-contained HTML page around a rendered map, for sharing drafts
+// which can be explored offline: pan and zoom, tooltips from the OSM tags kept
+// on features, a checkbox per layer and a search over feature names.
+
+use std::hash::Hash;
+use std::io::{self, Write};
+use svg_exporter::{escape, Svg};
+
+const STYLE: &str = include_str!("../html/map.css");
+const SCRIPT: &str = include_str!("../html/map.js");
+
+// The tags kept for tooltips and search when none are asked for.
+pub const TOOLTIP_TAGS: &[&str] = &["name", "addr:housenumber", "addr:street"];
+
+pub fn export_to_file<T: Hash + Eq>(
+    svg: &Svg<T>,
+    title: &str,
+    file: &str,
+    layer_order: &[T],
+) -> io::Result<()> {
+    let file = std::fs::File::create(file)?;
+    write(svg, title, &mut io::BufWriter::new(file), layer_order)
+}
+
+fn write<T: Hash + Eq, W: Write>(
+    svg: &Svg<T>,
+    title: &str,
+    out: &mut W,
+    layer_order: &[T],
+) -> io::Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, r#"<html><head><meta charset="utf-8">"#)?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    writeln!(out, "<style>\n{}</style>", STYLE)?;
+    writeln!(out, r#"</head><body><div id="map">"#)?;
+    svg.write(out, layer_order)?;
+    writeln!(out, "</div>")?;
+    writeln!(
+        out,
+        r#"<div id="panel"><input id="search" type="search" placeholder="Search names"><ul id="results"></ul>"#
+    )?;
+    writeln!(out, r#"<div id="layers"><b>Layers</b></div></div>"#)?;
+    writeln!(out, r#"<div id="tooltip" hidden></div>"#)?;
+    writeln!(out, "<script>\n{}</script>", SCRIPT)?;
+    writeln!(out, "</body></html>")?;
+    Ok(())
+}
+
+#[test]
+fn pages_need_nothing_else() {
+    use linemath::Point;
+    use osm_load::Bounds;
+
+    let bounds = Bounds {
+        width: 10.0,
+        height: 10.0,
+        min_lon: 0.0,
+        min_lat: 0.0,
+        max_lon: 10.0,
+        max_lat: 10.0,
+        scale_x: 1.0,
+        scale_y: 1.0,
+    };
+    let mut svg = Svg::new(bounds);
+    svg.set_style(0, "road", "stroke:#000");
+    let line = [Point::new(1.0, 1.0), Point::new(9.0, 9.0)];
+    svg.draw_polyline_with(0, &line, &[("data-name", "Broad & Main")]);
+
+    let mut out = vec![];
+    write(&svg, "<Draft>", &mut out, &[0]).unwrap();
+    let page = String::from_utf8(out).unwrap();
+    assert!(page.contains("<title>&lt;Draft&gt;</title>"));
+    assert!(page.contains(r#"<g data-layer="road">"#));
+    assert!(page.contains(r#"data-name="Broad &amp; Main""#));
+    // Nothing is fetched from elsewhere.
+    assert!(!page.contains("<script src") && !page.contains("<link"));
+}
+
+#[test]
+fn streets_can_be_searched_by_name() {
+    use linemath::Point;
+    use osm_load::Bounds;
+
+    let bounds = Bounds {
+        width: 10.0,
+        height: 10.0,
+        min_lon: 0.0,
+        min_lat: 0.0,
+        max_lon: 10.0,
+        max_lat: 10.0,
+        scale_x: 1.0,
+        scale_y: 1.0,
+    };
+    // Streets are drawn twice: as part of the unioned road surface, which has
+    // no tags, and as an invisible line which carries them.
+    let mut svg = Svg::new(bounds);
+    svg.set_style(0, "road", "fill:#bbb");
+    svg.set_style(1, "element-line", "stroke:transparent");
+    let surface = vec![
+        Point::new(1.0, 1.0),
+        Point::new(9.0, 1.0),
+        Point::new(9.0, 2.0),
+        Point::new(1.0, 2.0),
+    ];
+    svg.draw_polygon(0, &[surface]);
+    let street = [Point::new(1.0, 1.5), Point::new(9.0, 1.5)];
+    svg.draw_polyline_with(1, &street, &[("data-name", "Main Street")]);
+
+    let mut out = vec![];
+    write(&svg, "Draft", &mut out, &[0, 1]).unwrap();
+    let page = String::from_utf8(out).unwrap();
+    assert!(page.contains(r#"<g data-layer="element-line">"#));
+    assert!(page.contains(r#"data-name="Main Street""#));
+    // Which is what the search goes through.
+    assert!(page.contains("querySelectorAll('[data-name]')"));
+}
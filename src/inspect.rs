@@ -0,0 +1,204 @@
+// `mapfun inspect`: a report of the tags in an extract and what the rules make
+// of them, to show what a theme is missing before rendering.
+
+use osm_load::{self, ElementType, Feature, Geometry, Tag};
+use rules::RuleSet;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use {kind_for, Kind, TARGET_HEIGHT};
+
+#[derive(Default, Serialize)]
+pub struct TagCount {
+    pub count: usize,
+    // How many of the elements with this tag the rules turned into features.
+    pub classified: usize,
+    pub dropped: usize,
+}
+
+#[derive(Default, Serialize)]
+pub struct LayerCount {
+    pub features: usize,
+    pub vertices: usize,
+}
+
+#[derive(Default, Serialize)]
+pub struct Report {
+    // Element type -> key -> number of elements with that key.
+    pub keys: BTreeMap<String, BTreeMap<String, usize>>,
+    // Element type -> `key=value` -> counts.
+    pub tags: BTreeMap<String, BTreeMap<String, TagCount>>,
+    pub layers: BTreeMap<String, LayerCount>,
+}
+
+fn element_name(element: ElementType) -> String {
+    format!("{:?}", element).to_lowercase()
+}
+
+impl Report {
+    fn add(&mut self, element: ElementType, tags: &[Tag], classified: bool) {
+        let element = element_name(element);
+        let keys = self.keys.entry(element.clone()).or_default();
+        let values = self.tags.entry(element).or_default();
+        for tag in tags {
+            *keys.entry(tag.key.clone()).or_default() += 1;
+            let count = values
+                .entry(format!("{}={}", tag.key, tag.val))
+                .or_default();
+            count.count += 1;
+            if classified {
+                count.classified += 1;
+            } else {
+                count.dropped += 1;
+            }
+        }
+    }
+
+    pub fn write_text<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "# keys")?;
+        for (element, keys) in &self.keys {
+            let mut keys: Vec<_> = keys.iter().collect();
+            keys.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (key, count) in keys {
+                writeln!(out, "{}\t{}\t{}", element, key, count)?;
+            }
+        }
+
+        writeln!(out, "\n# tags (count, classified, dropped)")?;
+        for (element, tags) in &self.tags {
+            let mut tags: Vec<_> = tags.iter().collect();
+            tags.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+            for (tag, count) in tags {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    element, tag, count.count, count.classified, count.dropped
+                )?;
+            }
+        }
+
+        writeln!(out, "\n# layers (features, vertices)")?;
+        for (layer, count) in &self.layers {
+            writeln!(out, "{}\t{}\t{}", layer, count.features, count.vertices)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_classified(rules: &RuleSet, feature: &Feature) -> bool {
+    match rules.find(feature) {
+        // Relations only pass their tags on to their members, so a match is
+        // all they need.
+        Some(_) if feature.element == ElementType::Relation => true,
+        Some(rule) => kind_for(&rule.layer, feature, 0).is_some(),
+        None => false,
+    }
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+pub fn inspect(path: &str, rules: &RuleSet) -> std::io::Result<Report> {
+    let doc = osm_load::read_osm(path)?;
+    let mut report = Report::default();
+
+    for node in doc.nodes.values().filter(|node| !node.tags.is_empty()) {
+        let feature = Feature {
+            element: ElementType::Node,
+            id: node.id,
+            tags: &node.tags,
+            relation: None,
+        };
+        report.add(
+            ElementType::Node,
+            &node.tags,
+            is_classified(rules, &feature),
+        );
+    }
+    for way in doc.ways.values() {
+        let feature = Feature {
+            element: ElementType::Way,
+            id: way.id,
+            tags: &way.tags,
+            relation: None,
+        };
+        report.add(ElementType::Way, &way.tags, is_classified(rules, &feature));
+    }
+    for relation in doc.relations.values() {
+        let feature = Feature {
+            element: ElementType::Relation,
+            id: relation.id,
+            tags: &relation.tags,
+            relation: None,
+        };
+        let classified = is_classified(rules, &feature);
+        report.add(ElementType::Relation, &relation.tags, classified);
+    }
+
+    // Layers are counted under the names rules give them.
+    let filter = |feature: &Feature, range| {
+        let rule = rules.find(feature)?;
+        let kind = kind_for(&rule.layer, feature, range)?;
+        Some((rule.layer.clone(), kind))
+    };
+    let (geometry, kinds): (Geometry, Vec<(String, Kind)>) =
+        Geometry::from_osm(&doc, &filter, TARGET_HEIGHT)?;
+    for (layer, kind) in &kinds {
+        let layer = report.layers.entry(layer.clone()).or_default();
+        layer.features += 1;
+        layer.vertices += kind.resolve_coords(&geometry).len();
+    }
+
+    Ok(report)
+}
+
+#[test]
+fn tags_are_counted_per_element_type() {
+    let tag = |key: &str, val: &str| Tag {
+        key: key.into(),
+        val: val.into(),
+    };
+    let mut report = Report::default();
+    report.add(ElementType::Way, &[tag("highway", "primary")], true);
+    report.add(ElementType::Way, &[tag("highway", "primary")], false);
+    report.add(ElementType::Node, &[tag("highway", "stop")], false);
+
+    assert_eq!(2, report.keys["way"]["highway"]);
+    assert_eq!(1, report.keys["node"]["highway"]);
+    let primary = &report.tags["way"]["highway=primary"];
+    assert_eq!(
+        (2, 1, 1),
+        (primary.count, primary.classified, primary.dropped)
+    );
+}
+
+#[test]
+fn extracts_are_reported_by_rule_layer() {
+    use load_rules;
+
+    let dir = std::env::temp_dir().join(format!("mapfun-inspect-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tiny.osm");
+    std::fs::write(
+        &path,
+        r#"<osm>
+  <bounds minlat="40.0" minlon="-74.0" maxlat="40.1" maxlon="-73.9"/>
+  <node id="1" lat="40.01" lon="-73.99"/>
+  <node id="2" lat="40.02" lon="-73.99"/>
+  <node id="3" lat="40.02" lon="-73.98"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="1"/>
+    <tag k="building" v="yes"/></way>
+  <way id="11"><nd ref="1"/><nd ref="3"/><tag k="highway" v="residential"/></way>
+  <way id="12"><nd ref="2"/><nd ref="3"/><tag k="fixme" v="yes"/></way>
+</osm>"#,
+    )
+    .unwrap();
+    let (rules, _) = load_rules(None).unwrap();
+    let report = inspect(path.to_str().unwrap(), &rules).unwrap();
+
+    assert_eq!(3, report.keys["way"].len());
+    assert_eq!(1, report.tags["way"]["fixme=yes"].dropped);
+    assert_eq!(1, report.tags["way"]["highway=residential"].classified);
+    let layers: Vec<_> = report.layers.keys().map(String::as_str).collect();
+    assert_eq!(vec!["building", "road"], layers);
+    assert_eq!(4, report.layers["building"].vertices);
+    std::fs::remove_dir_all(dir).unwrap();
+}
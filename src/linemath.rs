@@ -1,99 +1,171 @@
+use i_overlay::core::fill_rule::FillRule;
+use i_overlay::core::overlay_rule::OverlayRule;
+use i_overlay::float::simplify::SimplifyShape;
+use i_overlay::float::single::SingleFloatOverlay;
+#[cfg(test)]
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::btree_map::*;
+use std::collections::BTreeSet;
+use std::f64::consts::PI;
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
-struct Point(f64, f64);
+// A projected coordinate.  NaN is rejected when a point is made, which gives
+// points a total order, and -0.0 is stored as 0.0 so that equal points compare
+// equal.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Point(f64, f64);
+
+impl Point {
+    // Panics on NaN; coordinates from input files should use `try_new`.
+    pub fn new(x: f64, y: f64) -> Point {
+        match Point::try_new(x, y) {
+            Some(point) => point,
+            None => panic!("NaN in point ({}, {})", x, y),
+        }
+    }
+
+    pub fn try_new(x: f64, y: f64) -> Option<Point> {
+        if x.is_nan() || y.is_nan() {
+            None
+        } else {
+            Some(Point(x + 0.0, y + 0.0))
+        }
+    }
+
+    pub fn x(self) -> f64 {
+        self.0
+    }
+
+    pub fn y(self) -> f64 {
+        self.1
+    }
+}
 
 impl Eq for Point {}
+impl PartialOrd for Point {
+    fn partial_cmp(&self, other: &Point) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
 impl Ord for Point {
     fn cmp(&self, other: &Point) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.0.total_cmp(&other.0).then(self.1.total_cmp(&other.1))
     }
 }
 
-#[flame]
-fn inline_map<A: Sized, B: Sized, F: Fn(A) -> B>(mut input: Vec<A>, f: F) -> Vec<B> {
-    use std::mem::*;
-    assert!(size_of::<A>() == size_of::<B>());
-    assert!(align_of::<A>() == align_of::<B>());
-    for item in input.iter_mut() {
-        let mut swap_into: A = unsafe { uninitialized() };
-        swap(&mut swap_into, item);
+// Anything the geometry functions can take points as.
+pub trait Coord: Copy {
+    fn xy(self) -> (f64, f64);
+    fn from_xy(x: f64, y: f64) -> Self;
+}
 
-        let mut out: B = f(swap_into);
-        {
-            let out_ref: &mut B = &mut out;
-            let out_transmuted: &mut A = unsafe { transmute(out_ref) };
-            swap(out_transmuted, item);
-        }
-        forget(out);
+impl Coord for Point {
+    fn xy(self) -> (f64, f64) {
+        (self.0, self.1)
+    }
+    fn from_xy(x: f64, y: f64) -> Point {
+        Point::new(x, y)
     }
-    let result: Vec<B> = unsafe { transmute(input) };
-    result
 }
 
-#[flame]
-pub fn dedup(line_segments: Vec<Vec<(f64, f64)>>) -> Vec<Vec<(f64, f64)>> {
-    let mut into = inline_map(line_segments, |ls| inline_map(ls, |(a, b)| Point(a, b)));
-    dedup_inner(&mut into);
-    let outof = inline_map(into, |ls| inline_map(ls, |Point(a, b)| (a, b)));
-    outof
+impl Coord for (f64, f64) {
+    fn xy(self) -> (f64, f64) {
+        self
+    }
+    fn from_xy(x: f64, y: f64) -> (f64, f64) {
+        (x, y)
+    }
 }
 
-// Deduplicates the list of line segments.  The order may not be the same after processing.
-fn dedup_inner(line_segments: &mut Vec<Vec<Point>>) {
-    let mut start_points = BTreeMap::new();
-    let mut should_remove = vec![];
+impl Coord for [f64; 2] {
+    fn xy(self) -> (f64, f64) {
+        (self[0], self[1])
+    }
+    fn from_xy(x: f64, y: f64) -> [f64; 2] {
+        [x, y]
+    }
+}
 
-    for (i, line_segment) in line_segments.iter().enumerate() {
-        if line_segment.is_empty() {
-            should_remove.push(i);
-            continue;
-        }
-        let my_start = line_segment[0];
+// Splits lines into the edges of a graph whose nodes are the ends of lines and
+// the points where lines branch, and keeps one copy of each edge whichever
+// direction it was drawn in.  Lines which share only part of their length come
+// out as the shared edge plus the parts that differ.  Lines of fewer than two
+// distinct points are dropped.
+#[cfg_attr(feature = "profiling", flame)]
+pub fn dedup<P: Ord + Copy>(line_segments: &mut Vec<Vec<P>>) {
+    for line in line_segments.iter_mut() {
+        line.dedup();
+    }
+    line_segments.retain(|line| line.len() > 1);
 
-        match start_points.entry(my_start) {
-            Entry::Vacant(vacant) => {
-                vacant.insert(vec![i]);
-            }
-            Entry::Occupied(mut occupied) => {
-                let collided = occupied
-                    .get()
-                    .iter()
-                    .any(|idx| &line_segments[*idx] == line_segment);
-                if collided {
-                    should_remove.push(i);
-                } else {
-                    occupied.get_mut().push(i);
-                }
+    let mut neighbours: BTreeMap<P, BTreeSet<P>> = BTreeMap::new();
+    for line in line_segments.iter() {
+        for pair in line.windows(2) {
+            neighbours.entry(pair[0]).or_default().insert(pair[1]);
+            neighbours.entry(pair[1]).or_default().insert(pair[0]);
+        }
+    }
+    // A point with exactly two neighbours lies on a single run of edges, so
+    // every line through it continues the same way.
+    let mut nodes: BTreeSet<P> = neighbours
+        .into_iter()
+        .filter(|(_, next)| next.len() != 2)
+        .map(|(point, _)| point)
+        .collect();
+    // Line ends are nodes, and so are points where a line doubles back on
+    // itself, since the edges either side of them are the same.
+    for line in line_segments.iter() {
+        nodes.insert(line[0]);
+        nodes.insert(line[line.len() - 1]);
+        for triple in line.windows(3) {
+            if triple[0] == triple[2] {
+                nodes.insert(triple[1]);
             }
         }
     }
 
-    should_remove.sort();
-    for &idx in should_remove.iter().rev() {
-        line_segments.swap_remove(idx);
+    let mut seen = BTreeSet::new();
+    let mut edges = vec![];
+    for line in line_segments.drain(..) {
+        let mut start = 0;
+        for end in 1..line.len() {
+            if end + 1 < line.len() && !nodes.contains(&line[end]) {
+                continue;
+            }
+            let edge = &line[start..=end];
+            let reversed: Vec<P> = edge.iter().rev().cloned().collect();
+            let key = if reversed[..] < *edge {
+                reversed
+            } else {
+                edge.to_vec()
+            };
+            if seen.insert(key) {
+                edges.push(edge.to_vec());
+            }
+            start = end;
+        }
     }
+    *line_segments = edges;
 }
 
-pub fn equalize(segment: &mut Vec<(f64, f64)>) {
-    let (sx, sy) = segment[0];
-    let (_, ey) = segment[segment.len() - 1];
-    segment.push((sx, ey));
-    segment.push((sx, sy));
+pub fn equalize(segment: &mut Vec<Point>) {
+    let start = segment[0];
+    let end = segment[segment.len() - 1];
+    segment.push(Point::new(start.x(), end.y()));
+    segment.push(start);
 }
 
-#[flame]
-pub fn connect(mut segments: Vec<Vec<(f64, f64)>>) -> Vec<Vec<(f64, f64)>> {
+// Joins lines end to start wherever one ends where another begins.  Lines
+// which only meet end to end or start to start are joined too, by reversing
+// one of them, once no other joins are left.
+#[cfg_attr(feature = "profiling", flame)]
+pub fn connect<P: PartialEq>(segments: &mut Vec<Vec<P>>) {
+    segments.retain(|segment| !segment.is_empty());
     loop {
         let mut swap_indexes = None;
         'outer: for (i, line_i) in segments.iter().enumerate() {
             for (j, line_j) in segments.iter().enumerate() {
-                if i == j {
-                    continue;
-                }
-                let start_i = line_i[0];
-                let end_j = line_j[line_j.len() - 1];
-                if start_i == end_j {
+                if i != j && line_i[0] == line_j[line_j.len() - 1] {
                     swap_indexes = Some((i, j));
                     break 'outer;
                 }
@@ -101,44 +173,272 @@ pub fn connect(mut segments: Vec<Vec<(f64, f64)>>) -> Vec<Vec<(f64, f64)>> {
         }
         match swap_indexes {
             Some((i, j)) => {
-                let (mut i_contents, mut j_contents) = if i > j {
-                    let i_contents = segments.swap_remove(i);
-                    let j_contents = segments.swap_remove(j);
-                    (i_contents, j_contents)
-                } else {
-                    let j_contents = segments.swap_remove(j);
-                    let i_contents = segments.swap_remove(i);
-                    (i_contents, j_contents)
-                };
-                j_contents.append(&mut i_contents);
-                segments.push(j_contents);
-                continue;
+                // The shared point is already the end of `j`.
+                let tail = std::mem::take(&mut segments[i]);
+                segments[j].extend(tail.into_iter().skip(1));
+                segments.swap_remove(i);
+            }
+            None => match facing(segments) {
+                // Reversing `i` makes it join up with another line next time round.
+                Some(i) => segments[i].reverse(),
+                None => break,
+            },
+        }
+    }
+}
+
+// A line which shares its start with another line's start, or its end with
+// another line's end.
+fn facing<P: PartialEq>(segments: &[Vec<P>]) -> Option<usize> {
+    segments.iter().enumerate().find_map(|(i, line_i)| {
+        let shares_an_end = segments[i + 1..].iter().any(|line_j| {
+            line_i[0] == line_j[0] || line_i[line_i.len() - 1] == line_j[line_j.len() - 1]
+        });
+        if shares_an_end {
+            Some(i)
+        } else {
+            None
+        }
+    })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Join {
+    Round,
+    // The miter is replaced by a bevel once it sticks out further than `limit` half-widths.
+    Miter(f64),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Cap {
+    Butt,
+    Square,
+    Round,
+}
+
+// Number of segments used to approximate a half circle.
+const ARC_STEPS: usize = 8;
+
+fn signed_area<P: Coord>(ring: &[P]) -> f64 {
+    let mut area = 0.0;
+    for i in 0..ring.len() {
+        let (x1, y1) = ring[i].xy();
+        let (x2, y2) = ring[(i + 1) % ring.len()].xy();
+        area += x1 * y2 - x2 * y1;
+    }
+    area / 2.0
+}
+
+fn counter_clockwise(mut ring: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    if signed_area(&ring) < 0.0 {
+        ring.reverse();
+    }
+    ring
+}
+
+fn arc(center: (f64, f64), radius: f64, from: f64, to: f64) -> Vec<(f64, f64)> {
+    let steps = ((((to - from).abs() / PI) * ARC_STEPS as f64).ceil() as usize).max(1);
+    (0..=steps)
+        .map(|i| {
+            let angle = from + (to - from) * (i as f64 / steps as f64);
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+// Outlines a polyline at `half_width` on either side.  The result is a set of
+// counter-clockwise rings (one per segment, join and cap) which cover the buffer
+// when combined with `union`.
+#[cfg_attr(feature = "profiling", flame)]
+pub fn buffer<P: Coord>(line: &[P], half_width: f64, join: Join, cap: Cap) -> Vec<Vec<P>> {
+    buffer_xy(line, half_width, join, cap)
+        .into_iter()
+        .map(|piece| piece.into_iter().map(|(x, y)| P::from_xy(x, y)).collect())
+        .collect()
+}
+
+fn buffer_xy<P: Coord>(line: &[P], half_width: f64, join: Join, cap: Cap) -> Vec<Vec<(f64, f64)>> {
+    let mut points: Vec<(f64, f64)> = Vec::with_capacity(line.len());
+    for p in line.iter().map(|p| p.xy()) {
+        if points.last() != Some(&p) {
+            points.push(p);
+        }
+    }
+
+    let mut pieces = vec![];
+    if points.is_empty() || half_width <= 0.0 {
+        return pieces;
+    }
+    if points.len() == 1 {
+        if cap == Cap::Round {
+            pieces.push(arc(points[0], half_width, 0.0, 2.0 * PI));
+        }
+        return pieces;
+    }
+
+    let directions: Vec<f64> = points
+        .windows(2)
+        .map(|w| (w[1].1 - w[0].1).atan2(w[1].0 - w[0].0))
+        .collect();
+
+    for (w, &angle) in points.windows(2).zip(directions.iter()) {
+        let (nx, ny) = (-angle.sin() * half_width, angle.cos() * half_width);
+        let (a, b) = (w[0], w[1]);
+        pieces.push(counter_clockwise(vec![
+            (a.0 - nx, a.1 - ny),
+            (b.0 - nx, b.1 - ny),
+            (b.0 + nx, b.1 + ny),
+            (a.0 + nx, a.1 + ny),
+        ]));
+    }
+
+    let last = points.len() - 1;
+    let closed = last > 1 && points[0] == points[last];
+    let corners = if closed { 0..last } else { 1..last };
+    for i in corners {
+        let center = points[i];
+        let incoming = directions[if i == 0 { last - 1 } else { i - 1 }];
+        let outgoing = directions[i];
+        let mut turn = outgoing - incoming;
+        while turn > PI {
+            turn -= 2.0 * PI;
+        }
+        while turn < -PI {
+            turn += 2.0 * PI;
+        }
+        if turn.abs() < 1e-9 {
+            continue;
+        }
+        // The gap opens on the outside of the turn: the right side for left turns.
+        let side = if turn > 0.0 { -PI / 2.0 } else { PI / 2.0 };
+        let (from, to) = (incoming + side, incoming + side + turn);
+        let mut piece = vec![center];
+        match join {
+            Join::Round => piece.extend(arc(center, half_width, from, to)),
+            Join::Miter(limit) => {
+                let miter_length = half_width / (turn / 2.0).cos().abs();
+                piece.push(arc(center, half_width, from, from)[0]);
+                if miter_length <= limit * half_width {
+                    let bisector = from + turn / 2.0;
+                    piece.push((
+                        center.0 + miter_length * bisector.cos(),
+                        center.1 + miter_length * bisector.sin(),
+                    ));
+                }
+                piece.push(arc(center, half_width, to, to)[0]);
             }
-            None => break,
         }
+        pieces.push(counter_clockwise(piece));
+    }
+
+    if closed {
+        return pieces;
+    }
+    let ends = [
+        (points[0], directions[0] + PI),
+        (points[last], directions[last - 1]),
+    ];
+    for &(center, facing) in ends.iter() {
+        match cap {
+            Cap::Butt => (),
+            Cap::Round => pieces.push(counter_clockwise(arc(
+                center,
+                half_width,
+                facing - PI / 2.0,
+                facing + PI / 2.0,
+            ))),
+            Cap::Square => {
+                let (dx, dy) = (facing.cos() * half_width, facing.sin() * half_width);
+                let (nx, ny) = (-dy, dx);
+                pieces.push(counter_clockwise(vec![
+                    (center.0 - nx, center.1 - ny),
+                    (center.0 - nx + dx, center.1 - ny + dy),
+                    (center.0 + nx + dx, center.1 + ny + dy),
+                    (center.0 + nx, center.1 + ny),
+                ]));
+            }
+        }
+    }
+
+    pieces
+}
+
+fn to_contours<P: Coord>(rings: Vec<Vec<P>>) -> Vec<Vec<[f64; 2]>> {
+    rings
+        .into_iter()
+        .filter(|ring| ring.len() >= 3)
+        .map(|ring| {
+            ring.into_iter()
+                .map(|p| p.xy())
+                .map(|(x, y)| [x, y])
+                .collect()
+        })
+        .collect()
+}
+
+fn from_shapes<P: Coord>(shapes: Vec<Vec<Vec<[f64; 2]>>>) -> Vec<Vec<Vec<P>>> {
+    shapes
+        .into_iter()
+        .map(|shape| {
+            shape
+                .into_iter()
+                .map(|contour| {
+                    let mut ring: Vec<P> =
+                        contour.into_iter().map(|[x, y]| P::from_xy(x, y)).collect();
+                    let first = ring[0];
+                    ring.push(first);
+                    ring
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Merges overlapping rings into polygons.  Each polygon is returned as its outer
+// ring followed by any holes.
+#[cfg_attr(feature = "profiling", flame)]
+pub fn union<P: Coord>(rings: Vec<Vec<P>>) -> Vec<Vec<Vec<P>>> {
+    let contours = to_contours(rings);
+    if contours.is_empty() {
+        return vec![];
     }
-    segments
+    from_shapes(contours.simplify_shape(FillRule::NonZero))
+}
+
+// The area covered by `rings` but not by `holes`, as polygons in the same form as `union`.
+#[cfg_attr(feature = "profiling", flame)]
+pub fn difference<P: Coord>(rings: Vec<Vec<P>>, holes: Vec<Vec<P>>) -> Vec<Vec<Vec<P>>> {
+    let (contours, holes) = (to_contours(rings), to_contours(holes));
+    if contours.is_empty() {
+        return vec![];
+    }
+    if holes.is_empty() {
+        return from_shapes(contours.simplify_shape(FillRule::NonZero));
+    }
+    from_shapes(contours.overlay(&holes, OverlayRule::Difference, FillRule::NonZero))
 }
 
 #[test]
 fn empty_list() {
     let mut input = vec![];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(Vec::<Vec<Point>>::new(), input)
 }
 
-
 #[test]
 fn one_empty_segment() {
     let mut input = vec![vec![]];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(Vec::<Vec<Point>>::new(), input)
 }
 
 #[test]
 fn one_full_segment() {
     let mut input = vec![vec![Point(0.0, 0.0), Point(1.0, 1.0)]];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(vec![vec![Point(0.0, 0.0), Point(1.0, 1.0)]], input)
 }
 
@@ -148,7 +448,7 @@ fn one_duplicate_segment() {
         vec![Point(0.0, 0.0), Point(1.0, 1.0)],
         vec![Point(0.0, 0.0), Point(1.0, 1.0)],
     ];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(vec![vec![Point(0.0, 0.0), Point(1.0, 1.0)]], input)
 }
 
@@ -159,7 +459,7 @@ fn one_duplicate_segment_with_another_segment_inbetween() {
         vec![Point(2.0, 3.0), Point(4.0, 5.0)],
         vec![Point(0.0, 0.0), Point(1.0, 1.0)],
     ];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(
         vec![
             vec![Point(0.0, 0.0), Point(1.0, 1.0)],
@@ -177,7 +477,7 @@ fn two_duplicate_segments() {
         vec![Point(0.0, 0.0), Point(1.0, 1.0)],
         vec![Point(2.0, 3.0), Point(4.0, 5.0)],
     ];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(
         vec![
             vec![Point(0.0, 0.0), Point(1.0, 1.0)],
@@ -187,14 +487,13 @@ fn two_duplicate_segments() {
     )
 }
 
-
 #[test]
 fn two_distinct_segments_that_start_with_the_same_point() {
     let mut input = vec![
         vec![Point(0.0, 0.0), Point(1.0, 1.0)],
         vec![Point(0.0, 0.0), Point(4.0, 5.0)],
     ];
-    dedup_inner(&mut input);
+    dedup(&mut input);
     assert_eq!(
         vec![
             vec![Point(0.0, 0.0), Point(1.0, 1.0)],
@@ -203,3 +502,254 @@ fn two_distinct_segments_that_start_with_the_same_point() {
         input
     )
 }
+
+#[test]
+fn reversed_duplicate_segment() {
+    let mut input = vec![
+        vec![Point(0.0, 0.0), Point(1.0, 1.0), Point(2.0, 0.0)],
+        vec![Point(2.0, 0.0), Point(1.0, 1.0), Point(0.0, 0.0)],
+    ];
+    dedup(&mut input);
+    assert_eq!(
+        vec![vec![Point(0.0, 0.0), Point(1.0, 1.0), Point(2.0, 0.0)]],
+        input
+    )
+}
+
+#[test]
+fn partly_shared_segments_are_split() {
+    let mut input = vec![
+        vec![
+            Point(0.0, 0.0),
+            Point(1.0, 0.0),
+            Point(2.0, 0.0),
+            Point(3.0, 0.0),
+        ],
+        vec![
+            Point(3.0, 0.0),
+            Point(2.0, 0.0),
+            Point(1.0, 0.0),
+            Point(1.0, 5.0),
+        ],
+    ];
+    dedup(&mut input);
+    assert_eq!(
+        vec![
+            vec![Point(0.0, 0.0), Point(1.0, 0.0)],
+            vec![Point(1.0, 0.0), Point(2.0, 0.0), Point(3.0, 0.0)],
+            vec![Point(1.0, 0.0), Point(1.0, 5.0)],
+        ],
+        input
+    )
+}
+
+#[test]
+fn connect_reverses_lines_drawn_the_other_way() {
+    let mut input = vec![
+        vec![Point(0.0, 0.0), Point(1.0, 0.0)],
+        vec![Point(2.0, 0.0), Point(1.0, 0.0)],
+    ];
+    connect(&mut input);
+    assert_eq!(
+        vec![vec![Point(2.0, 0.0), Point(1.0, 0.0), Point(0.0, 0.0)]],
+        input
+    )
+}
+
+#[cfg(test)]
+fn polygon_area<P: Coord>(polygon: &[Vec<P>]) -> f64 {
+    polygon
+        .iter()
+        .map(|ring| signed_area(ring))
+        .sum::<f64>()
+        .abs()
+}
+
+#[test]
+fn buffer_straight_line_with_butt_caps() {
+    let pieces = buffer(&[(0.0, 0.0), (10.0, 0.0)], 1.0, Join::Round, Cap::Butt);
+    let unioned = union(pieces);
+    assert_eq!(1, unioned.len());
+    assert!((polygon_area(&unioned[0]) - 20.0).abs() < 1e-6);
+}
+
+#[test]
+fn buffer_square_caps_extend_the_line() {
+    let pieces = buffer(&[(0.0, 0.0), (10.0, 0.0)], 1.0, Join::Round, Cap::Square);
+    let unioned = union(pieces);
+    assert_eq!(1, unioned.len());
+    assert!((polygon_area(&unioned[0]) - 24.0).abs() < 1e-6);
+}
+
+#[test]
+fn buffer_round_caps_add_a_circle() {
+    let pieces = buffer(&[(0.0, 0.0), (10.0, 0.0)], 1.0, Join::Round, Cap::Round);
+    let area = polygon_area(&union(pieces)[0]);
+    // The polygonal caps are slightly smaller than a true circle.
+    assert!(area < 20.0 + PI && area > 20.0 + PI * 0.95);
+}
+
+#[test]
+fn buffer_miter_join_fills_the_corner() {
+    let line = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+    let pieces = buffer(&line, 1.0, Join::Miter(4.0), Cap::Butt);
+    let unioned = union(pieces);
+    assert_eq!(1, unioned.len());
+    // Two 10x2 strips overlapping in a 1x1 square, plus the 1x1 outer corner.
+    assert!((polygon_area(&unioned[0]) - 40.0).abs() < 1e-6);
+}
+
+#[test]
+fn buffer_miter_limit_falls_back_to_bevel() {
+    let line = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+    let pieces = buffer(&line, 1.0, Join::Miter(1.0), Cap::Butt);
+    let area = polygon_area(&union(pieces)[0]);
+    assert!((area - 39.5).abs() < 1e-6);
+}
+
+#[test]
+fn union_of_crossing_roads_is_one_polygon() {
+    let mut pieces = buffer(&[(0.0, 5.0), (10.0, 5.0)], 1.0, Join::Round, Cap::Butt);
+    pieces.extend(buffer(
+        &[(5.0, 0.0), (5.0, 10.0)],
+        1.0,
+        Join::Round,
+        Cap::Butt,
+    ));
+    let unioned = union(pieces);
+    assert_eq!(1, unioned.len());
+    assert!((polygon_area(&unioned[0]) - 36.0).abs() < 1e-6);
+}
+
+#[test]
+fn union_keeps_enclosed_holes() {
+    let square = [
+        (0.0, 0.0),
+        (10.0, 0.0),
+        (10.0, 10.0),
+        (0.0, 10.0),
+        (0.0, 0.0),
+    ];
+    let unioned = union(buffer(&square, 1.0, Join::Miter(4.0), Cap::Butt));
+    assert_eq!(1, unioned.len());
+    assert_eq!(2, unioned[0].len());
+    assert!((polygon_area(&unioned[0]) - (144.0 - 64.0)).abs() < 1e-6);
+}
+
+#[test]
+fn difference_cuts_out_islands() {
+    let lake = vec![
+        (0.0, 0.0),
+        (10.0, 0.0),
+        (10.0, 10.0),
+        (0.0, 10.0),
+        (0.0, 0.0),
+    ];
+    let island = vec![(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0), (4.0, 4.0)];
+    let result = difference(vec![lake], vec![island]);
+    assert_eq!(1, result.len());
+    assert_eq!(2, result[0].len());
+    assert!((polygon_area(&result[0]) - 96.0).abs() < 1e-6);
+}
+
+// Small coordinates so that generated lines often share points.
+#[cfg(test)]
+fn arb_point() -> impl Strategy<Value = Point> {
+    (-3i8..3, -3i8..3).prop_map(|(x, y)| Point::new(x as f64, y as f64))
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn dedup_keeps_one_copy_of_each_edge(
+        lines in proptest::collection::vec(proptest::collection::vec(arb_point(), 0..5), 0..12)
+    ) {
+        let mut deduped = lines.clone();
+        dedup(&mut deduped);
+
+        // Every step of every line is still drawn, and drawn once.
+        let steps = |lines: &[Vec<Point>]| {
+            let mut steps: Vec<_> = lines
+                .iter()
+                .flat_map(|line| line.windows(2))
+                .filter(|pair| pair[0] != pair[1])
+                .map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1])))
+                .collect();
+            steps.sort();
+            steps
+        };
+        let mut expected = steps(&lines);
+        expected.dedup();
+        prop_assert_eq!(expected, steps(&deduped));
+        prop_assert!(deduped.iter().all(|line| line.len() > 1));
+
+        let mut again = deduped.clone();
+        dedup(&mut again);
+        prop_assert_eq!(deduped, again);
+    }
+
+    #[test]
+    fn connect_rejoins_a_split_line(
+        steps in proptest::collection::vec((1i32..4, -2i32..3), 2..20),
+        cuts in proptest::collection::vec(any::<proptest::sample::Index>(), 0..5),
+        order in any::<u64>()
+    ) {
+        // A line that always moves right can't run into itself, so there is
+        // only one way to put it back together.
+        let mut line = vec![Point::new(0.0, 0.0)];
+        for (dx, dy) in steps {
+            let last = line[line.len() - 1];
+            line.push(Point::new(last.x() + dx as f64, last.y() + dy as f64));
+        }
+        let mut cuts: Vec<usize> = cuts.iter().map(|cut| 1 + cut.index(line.len() - 2)).collect();
+        cuts.sort();
+        cuts.dedup();
+
+        // Neighbouring pieces share the point at each cut.
+        let mut pieces = vec![];
+        let mut start = 0;
+        for &cut in cuts.iter().chain(Some(&(line.len() - 1))) {
+            pieces.push(line[start..=cut].to_vec());
+            start = cut;
+        }
+        let n = pieces.len();
+        pieces.rotate_left(order as usize % n);
+        if order & 1 == 1 {
+            pieces.reverse();
+        }
+
+        connect(&mut pieces);
+        prop_assert_eq!(vec![line], pieces);
+    }
+
+    #[test]
+    fn connect_only_drops_junctions(
+        lines in proptest::collection::vec(proptest::collection::vec(arb_point(), 1..4), 0..8)
+    ) {
+        let before: usize = lines.iter().map(Vec::len).sum();
+        let mut connected = lines.clone();
+        connect(&mut connected);
+        let after: usize = connected.iter().map(Vec::len).sum();
+        let joins = lines.len() - connected.len();
+        prop_assert_eq!(before - joins, after);
+        prop_assert!(connected.iter().all(|line| !line.is_empty()));
+    }
+
+    #[test]
+    fn point_order_agrees_with_equality(
+        a in (-2i8..2, -2i8..2), b in (-2i8..2, -2i8..2), negate in any::<bool>()
+    ) {
+        let sign = if negate { -1.0 } else { 1.0 };
+        let p = Point::new(sign * a.0 as f64, sign * a.1 as f64);
+        let q = Point::new(b.0 as f64, b.1 as f64);
+        prop_assert_eq!(p == q, p.cmp(&q) == std::cmp::Ordering::Equal);
+        prop_assert_eq!(p.cmp(&q), q.cmp(&p).reverse());
+    }
+}
+
+#[test]
+fn nan_is_rejected() {
+    assert!(Point::try_new(f64::NAN, 0.0).is_none());
+    assert!(Point::try_new(0.0, f64::NAN).is_none());
+    assert_eq!(Point::new(0.0, 0.0), Point::new(-0.0, -0.0));
+}
@@ -1,98 +1,435 @@
+extern crate aabb_quadtree;
+extern crate bincode;
+extern crate euclid;
+#[cfg(feature = "profiling")]
 extern crate flame;
+extern crate i_overlay;
 extern crate osm_xml;
 extern crate proj5;
+#[cfg(test)]
+extern crate proptest;
 extern crate serde;
 extern crate serde_json;
+extern crate xml;
 
+#[cfg(feature = "profiling")]
 #[macro_use]
 extern crate flamer;
 mod linemath;
 
+mod batch;
+mod buildings;
+mod cache;
+mod clip;
+mod crs;
+mod export;
+mod furniture;
 mod geojson;
+mod gpx;
+mod grid;
+mod html;
+mod inspect;
+mod network;
 mod osm_load;
+mod roads;
+mod routing;
+mod rules;
+mod shapefile;
 mod svg_exporter;
+mod terrain;
 mod theme;
+mod timing;
+mod transit;
+mod watch;
+mod water;
 
+use linemath::Point;
 use osm_load::*;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::io::Write;
 use svg_exporter::*;
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 enum Kind {
-    Building(RangeIdx),
-    Road(RangeIdx),
+    // A building and the tags saying how tall it is.
+    Building(RangeIdx, buildings::Building),
+    Road(RangeIdx, roads::Profile, roads::Access),
     Coastline(RangeIdx),
     Park(RangeIdx),
-    ProcessedCoastline(Vec<(f64, f64)>),
-    ProcessedPark(Vec<(f64, f64)>),
-    Subway(Vec<(f64, f64)>),
+    Water(RangeIdx),
+    Wetland(RangeIdx),
+    // Waterway centerline and its width in metres.
+    Waterway(RangeIdx, f64),
+    // Landuse or landcover area and the layer it belongs on.
+    Landuse(RangeIdx, Layer),
+    Rail(RangeIdx),
+    LightRail(RangeIdx),
+    SubwayTrack(RangeIdx),
+    // A track belonging to a transit route relation.
+    RouteMember(RangeIdx, transit::Route),
+    Station(RangeIdx),
+    ProcessedCoastline(Vec<Point>),
+    ProcessedPark(Vec<Point>),
+    // Unioned road surface: an outer ring followed by its holes.
+    ProcessedRoad(Vec<Vec<Point>>),
+    // Building and road surfaces clipped to the inside of parks.
+    ParkBuilding(Vec<Vec<Point>>),
+    ParkPath(Vec<Vec<Point>>),
+    // Unclosed building ways (usually relation members) clipped to the inside of parks.
+    ParkBuildingEdge(Vec<Point>),
+    // Water areas, wetlands and waterway surfaces with islands cut out.
+    ProcessedWater(Vec<Vec<Point>>),
+    ProcessedWetland(Vec<Vec<Point>>),
+    ProcessedWaterway(Vec<Vec<Point>>),
+    ProcessedLanduse(Vec<Vec<Point>>, Layer),
+    // The connected tracks of one transit line.
+    ProcessedRoute(Vec<Point>, transit::Route),
+    Subway(Vec<Point>),
+    // Areas, lines and points from Shapefile overlays, with the inline style
+    // their attributes give them.
+    OverlayArea(Vec<Vec<Point>>, String),
+    OverlayLine(Vec<Point>, String),
+    OverlayPoint(Point, String),
+    // GPX tracks and routes, track runs coloured by `--colour-by` (with their
+    // band of the colour ramp), and waypoints.
+    GpxLine(Vec<Point>, Layer),
+    GpxBand(Vec<Point>, usize),
+    GpxWaypoint(Point),
+    // Contour lines (on the contour or index contour layer), index contour
+    // labels with their angle, and a hillshade image, from `--terrain`.
+    Contour(Vec<Point>, Layer),
+    ContourLabel(Point, f64, String),
+    Hillshade(String),
+    // Graticule or metric grid lines, and the labels where they meet the
+    // frame, from `--graticule` or `--grid`.
+    GridLine(Vec<Point>),
+    GridLabel(Point, String),
+    // A route planned with `--route`, and its start and end markers.
+    Itinerary(Vec<Point>),
+    ItineraryMarker(Point, Layer),
+    // An element whose shape was merged with others in processing, on the
+    // layer it was merged into.  It's drawn invisibly, to keep its OSM id and
+    // tags on the map.
+    Element(RangeIdx, Layer),
 }
 
-#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Layer {
     Building,
+    // Shadows cast by buildings, and the class of their walls in the oblique
+    // view (which are drawn on the building layer, in among the roofs).
+    BuildingShadow,
+    BuildingWall,
+    // The classes of elements drawn only to carry their OSM ids and tags.
+    ElementArea,
+    ElementLine,
     Road,
     Coastline,
     Park,
     ParkPath,
     ParkBuilding,
+    Water,
+    Waterway,
+    Wetland,
+    Residential,
+    Commercial,
+    Industrial,
+    Railway,
+    Farmland,
+    Cemetery,
+    Forest,
+    Scrub,
+    Sand,
+    Beach,
+    Rail,
+    // Cross-ties drawn over heavy rail.
+    RailHatching,
+    LightRail,
     Subway,
+    TransitRoute,
+    Station,
+    Hillshade,
+    Contour,
+    IndexContour,
+    ContourLabel,
+    OverlayArea,
+    OverlayLine,
+    OverlayPoint,
+    GpxRoute,
+    GpxTrack,
+    GpxWaypoint,
+    Itinerary,
+    ItineraryStart,
+    ItineraryEnd,
+    Grid,
+    GridLabel,
+    // Classes for map furniture: its text and symbols, and the panels behind.
+    Furniture,
+    FurniturePanel,
 }
 
+// Landuse and landcover layers, bottom first.  They all sit under parks, water,
+// roads and buildings.
+const LANDUSE_ORDER: &[Layer] = &[
+    Layer::Farmland,
+    Layer::Residential,
+    Layer::Commercial,
+    Layer::Industrial,
+    Layer::Railway,
+    Layer::Cemetery,
+    Layer::Forest,
+    Layer::Scrub,
+    Layer::Sand,
+    Layer::Beach,
+];
+
 impl Kind {
     fn to_layer(&self) -> Layer {
         match self {
-            Kind::Building(_) => Layer::Building,
-            Kind::Road(_) => Layer::Road,
+            Kind::Building(..) => Layer::Building,
+            Kind::Road(..) | Kind::ProcessedRoad(_) => Layer::Road,
             Kind::Coastline(_) => Layer::Coastline,
             Kind::ProcessedCoastline(_) => Layer::Coastline,
             Kind::Park(_) => Layer::Park,
             Kind::ProcessedPark(_) => Layer::Park,
-            Kind::Subway(_) => Layer::Subway,
+            Kind::ParkBuilding(_) | Kind::ParkBuildingEdge(_) => Layer::ParkBuilding,
+            Kind::ParkPath(_) => Layer::ParkPath,
+            Kind::Water(_) | Kind::ProcessedWater(_) => Layer::Water,
+            Kind::Wetland(_) | Kind::ProcessedWetland(_) => Layer::Wetland,
+            Kind::Waterway(..) | Kind::ProcessedWaterway(_) => Layer::Waterway,
+            Kind::Landuse(_, layer) | Kind::ProcessedLanduse(_, layer) => *layer,
+            Kind::Rail(_) => Layer::Rail,
+            Kind::LightRail(_) => Layer::LightRail,
+            Kind::SubwayTrack(_) | Kind::Subway(_) => Layer::Subway,
+            Kind::RouteMember(..) | Kind::ProcessedRoute(..) => Layer::TransitRoute,
+            Kind::Station(_) => Layer::Station,
+            Kind::OverlayArea(..) => Layer::OverlayArea,
+            Kind::OverlayLine(..) => Layer::OverlayLine,
+            Kind::OverlayPoint(..) => Layer::OverlayPoint,
+            Kind::GpxLine(_, layer) => *layer,
+            Kind::GpxBand(..) => Layer::GpxTrack,
+            Kind::GpxWaypoint(_) => Layer::GpxWaypoint,
+            Kind::Contour(_, layer) => *layer,
+            Kind::ContourLabel(..) => Layer::ContourLabel,
+            Kind::Hillshade(_) => Layer::Hillshade,
+            Kind::GridLine(_) => Layer::Grid,
+            Kind::GridLabel(..) => Layer::GridLabel,
+            Kind::Itinerary(_) => Layer::Itinerary,
+            Kind::ItineraryMarker(_, layer) => *layer,
+            Kind::Element(_, layer) => *layer,
         }
     }
-    fn resolve_coords<'a>(&'a self, geom: &'a Geometry) -> &'a [(f64, f64)] {
+    // Kinds still drawn from the OSM element they were classified from.
+    fn range(&self) -> Option<RangeIdx> {
         match self {
-            Kind::Building(r) | Kind::Road(r) | Kind::Coastline(r) | Kind::Park(r) => {
-                geom.resolve_coords(*r)
-            }
+            Kind::Building(r, _)
+            | Kind::Road(r, ..)
+            | Kind::Coastline(r)
+            | Kind::Park(r)
+            | Kind::Water(r)
+            | Kind::Wetland(r)
+            | Kind::Waterway(r, _)
+            | Kind::Landuse(r, _)
+            | Kind::Rail(r)
+            | Kind::LightRail(r)
+            | Kind::SubwayTrack(r)
+            | Kind::RouteMember(r, _)
+            | Kind::Station(r)
+            | Kind::Element(r, _) => Some(*r),
+            _ => None,
+        }
+    }
+    // Processing merges these kinds' shapes, losing the elements they came
+    // from.
+    fn to_element(&self) -> Option<Kind> {
+        match self {
+            Kind::Road(r, ..)
+            | Kind::Coastline(r)
+            | Kind::Park(r)
+            | Kind::Water(r)
+            | Kind::Wetland(r)
+            | Kind::Waterway(r, _)
+            | Kind::Landuse(r, _)
+            | Kind::RouteMember(r, _) => Some(Kind::Element(*r, self.to_layer())),
+            _ => None,
+        }
+    }
+    // Kinds which have been turned into polygons with holes.
+    fn polygon(&self) -> Option<&[Vec<Point>]> {
+        match self {
+            Kind::ProcessedRoad(rings)
+            | Kind::ParkBuilding(rings)
+            | Kind::ParkPath(rings)
+            | Kind::ProcessedWater(rings)
+            | Kind::ProcessedWetland(rings)
+            | Kind::ProcessedWaterway(rings)
+            | Kind::ProcessedLanduse(rings, _)
+            | Kind::OverlayArea(rings, _) => Some(rings),
+            _ => None,
+        }
+    }
+    fn resolve_coords<'a>(&'a self, geom: &'a Geometry) -> &'a [Point] {
+        match self {
+            Kind::Building(r, _)
+            | Kind::Road(r, ..)
+            | Kind::Coastline(r)
+            | Kind::Park(r)
+            | Kind::Water(r)
+            | Kind::Wetland(r)
+            | Kind::Waterway(r, _)
+            | Kind::Landuse(r, _)
+            | Kind::Rail(r)
+            | Kind::LightRail(r)
+            | Kind::SubwayTrack(r)
+            | Kind::RouteMember(r, _)
+            | Kind::Station(r)
+            | Kind::Element(r, _) => geom.resolve_coords(*r),
+            Kind::ProcessedRoad(rings)
+            | Kind::ParkBuilding(rings)
+            | Kind::ParkPath(rings)
+            | Kind::ProcessedWater(rings)
+            | Kind::ProcessedWetland(rings)
+            | Kind::ProcessedWaterway(rings)
+            | Kind::ProcessedLanduse(rings, _)
+            | Kind::OverlayArea(rings, _) => &rings[0][..],
             Kind::ProcessedCoastline(v) => &v[..],
             Kind::ProcessedPark(v) => &v[..],
+            Kind::ParkBuildingEdge(v) => &v[..],
+            Kind::GpxLine(v, _) | Kind::GpxBand(v, _) => &v[..],
+            Kind::OverlayLine(v, _) => &v[..],
+            Kind::OverlayPoint(point, _) => std::slice::from_ref(point),
+            Kind::GpxWaypoint(point) => std::slice::from_ref(point),
+            Kind::Contour(v, _) => &v[..],
+            Kind::ContourLabel(point, ..) => std::slice::from_ref(point),
+            Kind::Hillshade(_) => &[],
+            Kind::GridLine(v) => &v[..],
+            Kind::GridLabel(point, _) => std::slice::from_ref(point),
+            Kind::Itinerary(v) => &v[..],
+            Kind::ItineraryMarker(point, _) => std::slice::from_ref(point),
+            Kind::ProcessedRoute(v, _) => &v[..],
             Kind::Subway(v) => &v[..],
         }
     }
 }
 
-fn filter(relationship_tags: &[Tag], way_tags: &[Tag], range: RangeIdx) -> Option<Kind> {
-    type T = fn(RangeIdx) -> Kind;
-    osm_load::simple_filterer(|tag| match tag {
-        ("highway", _) => Some(Kind::Road as T),
-        ("building", _) => Some(Kind::Building as T),
-        (_, "coastline") => Some(Kind::Coastline as T),
-        (_, "park") => Some(Kind::Park as T),
-        (_, "garden") => Some(Kind::Park as T),
-        (_, "grass") => Some(Kind::Park as T),
-        (_, "memorial") => Some(Kind::Park as T),
-        _ => None,
-    })(relationship_tags, way_tags, range)
+// Height of the rendered map in SVG units.
+const TARGET_HEIGHT: f64 = 1000.0;
+
+const DEFAULT_RULES: &str = include_str!("../rules/default.rules");
+
+// Builds the Kind for a feature which a rule put on some layer.  Some layers
+// need more from the tags than the rule checked, so this can still reject it.
+type KindFor = fn(&Feature, RangeIdx) -> Option<Kind>;
+
+fn relation_tags<'a>(feature: &Feature<'a>) -> &'a [Tag] {
+    feature.relation.map_or(&[][..], |(_, tags)| tags)
+}
+
+// The layers which rules may assign features to, or None for any other name.
+fn rule_layer(layer: &str) -> Option<KindFor> {
+    let kind_for: KindFor = match layer {
+        "road" => |feature, range| {
+            let tags = if roads::profile(feature.tags).is_some() {
+                feature.tags
+            } else {
+                relation_tags(feature)
+            };
+            roads::profile(tags).map(|profile| Kind::Road(range, profile, roads::access(tags)))
+        },
+        "building" => {
+            |feature, range| Some(Kind::Building(range, buildings::building(feature.tags)))
+        }
+        "coastline" => |_, range| Some(Kind::Coastline(range)),
+        "park" => |_, range| Some(Kind::Park(range)),
+        "water" => |_, range| Some(Kind::Water(range)),
+        "wetland" => |_, range| Some(Kind::Wetland(range)),
+        "waterway" => |feature, range| {
+            water::waterway_width(feature.tags)
+                .or_else(|| water::waterway_width(relation_tags(feature)))
+                .map(|width| Kind::Waterway(range, width))
+        },
+        "residential" => |_, range| Some(Kind::Landuse(range, Layer::Residential)),
+        "commercial" => |_, range| Some(Kind::Landuse(range, Layer::Commercial)),
+        "industrial" => |_, range| Some(Kind::Landuse(range, Layer::Industrial)),
+        "railway" => |_, range| Some(Kind::Landuse(range, Layer::Railway)),
+        "farmland" => |_, range| Some(Kind::Landuse(range, Layer::Farmland)),
+        "cemetery" => |_, range| Some(Kind::Landuse(range, Layer::Cemetery)),
+        "forest" => |_, range| Some(Kind::Landuse(range, Layer::Forest)),
+        "scrub" => |_, range| Some(Kind::Landuse(range, Layer::Scrub)),
+        "sand" => |_, range| Some(Kind::Landuse(range, Layer::Sand)),
+        "beach" => |_, range| Some(Kind::Landuse(range, Layer::Beach)),
+        "rail" => |_, range| Some(Kind::Rail(range)),
+        "light-rail" => |_, range| Some(Kind::LightRail(range)),
+        "subway" => |_, range| Some(Kind::SubwayTrack(range)),
+        "transit-route" => |feature, range| {
+            if !transit::is_track(feature.tags) {
+                return None;
+            }
+            transit::route(relation_tags(feature)).map(|route| Kind::RouteMember(range, route))
+        },
+        "station" => |feature, range| {
+            if feature.element != ElementType::Node {
+                return None;
+            }
+            Some(Kind::Station(range))
+        },
+        _ => return None,
+    };
+    Some(kind_for)
+}
+
+fn kind_for(layer: &str, feature: &Feature, range: RangeIdx) -> Option<Kind> {
+    rule_layer(layer)?(feature, range)
+}
+
+// The parsed rules and their source text.
+fn load_rules(path: Option<&str>) -> std::io::Result<(rules::RuleSet, String)> {
+    let (name, text) = match path {
+        Some(path) => (path, std::fs::read_to_string(path)?),
+        None => ("default.rules", DEFAULT_RULES.to_string()),
+    };
+    let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let rules = rules::RuleSet::parse(name, &text).map_err(invalid)?;
+    for rule in rules.rules() {
+        if rule_layer(&rule.layer).is_none() {
+            return Err(invalid(format!(
+                "{}: unknown layer `{}`",
+                rule.name, rule.layer
+            )));
+        }
+    }
+    Ok((rules, text))
 }
 
-#[flame]
+// One line of `--explain` output: which rule claimed a feature.
+fn explain(feature: &Feature, rule: &rules::Rule, kept: bool) -> String {
+    let mut line = format!("{:?} {}", feature.element, feature.id).to_lowercase();
+    if let Some((id, _)) = feature.relation {
+        line += &format!(" (relation {})", id);
+    }
+    line += &format!("\t{}\t{} priority {}", rule.layer, rule.name, rule.priority);
+    if !kept {
+        line += "\tdropped";
+    }
+    line
+}
+
+#[cfg_attr(feature = "profiling", flame)]
 fn process_coastline_and_parks(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
     let mut coastlines: Vec<Vec<_>> = vec![];
     let mut disconnected_parks: Vec<Vec<_>> = vec![];
     let mut acc = vec![];
     for kind in results {
         match kind {
-            Kind::Coastline(idx) => {
-                coastlines.push(geometry.resolve_coords(idx).into_iter().cloned().collect())
-            }
+            Kind::Coastline(idx) => coastlines.push(geometry.resolve_coords(idx).to_vec()),
             Kind::Park(idx) => {
                 let geometry = geometry.resolve_coords(idx);
                 if geometry[0] == geometry[geometry.len() - 1] {
                     acc.push(Kind::Park(idx));
                 } else {
-                    let geometry = geometry.into_iter().cloned().collect();
-                    disconnected_parks.push(geometry);
+                    disconnected_parks.push(geometry.to_vec());
                 }
             }
             other => acc.push(other),
@@ -100,8 +437,8 @@ fn process_coastline_and_parks(results: Vec<Kind>, geometry: &Geometry) -> Vec<K
     }
 
     // Coastlines
-    let coastlines = linemath::dedup(coastlines);
-    let mut coastlines = linemath::connect(coastlines);
+    linemath::dedup(&mut coastlines);
+    linemath::connect(&mut coastlines);
     for coastline in coastlines.iter_mut() {
         linemath::equalize(coastline);
     }
@@ -111,8 +448,8 @@ fn process_coastline_and_parks(results: Vec<Kind>, geometry: &Geometry) -> Vec<K
     }
 
     // Parks
-    let disconnected_parks = linemath::dedup(disconnected_parks);
-    let mut disconnected_parks = linemath::connect(disconnected_parks);
+    linemath::dedup(&mut disconnected_parks);
+    linemath::connect(&mut disconnected_parks);
     for park in disconnected_parks.iter_mut() {
         linemath::equalize(park);
     }
@@ -124,77 +461,1148 @@ fn process_coastline_and_parks(results: Vec<Kind>, geometry: &Geometry) -> Vec<K
     acc
 }
 
-#[flame]
-fn process_subways(
-    results: Vec<Kind>,
-    _geometry: &Geometry,
-    subways: geojson::Geojson,
-) -> Vec<Kind> {
-    let mut acc = results.clone();
+#[cfg_attr(feature = "profiling", flame)]
+fn process_roads(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
+    let units_per_metre = geometry.bounds.units_per_metre();
+    let mut roads = vec![];
+    let mut acc = vec![];
+    for kind in results {
+        match kind {
+            Kind::Road(idx, profile, _) => roads.push((idx, profile)),
+            other => acc.push(other),
+        }
+    }
+
+    let network = network::Network::build(geometry, roads);
+    let mut pieces = vec![];
+    for edge in &network.edges {
+        let profile = edge.data;
+        pieces.extend(linemath::buffer(
+            &edge.points,
+            profile.width * units_per_metre / 2.0,
+            profile.join,
+            profile.cap,
+        ));
+    }
+    // Where square-ended roads meet, fill the corner between them with a disc
+    // as wide as the narrower of the two widest roads, so that a through road
+    // stays whole but a side road doesn't bulge into it.
+    let mut junctions: Vec<Vec<roads::Profile>> = vec![vec![]; network.nodes.len()];
+    for edge in &network.edges {
+        junctions[edge.from].push(edge.data);
+        junctions[edge.to].push(edge.data);
+    }
+    for (node, mut profiles) in network.nodes.iter().zip(junctions) {
+        if profiles.len() < 2 || profiles.iter().all(|p| p.cap == linemath::Cap::Round) {
+            continue;
+        }
+        profiles.sort_by(|a, b| b.width.total_cmp(&a.width));
+        pieces.extend(linemath::buffer(
+            &[node.point],
+            profiles[1].width * units_per_metre / 2.0,
+            linemath::Join::Round,
+            linemath::Cap::Round,
+        ));
+    }
+
+    for polygon in linemath::union(pieces) {
+        acc.push(Kind::ProcessedRoad(polygon));
+    }
+
+    acc
+}
+
+// Joins the outer and inner members of multipolygons into rings and cuts the
+// inner rings out of the outer ones.
+fn assemble_areas(outers: Vec<Vec<Point>>, inners: Vec<Vec<Point>>) -> Vec<Vec<Vec<Point>>> {
+    let close = |fragments: Vec<Vec<Point>>| {
+        let (mut closed, mut open): (Vec<_>, Vec<_>) = fragments
+            .into_iter()
+            .filter(|ring| ring.len() > 1)
+            .partition(|ring| ring[0] == ring[ring.len() - 1]);
+        linemath::dedup(&mut open);
+        linemath::connect(&mut open);
+        for mut ring in open {
+            // Rings cut off by the edge of the extract are closed with a straight line.
+            let first = ring[0];
+            ring.push(first);
+            closed.push(ring);
+        }
+        closed
+    };
+    linemath::difference(close(outers), close(inners))
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn process_water(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
+    let units_per_metre = geometry.bounds.units_per_metre();
+    let (mut water, mut water_holes) = (vec![], vec![]);
+    let (mut wetland, mut wetland_holes) = (vec![], vec![]);
+    let mut waterways = vec![];
+    let mut acc = vec![];
+    for kind in results {
+        match kind {
+            Kind::Water(idx) if geometry.is_inner(idx) => {
+                water_holes.push(geometry.resolve_coords(idx).to_vec())
+            }
+            Kind::Water(idx) => water.push(geometry.resolve_coords(idx).to_vec()),
+            Kind::Wetland(idx) if geometry.is_inner(idx) => {
+                wetland_holes.push(geometry.resolve_coords(idx).to_vec())
+            }
+            Kind::Wetland(idx) => wetland.push(geometry.resolve_coords(idx).to_vec()),
+            Kind::Waterway(idx, width) => waterways.extend(linemath::buffer(
+                geometry.resolve_coords(idx),
+                width * units_per_metre / 2.0,
+                linemath::Join::Round,
+                linemath::Cap::Round,
+            )),
+            other => acc.push(other),
+        }
+    }
+
+    for polygon in assemble_areas(water, water_holes) {
+        acc.push(Kind::ProcessedWater(polygon));
+    }
+    for polygon in assemble_areas(wetland, wetland_holes) {
+        acc.push(Kind::ProcessedWetland(polygon));
+    }
+    for polygon in linemath::union(waterways) {
+        acc.push(Kind::ProcessedWaterway(polygon));
+    }
+
+    acc
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn process_landuse(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
+    let mut areas: HashMap<Layer, (Vec<_>, Vec<_>)> = HashMap::new();
+    let mut acc = vec![];
+    for kind in results {
+        match kind {
+            Kind::Landuse(idx, layer) => {
+                let (outers, inners) = areas.entry(layer).or_default();
+                let coords = geometry.resolve_coords(idx).to_vec();
+                if geometry.is_inner(idx) {
+                    inners.push(coords);
+                } else {
+                    outers.push(coords);
+                }
+            }
+            other => acc.push(other),
+        }
+    }
+
+    for (layer, (outers, inners)) in areas {
+        for polygon in assemble_areas(outers, inners) {
+            acc.push(Kind::ProcessedLanduse(polygon, layer));
+        }
+    }
+
+    acc
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn process_park_clippings(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
+    let parks = results
+        .iter()
+        .filter(|kind| kind.to_layer() == Layer::Park)
+        .map(|kind| kind.resolve_coords(geometry).to_vec())
+        .collect();
+    let clipper = clip::Clipper::new(parks);
+
+    let mut acc = vec![];
+    for kind in &results {
+        match kind {
+            Kind::Building(..) => {
+                let building = kind.resolve_coords(geometry);
+                if building.len() > 2 && building[0] == building[building.len() - 1] {
+                    for clipped in clipper.clip_polygon(&[building.to_vec()]) {
+                        acc.push(Kind::ParkBuilding(clipped));
+                    }
+                } else {
+                    for clipped in clipper.clip_polyline(building) {
+                        acc.push(Kind::ParkBuildingEdge(clipped));
+                    }
+                }
+            }
+            Kind::ProcessedRoad(rings) => {
+                for clipped in clipper.clip_polygon(rings) {
+                    acc.push(Kind::ParkPath(clipped));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut results = results;
+    results.append(&mut acc);
+    results
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn process_transit(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
+    let mut lines: HashMap<transit::Route, Vec<Vec<_>>> = HashMap::new();
+    let mut acc = vec![];
+    for kind in results {
+        match kind {
+            Kind::RouteMember(idx, route) => lines
+                .entry(route)
+                .or_default()
+                .push(geometry.resolve_coords(idx).to_vec()),
+            other => acc.push(other),
+        }
+    }
+
+    for (route, mut tracks) in lines {
+        linemath::dedup(&mut tracks);
+        linemath::connect(&mut tracks);
+        for track in tracks {
+            acc.push(Kind::ProcessedRoute(track, route.clone()));
+        }
+    }
+
+    acc
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn process_subways(subways: geojson::Geojson) -> Vec<Kind> {
+    let mut acc = vec![];
 
     for feature in subways.features {
-        match feature.geometry {
+        if let geojson::Geometry::LineString { coordinates } = feature.geometry {
+            let lon_lat = coordinates
+                .iter()
+                .map(|coordinate| (coordinate[0], coordinate[1]))
+                .filter(|&lon_lat| osm_load::is_projectable(lon_lat))
+                .collect();
+            acc.push(Kind::Subway(osm_load::project(lon_lat)))
+        }
+    }
+
+    acc
+}
+
+// Projects any features (say, from a Shapefile) onto the overlay layers,
+// styled by their simplestyle properties.
+fn process_features(features: geojson::Geojson) -> Vec<Kind> {
+    let project = |coordinates: &[geojson::Coordinate]| {
+        let lon_lat = coordinates
+            .iter()
+            .map(|coordinate| (coordinate[0], coordinate[1]))
+            .filter(|&lon_lat| osm_load::is_projectable(lon_lat))
+            .collect();
+        osm_load::project(lon_lat)
+    };
+    let area = |rings: &[Vec<geojson::Coordinate>], style: &str| {
+        let rings: Vec<_> = rings.iter().map(|ring| project(ring)).collect();
+        Kind::OverlayArea(rings, style.into())
+    };
+
+    let mut acc = vec![];
+    for feature in features.features {
+        let style = geojson::simplestyle(&feature.properties);
+        match &feature.geometry {
+            geojson::Geometry::Point { coordinates } => acc.extend(
+                project(&[*coordinates])
+                    .into_iter()
+                    .map(|point| Kind::OverlayPoint(point, style.clone())),
+            ),
+            geojson::Geometry::MultiPoint { coordinates } => acc.extend(
+                project(coordinates)
+                    .into_iter()
+                    .map(|point| Kind::OverlayPoint(point, style.clone())),
+            ),
             geojson::Geometry::LineString { coordinates } => {
-                let as_tuple = coordinates
-                    .iter()
-                    .map(|coordinate| (coordinate[0], coordinate[1]))
-                    .collect();
-                let converted = osm_load::coord_convert(as_tuple);
-                acc.push(Kind::Subway(converted))
+                acc.push(Kind::OverlayLine(project(coordinates), style))
             }
-            _ => (),
+            geojson::Geometry::MultiLineString { coordinates } => {
+                for line in coordinates {
+                    acc.push(Kind::OverlayLine(project(line), style.clone()));
+                }
+            }
+            geojson::Geometry::Polygon { coordinates } => acc.push(area(coordinates, &style)),
+            geojson::Geometry::MultiPolygon { coordinates } => {
+                for polygon in coordinates {
+                    acc.push(area(polygon, &style));
+                }
+            }
+        }
+    }
+    acc
+}
+
+// Plans a route through `waypoints` (`lon,lat lon,lat ...`) over the loaded
+// roads, for `--route`.
+fn plan_route(
+    results: &[Kind],
+    geometry: &Geometry,
+    waypoints: &str,
+    mode: roads::Mode,
+) -> std::io::Result<Vec<Kind>> {
+    let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let waypoints = routing::parse_waypoints(waypoints).map_err(invalid)?;
+    if let Some(&waypoint) = waypoints.iter().find(|&&w| !osm_load::is_projectable(w)) {
+        return Err(invalid(format!("waypoint {:?} is off the map", waypoint)));
+    }
+    let roads = results
+        .iter()
+        .filter_map(|kind| match kind {
+            Kind::Road(idx, _, access) => Some((*idx, *access)),
+            _ => None,
+        })
+        .collect();
+    let network = network::Network::build(geometry, roads);
+    let units_per_metre = geometry.bounds.units_per_metre();
+    let (line, metres) = routing::route(
+        &network,
+        &osm_load::project(waypoints),
+        mode,
+        units_per_metre,
+    )
+    .map_err(invalid)?;
+    println!("route: {:.2} km", metres / 1000.0);
+
+    let (start, end) = (line[0], line[line.len() - 1]);
+    Ok(vec![
+        Kind::Itinerary(line),
+        Kind::ItineraryMarker(start, Layer::ItineraryStart),
+        Kind::ItineraryMarker(end, Layer::ItineraryEnd),
+    ])
+}
+
+// Index contours shorter than this (in map units) aren't labelled.
+const MIN_LABELLED_CONTOUR: f64 = 100.0;
+
+// A label for `line` halfway along it, with the angle of the line there kept
+// within 90° of upright, if the line is long enough and its middle is on the
+// map.
+fn contour_label(line: &[Point], bounds: &osm_load::Bounds) -> Option<(Point, f64)> {
+    let screen: Vec<Point> = line
+        .iter()
+        .map(|&point| bounds.transform_lat_lon_to_screen_coordinate(point))
+        .collect();
+    let step = |pair: &[Point]| (pair[1].x() - pair[0].x()).hypot(pair[1].y() - pair[0].y());
+    let length: f64 = screen.windows(2).map(step).sum();
+    if length < MIN_LABELLED_CONTOUR {
+        return None;
+    }
+    let mut along = length / 2.0;
+    for (i, pair) in screen.windows(2).enumerate() {
+        let len = step(pair);
+        if along <= len && len > 0.0 {
+            let t = along / len;
+            let x = pair[0].x() + t * (pair[1].x() - pair[0].x());
+            let y = pair[0].y() + t * (pair[1].y() - pair[0].y());
+            if x < 0.0 || x > bounds.width || y < 0.0 || y > bounds.height {
+                return None;
+            }
+            let point = Point::new(
+                line[i].x() + t * (line[i + 1].x() - line[i].x()),
+                line[i].y() + t * (line[i + 1].y() - line[i].y()),
+            );
+            let mut angle = (pair[1].y() - pair[0].y())
+                .atan2(pair[1].x() - pair[0].x())
+                .to_degrees();
+            if angle > 90.0 {
+                angle -= 180.0;
+            } else if angle <= -90.0 {
+                angle += 180.0;
+            }
+            return Some((point, angle));
+        }
+        along -= len;
+    }
+    None
+}
+
+// Contours, their labels and optionally a hillshade for the map, from the
+// `.hgt` tiles in `options.dir`.
+fn process_terrain(
+    geometry: &Geometry,
+    options: &terrain::TerrainOptions,
+) -> std::io::Result<Vec<Kind>> {
+    let bounds = &geometry.bounds;
+    let (west, south) = osm_load::unproject(Point::new(bounds.min_lon, bounds.min_lat));
+    let (east, north) = osm_load::unproject(Point::new(bounds.max_lon, bounds.max_lat));
+    let dem = terrain::Dem::load(&options.dir, (west, south, east, north))?;
+
+    let mut acc = vec![];
+    let contours = dem.contours(options.interval);
+    println!("{} contours", contours.len());
+    let lines = contours
+        .iter()
+        .map(|contour| {
+            let points = contour.points.iter().cloned();
+            points.filter(|&p| osm_load::is_projectable(p)).collect()
+        })
+        .collect();
+    let lines = osm_load::project_lines(lines);
+    for (contour, line) in contours.into_iter().zip(lines) {
+        if !contour.index {
+            acc.push(Kind::Contour(line, Layer::Contour));
+            continue;
         }
+        if let Some((point, angle)) = contour_label(&line, bounds) {
+            let text = format!("{:.0}", contour.height);
+            acc.push(Kind::ContourLabel(point, angle, text));
+        }
+        acc.push(Kind::Contour(line, Layer::IndexContour));
+    }
+    if options.hillshade {
+        acc.push(Kind::Hillshade(dem.hillshade(bounds)));
+    }
+    Ok(acc)
+}
+
+// Projects GPX tracks, routes and waypoints, splitting tracks into runs of one
+// colour when `colour_by` is given.
+fn process_gpx(mut gpx: gpx::Gpx, colour_by: Option<gpx::ColourBy>) -> Vec<Kind> {
+    let projectable = |point: &gpx::Waypoint| osm_load::is_projectable((point.lon, point.lat));
+    for line in gpx.tracks.iter_mut().chain(&mut gpx.routes) {
+        line.retain(projectable);
+    }
+    gpx.waypoints.retain(projectable);
+    // Everything is projected in one go: the waypoints, then each route, then
+    // each track.
+    let lines = std::iter::once(&gpx.waypoints)
+        .chain(&gpx.routes)
+        .chain(&gpx.tracks)
+        .map(|points| points.iter().map(|p| (p.lon, p.lat)).collect())
+        .collect();
+    let mut lines = osm_load::project_lines(lines).into_iter();
+
+    let mut acc = vec![];
+    for point in lines.next().unwrap_or_default() {
+        acc.push(Kind::GpxWaypoint(point));
+    }
+    for route in lines.by_ref().take(gpx.routes.len()) {
+        acc.push(Kind::GpxLine(route, Layer::GpxRoute));
     }
 
+    let colour_by = match colour_by {
+        Some(colour_by) => colour_by,
+        None => {
+            for track in lines {
+                acc.push(Kind::GpxLine(track, Layer::GpxTrack));
+            }
+            return acc;
+        }
+    };
+    let values: Vec<_> = gpx
+        .tracks
+        .iter()
+        .map(|track| gpx::step_values(track, colour_by))
+        .collect();
+    let known = values.iter().flatten().flatten();
+    let min = known.clone().cloned().fold(f64::INFINITY, f64::min);
+    let max = known.cloned().fold(f64::NEG_INFINITY, f64::max);
+    for (points, values) in lines.zip(values) {
+        let bands: Vec<_> = values
+            .into_iter()
+            .map(|value| value.map(|value| gpx::band(value, min, max)))
+            .collect();
+        // Steps without a value (no elevation or time) are drawn plain.
+        let mut start = 0;
+        for end in 1..=bands.len() {
+            if end < bands.len() && bands[end] == bands[start] {
+                continue;
+            }
+            let run = points[start..=end].to_vec();
+            acc.push(match bands[start] {
+                Some(band) => Kind::GpxBand(run, band),
+                None => Kind::GpxLine(run, Layer::GpxTrack),
+            });
+            start = end;
+        }
+    }
     acc
 }
 
-#[flame]
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    println!("{:?}", args);
+type Stage = fn(Vec<Kind>, &Geometry) -> Vec<Kind>;
 
-    let filename = &args[1].to_string();
-    let osm_file = format!("./data/osm/{}.osm", filename.as_str());
-    let (geometry, results) = Geometry::from_file(&osm_file, &filter, 1000.0);
-    let bounds = geometry.bounds;
-    let results = process_coastline_and_parks(results, &geometry);
+fn process_grid(geometry: &Geometry, grid: grid::Grid) -> Vec<Kind> {
+    let (lines, labels) = grid::grid(&geometry.bounds, grid);
+    let lines = lines.into_iter().map(|line| Kind::GridLine(line.points));
+    let labels = labels
+        .into_iter()
+        .map(|label| Kind::GridLabel(label.point, label.text));
+    lines.chain(labels).collect()
+}
+
+// Runs every processing stage over freshly classified features.
+fn process(results: Vec<Kind>, geometry: &Geometry) -> Vec<Kind> {
+    let stages: &[(&str, Stage)] = &[
+        ("coastline and parks", process_coastline_and_parks),
+        ("roads", process_roads),
+        ("water", process_water),
+        ("landuse", process_landuse),
+        ("park clippings", process_park_clippings),
+        ("transit", process_transit),
+    ];
+    let elements: Vec<Kind> = results.iter().filter_map(Kind::to_element).collect();
+    let mut processed = stages.iter().fold(results, |results, &(name, stage)| {
+        timing::stage(name, || stage(results, geometry), Vec::len)
+    });
+    processed.extend(elements);
+    processed
+}
+
+const SUBWAY_FILE: &str = "./data/geojson/subway_lines.pretty.geojson";
+
+// The overlays drawn when none are asked for.
+fn default_overlays() -> Vec<String> {
+    if std::path::Path::new(SUBWAY_FILE).exists() {
+        vec![SUBWAY_FILE.into()]
+    } else {
+        vec![]
+    }
+}
 
-    let subways = geojson::from_file("./data/geojson/subway_lines.pretty.geojson").unwrap();
-    let results = process_subways(results, &geometry, subways);
+// Overlays without simplestyle properties keep the theme's style.
+fn inline_style(style: &str) -> Vec<(&str, &str)> {
+    if style.is_empty() {
+        vec![]
+    } else {
+        vec![("style", style)]
+    }
+}
 
-    let mut svg = Svg::new(bounds);
+// GeoJSON, Shapefile and GPX files to draw over the map, and how.
+pub struct Overlays {
+    pub files: Vec<String>,
+    // How to colour the tracks of GPX overlays.
+    pub colour_by: Option<gpx::ColourBy>,
+    // The coordinate system of GeoJSON and Shapefile overlays which don't
+    // declare their own.
+    pub crs: Option<crs::Crs>,
+}
 
-    svg.set_clippings_layer(Layer::ParkBuilding, Layer::Park);
-    svg.set_clippings_layer(Layer::ParkPath, Layer::Park);
+impl Overlays {
+    fn new(files: Vec<String>) -> Overlays {
+        Overlays {
+            files,
+            colour_by: None,
+            crs: None,
+        }
+    }
 
-    theme::gray_theme(&mut svg);
+    // The default overlays plus those given by `--gpx`, `--overlay`,
+    // `--colour-by` and `--overlay-crs`, looked up with `option`.
+    fn from_options<'a>(option: &dyn Fn(&str) -> Option<&'a str>) -> std::io::Result<Overlays> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        let mut overlays = Overlays::new(default_overlays());
+        overlays.files.extend(option("--gpx").map(String::from));
+        overlays.files.extend(option("--overlay").map(String::from));
+        overlays.colour_by = option("--colour-by")
+            .map(|name| {
+                gpx::ColourBy::by_name(name)
+                    .ok_or_else(|| invalid("--colour-by must be elevation or speed".into()))
+            })
+            .transpose()?;
+        if let Some(name) = option("--overlay-crs") {
+            overlays.crs = Some(crs::Crs::by_name(name).map_err(invalid)?);
+        }
+        Ok(overlays)
+    }
+}
 
-    for kind in &results {
-        let layer = kind.to_layer();
-        let coords = kind.resolve_coords(&geometry);
-        svg.draw_polyline(layer, coords);
+// Loads a GeoJSON or Shapefile overlay, with its coordinates turned into
+// longitude and latitude.  The file's own coordinate system (a GeoJSON `crs`
+// member or a `.prj`) wins over `default_crs`.
+fn load_features(file: &str, default_crs: Option<crs::Crs>) -> std::io::Result<geojson::Geojson> {
+    let invalid = |message| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", file, message),
+        )
+    };
+    let (mut features, declared) = if file.to_lowercase().ends_with(".shp") {
+        shapefile::from_file(file)?
+    } else {
+        let features = geojson::from_file(file)?;
+        let declared = features.crs_name().map(crs::Crs::by_name).transpose();
+        (features, declared.map_err(invalid)?)
+    };
+    if let Some(crs) = declared.or(default_crs) {
+        crs.unproject_geojson(&mut features);
+    }
+    Ok(features)
+}
 
-        match layer {
-            Layer::Building => svg.draw_polyline(Layer::ParkBuilding, coords),
-            Layer::Road => svg.draw_polyline(Layer::ParkPath, coords),
-            // Layer::Subway => svg.draw_polyline(Layer::ParkPath, coords),
-            _ => (),
+// How to draw the map.
+pub struct RenderOptions {
+    pub theme: theme::Theme,
+    // Rendered height; None keeps the map's own units.
+    pub height: Option<f64>,
+    pub relief: buildings::Relief,
+    // Where the sunlight for building shadows comes from, in degrees
+    // clockwise from north.
+    pub sun: f64,
+    // Whether to write the OSM id, and any kept tags, of features still drawn
+    // from a single element.
+    pub osm_ids: bool,
+    pub furniture: furniture::Furniture,
+}
+
+impl RenderOptions {
+    fn new(theme: theme::Theme) -> RenderOptions {
+        RenderOptions {
+            theme,
+            height: None,
+            relief: buildings::Relief::Flat,
+            sun: DEFAULT_SUN,
+            osm_ids: false,
+            furniture: Default::default(),
         }
     }
+}
+
+// From the north-west, as on most shaded maps.
+const DEFAULT_SUN: f64 = 315.0;
+
+// The `id` and `data-*` attributes tracing a feature back to its OSM element.
+// A way drawn more than once (say, as a member of two relations) only gets an
+// `id` the first time.
+fn osm_attributes(
+    geometry: &Geometry,
+    kind: &Kind,
+    ids: &mut HashSet<String>,
+) -> Vec<(String, String)> {
+    let source = match kind.range().and_then(|r| geometry.source(r)) {
+        Some(source) => source,
+        None => return vec![],
+    };
+    let name = source.name();
+    let mut attributes = vec![];
+    if ids.insert(name.clone()) {
+        attributes.push(("id".to_string(), name.replace('/', "-")));
+    }
+    attributes.push(("data-osm-id".to_string(), name));
+    if let Some(relation) = source.relation {
+        attributes.push(("data-osm-relation".to_string(), relation.to_string()));
+    }
+    for (key, value) in &source.tags {
+        let key: String = key
+            .to_lowercase()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '-',
+            })
+            .collect();
+        attributes.push((format!("data-{}", key), value.clone()));
+    }
+    attributes
+}
+
+fn borrow_attributes(attributes: &[(String, String)]) -> Vec<(&str, &str)> {
+    attributes
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
 
-    let layer_order = &[
-        Layer::Coastline,
+// Draws buildings flat, with shadows, or as walls and roofs back to front.
+// Buildings which aren't closed are always drawn flat.
+// The footprint or roof carries the building's OSM attributes.
+fn draw_buildings(
+    svg: &mut Svg<Layer>,
+    geometry: &Geometry,
+    results: &[Kind],
+    options: &RenderOptions,
+    ids: &mut HashSet<String>,
+) {
+    let units_per_metre = geometry.bounds.units_per_metre();
+    let mut footprints = vec![];
+    for kind in results {
+        if let Kind::Building(_, building) = kind {
+            let footprint = kind.resolve_coords(geometry);
+            let attributes = if options.osm_ids {
+                osm_attributes(geometry, kind, ids)
+            } else {
+                vec![]
+            };
+            let closed = footprint.len() > 3 && footprint[0] == footprint[footprint.len() - 1];
+            if closed && options.relief != buildings::Relief::Flat {
+                footprints.push((footprint, building, attributes));
+            } else {
+                svg.draw_polyline_with(Layer::Building, footprint, &borrow_attributes(&attributes));
+            }
+        }
+    }
+
+    if options.relief == buildings::Relief::Shadow {
+        for (footprint, building, attributes) in &footprints {
+            let height = building.height();
+            for rings in buildings::shadow(footprint, height, options.sun, units_per_metre) {
+                svg.draw_polygon(Layer::BuildingShadow, &rings);
+            }
+            svg.draw_polyline_with(Layer::Building, footprint, &borrow_attributes(attributes));
+        }
+        return;
+    }
+
+    let wall = svg.class_of(&Layer::BuildingWall).unwrap_or_default();
+    footprints.sort_by(|(a, ..), (b, ..)| buildings::depth(b).total_cmp(&buildings::depth(a)));
+    for (footprint, building, attributes) in footprints {
+        let mut faces = buildings::extrude(footprint, building.wall_height(), units_per_metre);
+        let roof = faces.pop().unwrap();
+        for face in faces {
+            svg.draw_polyline_with(Layer::Building, &face, &[("class", &wall)]);
+        }
+        svg.draw_polyline_with(Layer::Building, &roof, &borrow_attributes(&attributes));
+    }
+}
+
+// Draws the processed features, plus overlays, to an SVG file, or to an
+// interactive HTML page if `out` ends in `.html`.
+fn render(
+    geometry: &Geometry,
+    results: &[Kind],
+    overlays: &Overlays,
+    options: &RenderOptions,
+    out: &str,
+) -> std::io::Result<()> {
+    let mut overlay_kinds = vec![];
+    for file in &overlays.files {
+        // GPS devices and GIS tools often write upper case extensions.
+        let lower = file.to_lowercase();
+        if lower.ends_with(".gpx") {
+            overlay_kinds.extend(process_gpx(gpx::from_file(file)?, overlays.colour_by));
+        } else if lower.ends_with(".shp") {
+            overlay_kinds.extend(process_features(load_features(file, overlays.crs)?));
+        } else {
+            overlay_kinds.extend(process_subways(load_features(file, overlays.crs)?));
+        }
+    }
+
+    let mut svg = Svg::new(geometry.bounds);
+
+    (options.theme)(&mut svg);
+    if let Some(height) = options.height {
+        svg.set_height(height);
+    }
+    let mut ids = HashSet::new();
+    draw_buildings(&mut svg, geometry, results, options, &mut ids);
+
+    let element_area = svg.class_of(&Layer::ElementArea).unwrap_or_default();
+    let element_line = svg.class_of(&Layer::ElementLine).unwrap_or_default();
+    for kind in results.iter().chain(&overlay_kinds) {
+        let layer = kind.to_layer();
+        let attributes = match kind {
+            Kind::Building(..) => vec![],
+            Kind::Element(..) if !options.osm_ids => continue,
+            _ if options.osm_ids => osm_attributes(geometry, kind, &mut ids),
+            _ => vec![],
+        };
+        let attributes = borrow_attributes(&attributes);
+        match kind {
+            Kind::ProcessedRoute(coords, route) => match &route.colour {
+                Some(colour) => {
+                    let style = format!("stroke:{}", colour);
+                    svg.draw_polyline_with(layer, coords, &[("style", &style)]);
+                }
+                None => svg.draw_polyline(layer, coords),
+            },
+            Kind::Building(..) => (),
+            Kind::Element(..) => {
+                let coords = kind.resolve_coords(geometry);
+                let closed = coords.len() > 3 && coords[0] == coords[coords.len() - 1];
+                let class = if closed { &element_area } else { &element_line };
+                let mut attributes = attributes;
+                attributes.push(("class", class));
+                svg.draw_polyline_with(layer, coords, &attributes);
+            }
+            Kind::Station(_) => {
+                let point = kind.resolve_coords(geometry)[0];
+                svg.draw_point_with(layer, point, 2.0, &attributes)
+            }
+            Kind::GpxBand(coords, band) => {
+                let style = format!("stroke:{}", gpx::band_colour(*band));
+                svg.draw_polyline_with(layer, coords, &[("style", &style)]);
+            }
+            Kind::GpxWaypoint(point) => svg.draw_point(layer, *point, 3.0),
+            Kind::OverlayArea(rings, style) => {
+                svg.draw_polygon_with(layer, rings, &inline_style(style))
+            }
+            Kind::OverlayLine(coords, style) => {
+                svg.draw_polyline_with(layer, coords, &inline_style(style))
+            }
+            Kind::OverlayPoint(point, style) => {
+                svg.draw_point_with(layer, *point, 2.5, &inline_style(style))
+            }
+            Kind::ItineraryMarker(point, _) => svg.draw_point(layer, *point, 4.0),
+            Kind::ContourLabel(point, angle, text) => svg.draw_text(layer, *point, *angle, text),
+            Kind::Hillshade(href) => svg.draw_image(layer, href),
+            Kind::GridLabel(point, text) => svg.draw_text(layer, *point, 0.0, text),
+            Kind::Rail(_) => {
+                let coords = kind.resolve_coords(geometry);
+                svg.draw_polyline_with(layer, coords, &attributes);
+                svg.draw_polyline(Layer::RailHatching, coords);
+            }
+            _ => match kind.polygon() {
+                Some(rings) => svg.draw_polygon(layer, rings),
+                None => svg.draw_polyline_with(layer, kind.resolve_coords(geometry), &attributes),
+            },
+        }
+    }
+
+    let mut layer_order = vec![Layer::Coastline];
+    layer_order.extend_from_slice(LANDUSE_ORDER);
+    layer_order.extend_from_slice(&[
         Layer::Park,
+        Layer::Wetland,
+        Layer::Hillshade,
+        Layer::Contour,
+        Layer::IndexContour,
+        Layer::ContourLabel,
+        Layer::Water,
+        Layer::Waterway,
         Layer::Road,
+        Layer::BuildingShadow,
         Layer::Building,
         Layer::ParkBuilding,
         Layer::ParkPath,
+        Layer::Rail,
+        Layer::RailHatching,
+        Layer::LightRail,
         Layer::Subway,
-    ];
+        Layer::TransitRoute,
+        Layer::Station,
+        Layer::OverlayArea,
+        Layer::OverlayLine,
+        Layer::OverlayPoint,
+        Layer::GpxRoute,
+        Layer::GpxTrack,
+        Layer::GpxWaypoint,
+        Layer::Itinerary,
+        Layer::ItineraryStart,
+        Layer::ItineraryEnd,
+        Layer::Grid,
+        Layer::GridLabel,
+    ]);
+    furniture::draw(&mut svg, &options.furniture, &geometry.bounds, &layer_order);
 
-    svg.export_to_file(&format!("./data/svg/{}.svg", filename), layer_order)?;
+    if out.ends_with(".html") {
+        let title = std::path::Path::new(out)
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        html::export_to_file(&svg, &title, out, &layer_order)
+    } else {
+        svg.export_to_file(out, &layer_order)
+    }
+}
+
+#[cfg_attr(feature = "profiling", flame)]
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    let filename = &args[1].to_string();
+    let option = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .map(String::as_str)
+    };
+    // Bad options are reported like any other error.
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let (rules, rules_text) = load_rules(option("--rules"))?;
+
+    if filename == "inspect" {
+        let input = args
+            .get(2)
+            .ok_or_else(|| invalid("usage: mapfun inspect <file> [--json]".into()))?;
+        let path = if std::path::Path::new(input).exists() {
+            input.clone()
+        } else {
+            format!("./data/osm/{}.osm", input)
+        };
+        let report = inspect::inspect(&path, &rules)?;
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        if args.iter().any(|arg| arg == "--json") {
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            writeln!(out)?;
+        } else {
+            report.write_text(&mut out)?;
+        }
+        return Ok(());
+    }
+    if filename == "network" {
+        let input = args.get(2).ok_or_else(|| {
+            invalid("usage: mapfun network <file> [--rules FILE] [--adjacency]".into())
+        })?;
+        let adjacency = args.iter().any(|arg| arg == "--adjacency");
+        return network::run(input, &rules, adjacency);
+    }
+    if filename == "batch" {
+        let manifest = args
+            .get(2)
+            .ok_or_else(|| invalid("usage: mapfun batch <manifest.json>".into()))?;
+        return batch::run(manifest);
+    }
+    if filename == "watch" {
+        let name = args.get(2).ok_or_else(|| {
+            invalid(
+                "usage: mapfun watch <name> [--rules FILE] [--overlay FILE] [--gpx FILE] \
+                 [--colour-by elevation|speed] [--overlay-crs CRS] [--serve PORT]"
+                    .into(),
+            )
+        })?;
+        let port = option("--serve")
+            .map(|port| {
+                port.parse()
+                    .map_err(|_| invalid(format!("invalid port `{}`", port)))
+            })
+            .transpose()?;
+        let overlays = Overlays::from_options(&option)?;
+        return watch::watch(name, option("--rules"), overlays, port);
+    }
+    println!("{:?}", args);
+
+    let explanations = RefCell::new(vec![]);
+    let explain_file = option("--explain");
+
+    let filter = |feature: &Feature, range: RangeIdx| {
+        let rule = rules.find(feature)?;
+        let kind = kind_for(&rule.layer, feature, range);
+        if explain_file.is_some() {
+            let line = explain(feature, rule, kind.is_some());
+            explanations.borrow_mut().push(line);
+        }
+        kind
+    };
+
+    let osm_file = format!("./data/osm/{}.osm", filename.as_str());
+    let html = args.iter().any(|arg| arg == "--html");
+    // OSM tags to keep for `data-*` attributes, which also turns on ids.  The
+    // HTML page's tooltips and search need a few.
+    let osm_tags: Vec<String> = match option("--osm-tags") {
+        Some(keys) => keys.split(',').map(String::from).collect(),
+        None if html => html::TOOLTIP_TAGS.iter().map(|&key| key.into()).collect(),
+        None => vec![],
+    };
+    let from_osm = |input: &[u8]| {
+        let doc = osm_load::parse_osm(input)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", osm_file, e)))?;
+        let (mut geometry, results) = Geometry::from_osm(&doc, &filter, TARGET_HEIGHT)?;
+        if !osm_tags.is_empty() {
+            geometry.keep_tags(&doc, &osm_tags);
+        }
+        Ok((geometry, results))
+    };
+    // Explaining needs the rules to run, so it bypasses the cache.
+    let load = || {
+        let input = std::fs::read(&osm_file)?;
+        if explain_file.is_some() || args.iter().any(|a| a == "--no-cache") {
+            from_osm(&input)
+        } else {
+            let key = cache::key(&input, &rules_text, &osm_tags, TARGET_HEIGHT);
+            Ok(match cache::load(filename, key) {
+                Some(cached) => cached,
+                None => {
+                    let (geometry, results) = from_osm(&input)?;
+                    if let Err(e) = cache::store(filename, key, &geometry, &results) {
+                        eprintln!("couldn't write the geometry cache: {}", e);
+                    }
+                    (geometry, results)
+                }
+            })
+        }
+    };
+    let loaded = timing::stage("load", load, |loaded: &std::io::Result<(_, Vec<_>)>| {
+        loaded.as_ref().map_or(0, |(_, results)| results.len())
+    });
+    let (geometry, results) = loaded?;
+    if let Some(explain_file) = explain_file {
+        let mut lines = explanations.into_inner();
+        lines.push(String::new());
+        std::fs::write(explain_file, lines.join("\n"))?;
+    }
+    let mut results = results;
+    if let Some(waypoints) = option("--route") {
+        let mode = option("--mode").unwrap_or("foot");
+        let mode = roads::Mode::by_name(mode)
+            .ok_or_else(|| invalid("--mode must be foot, bicycle or car".into()))?;
+        let route = plan_route(&results, &geometry, waypoints, mode)?;
+        results.extend(route);
+    }
+    let mut results = process(results, &geometry);
+    if let Some(dir) = option("--terrain") {
+        let interval = option("--contour-interval")
+            .map(|interval| {
+                interval
+                    .parse()
+                    .map_err(|_| invalid("--contour-interval must be a number".into()))
+            })
+            .transpose()?;
+        let options = terrain::TerrainOptions {
+            dir: dir.into(),
+            interval: interval.unwrap_or_else(terrain::default_interval),
+            hillshade: args.iter().any(|arg| arg == "--hillshade"),
+        };
+        results.extend(process_terrain(&geometry, &options)?);
+    }
+    let spacing = |name: &str| {
+        option(name)
+            .map(|interval| match interval.parse::<f64>() {
+                Ok(interval) if interval > 0.0 => Ok(interval),
+                _ => Err(invalid(format!("{} must be a positive number", name))),
+            })
+            .transpose()
+    };
+    if let Some(degrees) = spacing("--graticule")? {
+        results.extend(process_grid(&geometry, grid::Grid::Graticule(degrees)));
+    }
+    if let Some(metres) = spacing("--grid")? {
+        results.extend(process_grid(&geometry, grid::Grid::Metric(metres)));
+    }
+    if let Some(layout) = option("--geojson") {
+        let options = export::GeojsonOptions {
+            split: match layout {
+                "combined" => false,
+                "split" => true,
+                _ => return Err(invalid("--geojson must be combined or split".into())),
+            },
+            projected: args.iter().any(|arg| arg == "--geojson-projected"),
+        };
+        let out = if options.split {
+            format!("./data/export/{}", filename)
+        } else {
+            format!("./data/export/{}.geojson", filename)
+        };
+        export::export_to(&geometry, &results, options, &out)?;
+        println!("wrote {}", out);
+    }
+    let extension = if html { "html" } else { "svg" };
+    let out = format!("./data/svg/{}.{}", filename, extension);
+    let overlays = Overlays::from_options(&option)?;
+    let mut render_options = RenderOptions::new(theme::gray_theme);
+    if let Some(relief) = option("--buildings") {
+        render_options.relief = buildings::Relief::by_name(relief)
+            .ok_or_else(|| invalid("--buildings must be flat, shadow or extrude".into()))?;
+    }
+    if let Some(sun) = option("--sun") {
+        render_options.sun = sun
+            .parse()
+            .map_err(|_| invalid("--sun must be a bearing in degrees".into()))?;
+    }
+    let corner = |name: &str| {
+        option(name)
+            .map(|corner| {
+                furniture::Corner::by_name(corner).ok_or_else(|| {
+                    invalid(format!(
+                        "{} must be top-left, top-right, bottom-left or bottom-right",
+                        name
+                    ))
+                })
+            })
+            .transpose()
+    };
+    let furniture = &mut render_options.furniture;
+    furniture.title = option("--title").map(String::from);
+    furniture.subtitle = option("--subtitle").map(String::from);
+    furniture.title_corner = corner("--title-corner")?.unwrap_or(furniture.title_corner);
+    furniture.scale_bar = corner("--scale-bar")?;
+    furniture.north_arrow = corner("--north-arrow")?;
+    furniture.legend = corner("--legend")?;
+    // On by default, so it can be turned off.
+    furniture.attribution = match option("--attribution") {
+        Some("none") => None,
+        _ => corner("--attribution")?.or(furniture.attribution),
+    };
+    render_options.osm_ids = !osm_tags.is_empty() || args.iter().any(|arg| arg == "--osm-ids");
+    let export = || render(&geometry, &results, &overlays, &render_options, &out);
+    timing::stage("export", export, |_| results.len())?;
+
+    let stages = timing::take();
+    if args.iter().any(|arg| arg == "--timings") {
+        timing::write_summary(&stages, &mut std::io::stderr())?;
+    }
+    if let Some(timings_file) = option("--timings-json") {
+        let file = std::fs::File::create(timings_file)?;
+        serde_json::to_writer_pretty(file, &stages)?;
+    }
+    #[cfg(feature = "profiling")]
     flame::dump_html(std::fs::File::create("./flame.html")?)?;
 
     Ok(())
 }
+
+#[test]
+fn merged_features_keep_their_ids_and_tags() {
+    let doc = osm_load::parse_osm(
+        br#"<osm>
+  <bounds minlat="40.0" minlon="-74.0" maxlat="40.1" maxlon="-73.9"/>
+  <node id="1" lat="40.01" lon="-73.99"/>
+  <node id="2" lat="40.02" lon="-73.99"/>
+  <node id="3" lat="40.02" lon="-73.98"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/>
+    <tag k="highway" v="residential"/><tag k="name" v="Main Street"/></way>
+  <way id="11"><nd ref="3"/><nd ref="1"/><tag k="highway" v="service"/></way>
+</osm>"#,
+    )
+    .unwrap();
+    let (rules, _) = load_rules(None).unwrap();
+    let filter = |feature: &Feature, range: RangeIdx| {
+        let rule = rules.find(feature)?;
+        kind_for(&rule.layer, feature, range)
+    };
+    let (mut geometry, results) = Geometry::from_osm(&doc, &filter, TARGET_HEIGHT).unwrap();
+    geometry.keep_tags(&doc, &["name".to_string()]);
+    let results = process(results, &geometry);
+    // Both ways are unioned into one road surface...
+    let surfaces = results.iter().filter(|kind| kind.polygon().is_some());
+    assert_eq!(1, surfaces.count());
+
+    // ...but are each still on the map with their ids and tags.
+    let mut options = RenderOptions::new(theme::gray_theme);
+    options.osm_ids = true;
+    let dir = std::env::temp_dir().join(format!("mapfun-ids-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("map.svg");
+    let overlays = Overlays::new(vec![]);
+    render(
+        &geometry,
+        &results,
+        &overlays,
+        &options,
+        out.to_str().unwrap(),
+    )
+    .unwrap();
+    let svg = std::fs::read_to_string(&out).unwrap();
+    let street = svg
+        .lines()
+        .find(|line| line.contains(r#"data-osm-id="way/10""#))
+        .unwrap();
+    assert!(street.contains(r#"data-name="Main Street""#));
+    assert!(street.contains(r#"class="element-line""#));
+    assert!(svg.contains(r#"data-osm-id="way/11""#));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn overlay_options() {
+    let option = |name: &str| match name {
+        "--overlay" => Some("parcels.shp"),
+        "--gpx" => Some("run.gpx"),
+        "--colour-by" => Some("speed"),
+        _ => None,
+    };
+    let overlays = Overlays::from_options(&option).unwrap();
+    assert!(overlays
+        .files
+        .ends_with(&["run.gpx".into(), "parcels.shp".into()]));
+    assert_eq!(Some(gpx::ColourBy::Speed), overlays.colour_by);
+
+    let option = |name: &str| {
+        if name == "--overlay-crs" {
+            Some("EPSG:1")
+        } else {
+            None
+        }
+    };
+    assert!(Overlays::from_options(&option).is_err());
+}
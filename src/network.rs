@@ -0,0 +1,356 @@
+// The street network: road ways split wherever they share a node with another
+// way, so that every edge runs between two junctions or dead ends.
+
+use batch::input_path;
+use geojson::{self, Geojson};
+use linemath::Point;
+use osm_load::{self, Bounds, ElementType, Feature, Geometry, RangeIdx};
+use rules::RuleSet;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use TARGET_HEIGHT;
+
+pub struct Node {
+    pub id: i64,
+    pub point: Point,
+    // How many edge ends meet here.
+    pub degree: usize,
+}
+
+pub struct Edge<T> {
+    // Indexes into `Network::nodes`.
+    pub from: usize,
+    pub to: usize,
+    pub points: Vec<Point>,
+    // In metres.
+    pub length: f64,
+    // Whatever was attached to the way the edge came from.
+    pub data: T,
+}
+
+pub struct Network<T> {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge<T>>,
+}
+
+// The same way reached twice (say, directly and through a route relation)
+// should only make one set of edges, whichever way round it was listed.
+fn canonical(ids: &[i64]) -> Vec<i64> {
+    let reversed: Vec<i64> = ids.iter().rev().cloned().collect();
+    if reversed[..] < *ids {
+        reversed
+    } else {
+        ids.to_vec()
+    }
+}
+
+impl<T: Clone> Network<T> {
+    // Builds the network from the ways at `ways`, each with the data to put on
+    // its edges.
+    #[cfg_attr(feature = "profiling", flame)]
+    pub fn build(geometry: &Geometry, ways: Vec<(RangeIdx, T)>) -> Network<T> {
+        let mut seen = HashSet::new();
+        let ways: Vec<_> = ways
+            .into_iter()
+            .filter(|&(idx, _)| {
+                let ids = geometry.resolve_node_ids(idx);
+                ids.len() > 1 && seen.insert(canonical(ids))
+            })
+            .collect();
+
+        // Nodes used more than once, counting the ends of ways twice, are where
+        // ways are split.
+        let mut uses: HashMap<i64, usize> = HashMap::new();
+        for &(idx, _) in &ways {
+            let ids = geometry.resolve_node_ids(idx);
+            for &id in ids.iter().chain(&[ids[0], ids[ids.len() - 1]]) {
+                *uses.entry(id).or_default() += 1;
+            }
+        }
+
+        let units_per_metre = geometry.bounds.units_per_metre();
+        let mut network = Network {
+            nodes: vec![],
+            edges: vec![],
+        };
+        let mut index = HashMap::new();
+        for (idx, data) in ways {
+            let ids = geometry.resolve_node_ids(idx);
+            let points = geometry.resolve_coords(idx);
+            let mut start = 0;
+            for end in 1..ids.len() {
+                if uses[&ids[end]] < 2 {
+                    continue;
+                }
+                if end - start > 1 || ids[start] != ids[end] {
+                    let from = network.node(&mut index, ids[start], points[start]);
+                    let to = network.node(&mut index, ids[end], points[end]);
+                    let points = points[start..=end].to_vec();
+                    let length = points
+                        .windows(2)
+                        .map(|pair| distance(pair[0], pair[1]))
+                        .sum::<f64>()
+                        / units_per_metre;
+                    network.nodes[from].degree += 1;
+                    network.nodes[to].degree += 1;
+                    network.edges.push(Edge {
+                        from,
+                        to,
+                        points,
+                        length,
+                        data: data.clone(),
+                    });
+                }
+                start = end;
+            }
+        }
+        network
+    }
+
+    fn node(&mut self, index: &mut HashMap<i64, usize>, id: i64, point: Point) -> usize {
+        let nodes = &mut self.nodes;
+        *index.entry(id).or_insert_with(|| {
+            nodes.push(Node {
+                id,
+                point,
+                degree: 0,
+            });
+            nodes.len() - 1
+        })
+    }
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    (b.x() - a.x()).hypot(b.y() - a.y())
+}
+
+impl<T> Network<T> {
+    // Nodes where a single road stops.  Roads leaving the map look like dead
+    // ends too, so only nodes inside `bounds` count.
+    pub fn dead_ends<'a>(&'a self, bounds: &'a Bounds) -> impl Iterator<Item = &'a Node> + 'a {
+        self.nodes.iter().filter(move |node| {
+            let (x, y) = (node.point.x(), node.point.y());
+            node.degree == 1
+                && x > bounds.min_lon
+                && x < bounds.max_lon
+                && y > bounds.min_lat
+                && y < bounds.max_lat
+        })
+    }
+
+    // The edges leaving each node, as `(edge, other node)`.
+    pub fn adjacency(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut adjacency = vec![vec![]; self.nodes.len()];
+        for (i, edge) in self.edges.iter().enumerate() {
+            adjacency[edge.from].push((i, edge.to));
+            if edge.to != edge.from {
+                adjacency[edge.to].push((i, edge.from));
+            }
+        }
+        adjacency
+    }
+
+    // One line per node, ordered by id:
+    //
+    //     <id> <lon> <lat> <neighbour id>:<metres> ...
+    //
+    // separated by tabs.
+    pub fn write_adjacency<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let adjacency = self.adjacency();
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+        order.sort_by_key(|&i| self.nodes[i].id);
+        for i in order {
+            let node = &self.nodes[i];
+            let (lon, lat) = osm_load::unproject(node.point);
+            write!(out, "{}\t{:.7}\t{:.7}", node.id, lon, lat)?;
+            for &(edge, other) in &adjacency[i] {
+                let length = self.edges[edge].length;
+                write!(out, "\t{}:{:.1}", self.nodes[other].id, length)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Serialize> Network<T> {
+    // Edges as LineStrings, carrying their data and length, followed by the
+    // nodes as Points.
+    pub fn to_geojson(&self, bounds: &Bounds) -> Geojson {
+        let coordinate = |point: Point| {
+            let (lon, lat) = osm_load::unproject(point);
+            [lon, lat]
+        };
+        let dead_ends: HashSet<i64> = self.dead_ends(bounds).map(|node| node.id).collect();
+
+        let mut features = vec![];
+        for edge in &self.edges {
+            let mut properties = match serde_json::to_value(&edge.data) {
+                Ok(Value::Object(map)) => map,
+                _ => serde_json::Map::new(),
+            };
+            properties.insert("from".into(), self.nodes[edge.from].id.into());
+            properties.insert("to".into(), self.nodes[edge.to].id.into());
+            properties.insert("length".into(), edge.length.into());
+            features.push(geojson::Feature {
+                geometry: geojson::Geometry::LineString {
+                    coordinates: edge.points.iter().cloned().map(coordinate).collect(),
+                },
+                properties: Value::Object(properties),
+            });
+        }
+        for node in &self.nodes {
+            features.push(geojson::Feature {
+                geometry: geojson::Geometry::Point {
+                    coordinates: coordinate(node.point),
+                },
+                properties: json!({
+                    "id": node.id,
+                    "degree": node.degree,
+                    "dead_end": dead_ends.contains(&node.id),
+                }),
+            });
+        }
+        Geojson::new(features)
+    }
+}
+
+// What an exported edge knows about the way it came from.
+#[derive(Clone, Serialize)]
+pub struct Way {
+    pub way: i64,
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, String>,
+}
+
+// `mapfun network`: writes the street network of an extract (the ways the
+// rules put on the `road` layer) to `data/network/<name>.geojson`, or to
+// `<name>.adj` as an adjacency list.
+pub fn run(input: &str, rules: &RuleSet, adjacency: bool) -> io::Result<()> {
+    let filter = |feature: &Feature, idx: RangeIdx| {
+        if feature.element != ElementType::Way || feature.relation.is_some() {
+            return None;
+        }
+        if rules.find(feature)?.layer != "road" {
+            return None;
+        }
+        let way = Way {
+            way: feature.id,
+            tags: feature
+                .tags
+                .iter()
+                .map(|tag| (tag.key.clone(), tag.val.clone()))
+                .collect(),
+        };
+        Some((idx, way))
+    };
+    let (geometry, ways) = Geometry::from_file(&input_path(input), &filter, TARGET_HEIGHT)?;
+    let network = Network::build(&geometry, ways);
+    let dead_ends = network.dead_ends(&geometry.bounds).count();
+    println!(
+        "{} nodes, {} edges, {} dead ends",
+        network.nodes.len(),
+        network.edges.len(),
+        dead_ends
+    );
+
+    let name = std::path::Path::new(input)
+        .file_stem()
+        .map_or(input.into(), |stem| stem.to_string_lossy());
+    std::fs::create_dir_all("./data/network")?;
+    let out = if adjacency {
+        let out = format!("./data/network/{}.adj", name);
+        network.write_adjacency(&mut BufWriter::new(File::create(&out)?))?;
+        out
+    } else {
+        let out = format!("./data/network/{}.geojson", name);
+        let geojson = network.to_geojson(&geometry.bounds);
+        serde_json::to_writer(BufWriter::new(File::create(&out)?), &geojson)?;
+        out
+    };
+    println!("wrote {}", out);
+    Ok(())
+}
+
+#[cfg(test)]
+pub fn test_geometry(ways: &[&[i64]]) -> Geometry {
+    // Node n sits at (n, n % 3), in metres at the equator.
+    let mut geometry = Geometry {
+        bounds: Bounds {
+            width: 10.0,
+            height: 10.0,
+            min_lon: -100.0,
+            min_lat: -100.0,
+            max_lon: 100.0,
+            max_lat: 100.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+        },
+        coords: vec![],
+        node_ids: vec![],
+        polys: vec![],
+        inner: vec![],
+        sources: vec![],
+    };
+    for way in ways {
+        let start = geometry.coords.len();
+        for &id in way.iter() {
+            geometry.node_ids.push(id);
+            geometry.coords.push(Point::new(id as f64, (id % 3) as f64));
+        }
+        geometry.polys.push(start..geometry.coords.len());
+        geometry.inner.push(false);
+    }
+    geometry
+}
+
+#[test]
+fn ways_are_split_at_shared_nodes() {
+    // A crossroads at 3, plus a way which ends in the middle of nowhere.
+    let geometry = test_geometry(&[&[1, 2, 3, 4, 5], &[10, 3, 11], &[5, 6]]);
+    let network = Network::build(&geometry, vec![(0, 'a'), (1, 'b'), (2, 'c')]);
+
+    let edges: Vec<_> = network
+        .edges
+        .iter()
+        .map(|e| (network.nodes[e.from].id, network.nodes[e.to].id, e.data))
+        .collect();
+    assert_eq!(
+        vec![
+            (1, 3, 'a'),
+            (3, 5, 'a'),
+            (10, 3, 'b'),
+            (3, 11, 'b'),
+            (5, 6, 'c')
+        ],
+        edges
+    );
+
+    let degree = |id| network.nodes.iter().find(|n| n.id == id).unwrap().degree;
+    assert_eq!((4, 2, 1), (degree(3), degree(5), degree(6)));
+    let dead_ends: Vec<_> = network.dead_ends(&geometry.bounds).map(|n| n.id).collect();
+    assert_eq!(vec![1, 10, 11, 6], dead_ends);
+
+    let length = 2f64.sqrt() + 5f64.sqrt();
+    assert!((network.edges[0].length - length).abs() < 1e-9);
+}
+
+#[test]
+fn repeated_ways_make_one_set_of_edges() {
+    let geometry = test_geometry(&[&[1, 2, 3], &[3, 2, 1], &[1, 2, 3]]);
+    let network = Network::build(&geometry, vec![(0, ()), (1, ()), (2, ())]);
+    assert_eq!(1, network.edges.len());
+
+    let mut adjacency = vec![];
+    network.write_adjacency(&mut adjacency).unwrap();
+    let adjacency = String::from_utf8(adjacency).unwrap();
+    let lines: Vec<Vec<&str>> = adjacency
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(vec!["1", "3:3.7"], [lines[0][0], lines[0][3]]);
+    assert_eq!(vec!["3", "1:3.7"], [lines[1][0], lines[1][3]]);
+}
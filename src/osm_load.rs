@@ -3,22 +3,79 @@ use osm_xml::{Member, Reference, Way, OSM};
 use proj5::FromLonLat;
 use proj5::{crs::MercatorSystem, *};
 
+use linemath::Point;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
 use std::ops::Range;
+use timing;
 
 pub use osm_xml::Tag;
 
-pub type Callback<'a, T> = &'a Fn(&[Tag], &[Tag], RangeIdx) -> Option<T>;
+pub type Callback<'a, T> = &'a dyn Fn(&Feature, RangeIdx) -> Option<T>;
 pub type RangeIdx = usize;
 
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ElementType {
+    Node,
+    Way,
+    Relation,
+}
+
+// A node or way offered to the callback, along with the relation it was
+// reached through (if any).
+pub struct Feature<'a> {
+    pub element: ElementType,
+    pub id: i64,
+    pub tags: &'a [Tag],
+    pub relation: Option<(i64, &'a [Tag])>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Geometry {
     pub bounds: Bounds,
-    pub coords: Vec<(f64, f64)>,
+    pub coords: Vec<Point>,
+    // The OSM id of the node each coordinate came from.
+    pub node_ids: Vec<i64>,
     pub polys: Vec<Range<usize>>,
+    // Whether each poly was an inner member of a multipolygon relation.
+    pub inner: Vec<bool>,
+    // The OSM element each poly came from.
+    pub sources: Vec<Source>,
+}
+
+// An OSM element, the relation it was reached through (if any), and the tags
+// `Geometry::keep_tags` kept from them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    pub element: ElementType,
+    pub id: i64,
+    pub relation: Option<i64>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl Source {
+    fn new(element: ElementType, id: i64, relation: Option<i64>) -> Source {
+        Source {
+            element,
+            id,
+            relation,
+            tags: vec![],
+        }
+    }
+
+    // Such as `way/123`, as in osm.org URLs.
+    pub fn name(&self) -> String {
+        let element = match self.element {
+            ElementType::Node => "node",
+            ElementType::Way => "way",
+            ElementType::Relation => "relation",
+        };
+        format!("{}/{}", element, self.id)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct Bounds {
     pub width: f64,
     pub height: f64,
@@ -31,46 +88,120 @@ pub struct Bounds {
 }
 
 impl Geometry {
-    pub fn resolve_coords(&self, range_idx: RangeIdx) -> &[(f64, f64)] {
+    pub fn resolve_coords(&self, range_idx: RangeIdx) -> &[Point] {
         &self.coords[self.polys[range_idx].clone()]
     }
 
-    #[flame]
-    pub fn from_file<T>(path: &str, callback: Callback<T>, target_h: f64) -> (Geometry, Vec<T>) {
-        let f = File::open(path).unwrap();
-        let br = BufReader::new(f);
-        let doc = flame::span_of("reading osm data", || OSM::parse(br).unwrap());
-        let bounds = doc.bounds.unwrap();
+    pub fn resolve_node_ids(&self, range_idx: RangeIdx) -> &[i64] {
+        &self.node_ids[self.polys[range_idx].clone()]
+    }
+
+    pub fn is_inner(&self, range_idx: RangeIdx) -> bool {
+        self.inner[range_idx]
+    }
+
+    pub fn source(&self, range_idx: RangeIdx) -> Option<&Source> {
+        self.sources.get(range_idx)
+    }
+
+    // Copies the values of `keys` from each poly's element, or failing that
+    // from the relation it was reached through, into its source.
+    pub fn keep_tags(&mut self, doc: &OSM, keys: &[String]) {
+        for source in &mut self.sources {
+            let tags = match source.element {
+                ElementType::Node => doc.nodes.get(&source.id).map(|node| &node.tags),
+                ElementType::Way => doc.ways.get(&source.id).map(|way| &way.tags),
+                ElementType::Relation => doc.relations.get(&source.id).map(|rel| &rel.tags),
+            };
+            let relation_tags = source
+                .relation
+                .and_then(|id| doc.relations.get(&id))
+                .map(|rel| &rel.tags);
+            source.tags = keys
+                .iter()
+                .filter_map(|key| {
+                    let value = tags
+                        .and_then(|tags| find_tag(tags, key))
+                        .or_else(|| relation_tags.and_then(|tags| find_tag(tags, key)))?;
+                    Some((key.clone(), value.to_string()))
+                })
+                .collect();
+        }
+    }
+
+    #[cfg_attr(feature = "profiling", flame)]
+    pub fn from_file<T>(
+        path: &str,
+        callback: Callback<T>,
+        target_h: f64,
+    ) -> io::Result<(Geometry, Vec<T>)> {
+        Geometry::from_osm(&read_osm(path)?, callback, target_h)
+    }
+
+    pub fn from_osm<T>(
+        doc: &OSM,
+        callback: Callback<T>,
+        target_h: f64,
+    ) -> io::Result<(Geometry, Vec<T>)> {
+        Geometry::from_osm_cropped(doc, callback, None, target_h)
+    }
+
+    // Like `from_osm`, but framing the map on `crop` (`[min_lon, min_lat,
+    // max_lon, max_lat]` in degrees) rather than the extract's own bounds.
+    // Features are kept whole; the frame clips them when drawn.  Extracts
+    // without a `<bounds>` are framed on their nodes.
+    #[cfg_attr(feature = "profiling", flame)]
+    pub fn from_osm_cropped<T>(
+        doc: &OSM,
+        callback: Callback<T>,
+        crop: Option<[f64; 4]>,
+        target_h: f64,
+    ) -> io::Result<(Geometry, Vec<T>)> {
+        let frame = crop
+            .or_else(|| doc.bounds.map(|b| [b.minlon, b.minlat, b.maxlon, b.maxlat]))
+            .or_else(|| node_extent(doc))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no bounds, and no nodes to frame the map on",
+                )
+            })?;
+        let [min_lon, min_lat, max_lon, max_lat] = frame;
 
-        let bounds_converted = coord_convert(vec![
-            (bounds.maxlon, bounds.maxlat),
-            (bounds.minlon, bounds.minlat),
-        ]);
+        let bounds_converted = coord_convert(vec![(max_lon, max_lat), (min_lon, min_lat)]);
         let (b_max_lon, b_max_lat) = bounds_converted[0];
         let (b_min_lon, b_min_lat) = bounds_converted[1];
         let target_w = ((b_max_lon - b_min_lon) / (b_max_lat - b_min_lat)) * target_h;
         let scale_x = target_w / (b_max_lon - b_min_lon);
         let scale_y = target_h / (b_max_lat - b_min_lat);
 
+        // Longitude, latitude and node id.
         let mut all_coords = Vec::new();
         let mut all_polys = Vec::new();
+        let mut all_inner = Vec::new();
+        let mut all_sources = Vec::new();
         let mut all_values = Vec::new();
 
-        flame::span_of("finding relations", || {
+        timing::span_of("finding relations", || {
             for rel in doc.relations.values() {
-                let rel_tags = &rel.tags;
+                let relation = (rel.id, rel.tags.as_slice());
                 for member in &rel.members {
-                    if let &Member::Way(ref reference, _) = member {
+                    if let Member::Way(reference, role) = member {
                         let member = doc.resolve_reference(reference);
                         if let Reference::Way(way) = member {
                             collect_ways(
-                                Some(rel_tags),
+                                Some(relation),
                                 way,
                                 callback,
                                 &mut all_coords,
                                 &mut all_polys,
                                 &mut all_values,
-                                &doc,
+                                doc,
+                            );
+                            all_inner.resize(all_polys.len(), role == "inner");
+                            all_sources.resize(
+                                all_polys.len(),
+                                Source::new(ElementType::Way, way.id, Some(rel.id)),
                             );
                         }
                     }
@@ -78,7 +209,7 @@ impl Geometry {
             }
         });
 
-        flame::span_of("finding ways", || {
+        timing::span_of("finding ways", || {
             for way in doc.ways.values() {
                 collect_ways(
                     None,
@@ -87,14 +218,39 @@ impl Geometry {
                     &mut all_coords,
                     &mut all_polys,
                     &mut all_values,
-                    &doc,
+                    doc,
                 );
+                all_inner.resize(all_polys.len(), false);
+                all_sources.resize(all_polys.len(), Source::new(ElementType::Way, way.id, None));
+            }
+        });
+
+        timing::span_of("finding nodes", || {
+            for node in doc.nodes.values() {
+                let lon_lat = (node.lon, node.lat);
+                if node.tags.is_empty() || !is_projectable(lon_lat) {
+                    continue;
+                }
+                let feature = Feature {
+                    element: ElementType::Node,
+                    id: node.id,
+                    tags: &node.tags,
+                    relation: None,
+                };
+                if let Some(v) = callback(&feature, all_polys.len()) {
+                    all_values.push(v);
+                    all_polys.push(all_coords.len()..all_coords.len() + 1);
+                    all_coords.push((lon_lat, node.id));
+                    all_inner.push(false);
+                    all_sources.push(Source::new(ElementType::Node, node.id, None));
+                }
             }
         });
 
-        let all_coords = coord_convert(all_coords);
+        let (all_coords, all_node_ids): (Vec<_>, Vec<_>) = all_coords.into_iter().unzip();
+        let all_coords = project(all_coords);
 
-        (
+        Ok((
             Geometry {
                 bounds: Bounds {
                     min_lon: b_min_lon,
@@ -107,56 +263,92 @@ impl Geometry {
                     scale_y,
                 },
                 coords: all_coords,
+                node_ids: all_node_ids,
                 polys: all_polys,
+                inner: all_inner,
+                sources: all_sources,
             },
             all_values,
-        )
+        ))
     }
 }
 
+// Errors from either name the file, as a batch may be reading several.
+pub fn read_osm(path: &str) -> io::Result<OSM> {
+    let in_file = |e: &dyn std::fmt::Display| format!("{}: {}", path, e);
+    let f = File::open(path).map_err(|e| io::Error::new(e.kind(), in_file(&e)))?;
+    let br = BufReader::new(f);
+    timing::span_of("reading osm data", || OSM::parse(br))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, in_file(&e)))
+}
+
+pub fn parse_osm(data: &[u8]) -> io::Result<OSM> {
+    timing::span_of("reading osm data", || OSM::parse(data))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+// Equatorial radius used by `coord_convert`.
+const EARTH_RADIUS: f64 = 6_378_137.0;
+
 impl Bounds {
-    pub fn transform_lat_lon_to_screen_coordinate(&self, (lon, lat): (f64, f64)) -> (f64, f64) {
-        (
-            (lon - self.min_lon) * self.scale_x,
-            (lat - self.min_lat) * self.scale_y,
+    pub fn center(&self) -> Point {
+        Point::new(
+            (self.min_lon + self.max_lon) / 2.0,
+            (self.min_lat + self.max_lat) / 2.0,
         )
     }
-}
-pub fn simple_filterer<T, F>(f: F) -> impl Fn(&[Tag], &[Tag], RangeIdx) -> Option<T>
-where
-    F: Fn((&str, &str)) -> Option<fn(RangeIdx) -> T>,
-{
-    move |relationship_tags, way_tags, range| {
-        for &tags in &[relationship_tags, way_tags] {
-            for tag in tags {
-                let computed = f((&tag.key, &tag.val));
-                if computed.is_some() {
-                    return computed.map(|f| f(range));
-                }
-            }
-        }
-        None
+
+    // Mercator stretches distances by 1/cos(latitude), so a metre on the ground
+    // covers more than one projected unit away from the equator.
+    pub fn units_per_metre(&self) -> f64 {
+        let (_, latitude) = unproject(self.center());
+        1.0 / latitude.to_radians().cos()
+    }
+
+    pub fn transform_lat_lon_to_screen_coordinate(&self, point: Point) -> Point {
+        Point::new(
+            (point.x() - self.min_lon) * self.scale_x,
+            (point.y() - self.min_lat) * self.scale_y,
+        )
+    }
+
+    // The inverse of `transform_lat_lon_to_screen_coordinate`.
+    pub fn transform_screen_coordinate_to_lat_lon(&self, point: Point) -> Point {
+        Point::new(
+            point.x() / self.scale_x + self.min_lon,
+            point.y() / self.scale_y + self.min_lat,
+        )
     }
 }
+pub fn find_tag<'a>(tags: &'a [Tag], key: &str) -> Option<&'a str> {
+    tags.iter().find(|t| t.key == key).map(|t| t.val.as_str())
+}
+
 fn collect_ways<T>(
-    relationship_tags: Option<&[Tag]>,
+    relation: Option<(i64, &[Tag])>,
     way: &Way,
     callback: Callback<T>,
-    all_coords: &mut Vec<(f64, f64)>,
+    all_coords: &mut Vec<((f64, f64), i64)>,
     all_polys: &mut Vec<Range<usize>>,
     all_values: &mut Vec<T>,
     doc: &OSM,
 ) {
-    let tags = &way.tags;
     let start = all_coords.len();
-    let relationship_tags = relationship_tags.unwrap_or(&[]);
+    let feature = Feature {
+        element: ElementType::Way,
+        id: way.id,
+        tags: &way.tags,
+        relation,
+    };
 
-    if let Some(v) = callback(relationship_tags, tags, all_polys.len()) {
+    if let Some(v) = callback(&feature, all_polys.len()) {
         all_values.push(v);
         for node in &way.nodes {
             let node = doc.resolve_reference(node);
             if let Reference::Node(node) = node {
-                all_coords.push((node.lon, node.lat));
+                if is_projectable((node.lon, node.lat)) {
+                    all_coords.push(((node.lon, node.lat), node.id));
+                }
             }
         }
         let end = all_coords.len();
@@ -164,7 +356,81 @@ fn collect_ways<T>(
     }
 }
 
-#[flame]
+// Identifies the projection used by `coord_convert`, for anything which stores
+// projected coordinates.
+pub const PROJECTION: &str = "mercator/wgs84";
+
+// Whether a longitude and latitude can be projected.  NaN can't, and neither
+// can the poles, which Mercator sends to infinity.
+// `[min_lon, min_lat, max_lon, max_lat]` around the nodes which can be
+// projected, if they cover an area.
+fn node_extent(doc: &OSM) -> Option<[f64; 4]> {
+    let mut extent: Option<[f64; 4]> = None;
+    for node in doc.nodes.values() {
+        let (lon, lat) = (node.lon, node.lat);
+        if !is_projectable((lon, lat)) {
+            continue;
+        }
+        extent = Some(match extent {
+            Some([w, s, e, n]) => [w.min(lon), s.min(lat), e.max(lon), n.max(lat)],
+            None => [lon, lat, lon, lat],
+        });
+    }
+    extent.filter(|[w, s, e, n]| w < e && s < n)
+}
+
+pub fn is_projectable((lon, lat): (f64, f64)) -> bool {
+    lon.abs() <= 180.0 && lat.abs() < 90.0
+}
+
+// Projects coordinates which have passed `is_projectable`.
+pub fn project(lon_lats: Vec<(f64, f64)>) -> Vec<Point> {
+    coord_convert(lon_lats)
+        .into_iter()
+        .map(|(x, y)| Point::new(x, y))
+        .collect()
+}
+
+// Projects many lines at once.  Each call to `project` sets up its own thread
+// pool, so this is much quicker than projecting the lines one by one.
+pub fn project_lines(lines: Vec<Vec<(f64, f64)>>) -> Vec<Vec<Point>> {
+    let lens: Vec<_> = lines.iter().map(Vec::len).collect();
+    let mut points = project(lines.into_iter().flatten().collect()).into_iter();
+    lens.into_iter()
+        .map(|len| points.by_ref().take(len).collect())
+        .collect()
+}
+
+// proj5's Mercator northing for a latitude in radians, copied from
+// `MercatorSystem` (which bundles a slightly unusual eccentricity term) so
+// that `unproject` can invert exactly what `coord_convert` did.
+fn mercator_y(lat: f64) -> f64 {
+    let ellipsoid = WGS_1984_ELLIPSOID;
+    let temp = ellipsoid.b / ellipsoid.a;
+    let eccentricity = (1.0 - temp * temp).sqrt();
+    let con = eccentricity * lat.sin();
+    let con = (1.0 - con) / (1.0 + con).powf(0.5 * eccentricity);
+    let ts = (0.5 * (std::f64::consts::FRAC_PI_2 - lat)).tan() / con;
+    -ellipsoid.a * ts.ln()
+}
+
+// The longitude and latitude of a projected point; the inverse of `project`.
+// proj5's own inverse doesn't undo its forward projection, so the latitude is
+// found by Newton's method, using the spherical slope of a/cos(lat).
+pub fn unproject(point: Point) -> (f64, f64) {
+    let lon = (point.x() / EARTH_RADIUS).to_degrees();
+    let mut lat = 2.0 * (point.y() / EARTH_RADIUS).exp().atan() - std::f64::consts::FRAC_PI_2;
+    for _ in 0..50 {
+        let step = (mercator_y(lat) - point.y()) * lat.cos() / EARTH_RADIUS;
+        lat -= step;
+        if step.abs() < 1e-13 {
+            break;
+        }
+    }
+    (lon, lat.to_degrees())
+}
+
+#[cfg_attr(feature = "profiling", flame)]
 pub fn coord_convert(input: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
     let ellipsoid = WGS_1984_ELLIPSOID;
     //let system = UTMSystem { utm_zone: 10 };
@@ -174,3 +440,57 @@ pub fn coord_convert(input: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
     let out = system.from_lon_lat(input, &ellipsoid, &mut strategy);
     out.data
 }
+
+#[test]
+fn lines_are_projected_separately() {
+    let lines = vec![vec![(-74.0, 40.7), (-73.9, 40.8)], vec![], vec![(0.0, 0.0)]];
+    let projected = project_lines(lines.clone());
+    assert_eq!(3, projected.len());
+    for (line, projected) in lines.into_iter().zip(projected) {
+        assert_eq!(project(line), projected);
+    }
+}
+
+#[test]
+fn unproject_inverts_project() {
+    let lon_lats = vec![(-74.0, 40.7), (0.0, 0.0), (151.2, -33.9), (-179.0, 85.0)];
+    for (projected, (lon, lat)) in project(lon_lats.clone()).into_iter().zip(lon_lats) {
+        let (lon2, lat2) = unproject(projected);
+        assert!((lon - lon2).abs() < 1e-9 && (lat - lat2).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn tags_are_kept_from_ways_and_their_relations() {
+    let doc = parse_osm(
+        br#"<osm>
+  <bounds minlat="40.0" minlon="-74.0" maxlat="40.1" maxlon="-73.9"/>
+  <node id="1" lat="40.01" lon="-73.99"/>
+  <node id="2" lat="40.02" lon="-73.99"/>
+  <node id="3" lat="40.02" lon="-73.98"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="1"/></way>
+  <relation id="20">
+    <member type="way" ref="10" role="outer"/>
+    <tag k="building" v="yes"/>
+    <tag k="name" v="Hall"/>
+  </relation>
+  <node id="4" lat="40.05" lon="-73.95"><tag k="name" v="Stop"/></node>
+</osm>"#,
+    )
+    .unwrap();
+    let callback = |feature: &Feature, _| match feature.element {
+        ElementType::Way => feature.relation.map(|_| ()),
+        _ => Some(()),
+    };
+    let (mut geometry, kept) = Geometry::from_osm(&doc, &callback, 1000.0).unwrap();
+    assert_eq!(2, kept.len());
+    geometry.keep_tags(&doc, &["name".into(), "height".into()]);
+
+    let way = geometry.source(0).unwrap();
+    assert_eq!("way/10", way.name());
+    assert_eq!(Some(20), way.relation);
+    assert_eq!(vec![("name".to_string(), "Hall".to_string())], way.tags);
+    let node = geometry.source(1).unwrap();
+    assert_eq!(("node/4".to_string(), None), (node.name(), node.relation));
+    assert_eq!(vec![("name".to_string(), "Stop".to_string())], node.tags);
+}
@@ -0,0 +1,240 @@
+use linemath::{Cap, Join};
+use osm_load::{find_tag, Tag};
+use serde::{Deserialize, Serialize};
+
+const LANE_WIDTH: f64 = 3.5;
+const FEET_TO_METRES: f64 = 0.3048;
+
+fn default_width(class: &str) -> f64 {
+    match class {
+        "motorway" | "trunk" => 14.0,
+        "primary" => 11.0,
+        "secondary" => 9.0,
+        "tertiary" => 8.0,
+        "motorway_link" | "trunk_link" | "primary_link" | "secondary_link" | "tertiary_link" => 7.0,
+        "residential" | "unclassified" | "living_street" => 7.0,
+        "pedestrian" => 6.0,
+        "service" => 4.0,
+        "track" => 3.0,
+        "footway" | "path" | "cycleway" | "bridleway" | "steps" | "corridor" => 2.0,
+        _ => 5.0,
+    }
+}
+
+// Parses values like `8`, `8 m`, `12'`, `12 ft` or `12'6"` into metres.
+pub fn parse_width(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if let Some(idx) = value.find('\'') {
+        let feet: f64 = value[..idx].trim().parse().ok()?;
+        let inches = value[idx + 1..].trim().trim_end_matches('"');
+        let inches: f64 = if inches.is_empty() {
+            0.0
+        } else {
+            inches.trim().parse().ok()?
+        };
+        return Some((feet + inches / 12.0) * FEET_TO_METRES);
+    }
+    if let Some(feet) = value.strip_suffix("ft") {
+        return feet.trim().parse::<f64>().ok().map(|f| f * FEET_TO_METRES);
+    }
+    value.trim_end_matches('m').trim().parse().ok()
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Profile {
+    // Full width of the road surface in metres.
+    pub width: f64,
+    pub join: Join,
+    pub cap: Cap,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            width: default_width(""),
+            join: Join::Round,
+            cap: Cap::Round,
+        }
+    }
+}
+
+fn width(class: &str, tags: &[Tag]) -> f64 {
+    if let Some(width) = find_tag(tags, "width").and_then(parse_width) {
+        if width > 0.0 {
+            return width;
+        }
+    }
+    if let Some(lanes) = find_tag(tags, "lanes").and_then(|l| l.parse::<f64>().ok()) {
+        if lanes > 0.0 {
+            return lanes * LANE_WIDTH;
+        }
+    }
+    default_width(class)
+}
+
+// Describes how a road should be buffered, or None if the tags don't describe a road.
+pub fn profile(tags: &[Tag]) -> Option<Profile> {
+    let class = find_tag(tags, "highway")?;
+    let (join, cap) = match class {
+        // Highways are split into many ways which meet end to end, so rounded
+        // ends would bulge wherever two ways of different widths meet.
+        "motorway" | "trunk" | "motorway_link" | "trunk_link" => (Join::Miter(2.0), Cap::Butt),
+        "steps" => (Join::Miter(2.0), Cap::Square),
+        _ => (Join::Round, Cap::Round),
+    };
+    Some(Profile {
+        width: width(class, tags),
+        join,
+        cap,
+    })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    Foot,
+    Bicycle,
+    Car,
+}
+
+impl Mode {
+    pub fn by_name(name: &str) -> Option<Mode> {
+        match name {
+            "foot" | "walk" => Some(Mode::Foot),
+            "bicycle" | "bike" => Some(Mode::Bicycle),
+            "car" => Some(Mode::Car),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Oneway {
+    No,
+    // Only in the direction the way was drawn.
+    Forward,
+    Backward,
+}
+
+// Who may use a road, and which way.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Access {
+    pub foot: bool,
+    pub bicycle: bool,
+    pub car: bool,
+    // Applies to vehicles; people on foot can always walk either way.
+    pub oneway: Oneway,
+    // Whether cyclists are exempt from `oneway`.
+    pub contraflow: bool,
+}
+
+impl Access {
+    // Whether `mode` may travel along the way (`forward`) or against it.
+    pub fn allows(&self, mode: Mode, forward: bool) -> bool {
+        let direction_ok = match self.oneway {
+            Oneway::No => true,
+            Oneway::Forward => forward,
+            Oneway::Backward => !forward,
+        };
+        match mode {
+            Mode::Foot => self.foot,
+            Mode::Bicycle => self.bicycle && (direction_ok || self.contraflow),
+            Mode::Car => self.car && direction_ok,
+        }
+    }
+}
+
+// Reads an access value like `yes`, `designated` or `private`, or None if it
+// says nothing either way (`destination`, `unknown`, ...).
+fn allowed(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "designated" | "permissive" | "official" => Some(true),
+        "no" | "private" | "use_sidepath" | "discouraged" => Some(false),
+        _ => None,
+    }
+}
+
+// Who may use a road, from its class and its access and oneway tags.
+pub fn access(tags: &[Tag]) -> Access {
+    let class = find_tag(tags, "highway").unwrap_or("");
+    let (mut foot, mut bicycle, mut car) = match class {
+        "motorway" | "motorway_link" | "trunk" | "trunk_link" => (false, false, true),
+        "footway" | "pedestrian" | "steps" | "corridor" => (true, false, false),
+        "path" => (true, true, false),
+        "cycleway" => (false, true, false),
+        "bridleway" | "construction" | "proposed" => (false, false, false),
+        _ => (true, true, true),
+    };
+
+    // More specific tags override less specific ones.
+    let tag = |key| find_tag(tags, key).and_then(allowed);
+    if let Some(all) = tag("access") {
+        foot = all;
+        bicycle = all;
+        car = all;
+    }
+    if let Some(vehicle) = tag("vehicle") {
+        bicycle = vehicle;
+        car = vehicle;
+    }
+    foot = tag("foot").unwrap_or(foot);
+    bicycle = tag("bicycle").unwrap_or(bicycle);
+    car = tag("motor_vehicle").unwrap_or(car);
+    car = tag("motorcar").unwrap_or(car);
+
+    let oneway = match find_tag(tags, "oneway") {
+        Some("yes") | Some("true") | Some("1") => Oneway::Forward,
+        Some("-1") | Some("reverse") => Oneway::Backward,
+        Some(_) => Oneway::No,
+        None if find_tag(tags, "junction") == Some("roundabout") => Oneway::Forward,
+        None if class == "motorway" => Oneway::Forward,
+        None => Oneway::No,
+    };
+    let contraflow = find_tag(tags, "oneway:bicycle") == Some("no")
+        || find_tag(tags, "cycleway").is_some_and(|c| c.starts_with("opposite"));
+
+    Access {
+        foot,
+        bicycle,
+        car,
+        oneway,
+        contraflow,
+    }
+}
+
+#[cfg(test)]
+pub fn tags(pairs: &[(&str, &str)]) -> Vec<Tag> {
+    pairs
+        .iter()
+        .map(|&(key, val)| Tag {
+            key: key.into(),
+            val: val.into(),
+        })
+        .collect()
+}
+
+#[test]
+fn access_follows_the_most_specific_tag() {
+    let footway = access(&tags(&[("highway", "footway")]));
+    assert!(footway.allows(Mode::Foot, false) && !footway.allows(Mode::Car, true));
+
+    let private = access(&tags(&[
+        ("highway", "service"),
+        ("access", "private"),
+        ("foot", "yes"),
+    ]));
+    assert_eq!(
+        (true, false, false),
+        (private.foot, private.bicycle, private.car)
+    );
+
+    let oneway = access(&tags(&[
+        ("highway", "residential"),
+        ("oneway", "yes"),
+        ("oneway:bicycle", "no"),
+    ]));
+    assert!(oneway.allows(Mode::Car, true) && !oneway.allows(Mode::Car, false));
+    assert!(oneway.allows(Mode::Bicycle, false) && oneway.allows(Mode::Foot, false));
+
+    let roundabout = access(&tags(&[("highway", "primary"), ("junction", "roundabout")]));
+    assert_eq!(Oneway::Forward, roundabout.oneway);
+}
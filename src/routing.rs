@@ -0,0 +1,234 @@
+// Shortest paths over the street network, for drawing routes on the map
+// without an outside routing service.
+
+use linemath::Point;
+use network::Network;
+use roads::{Access, Mode};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// Waypoints further than this from any usable road are probably mistakes.
+const MAX_SNAP_METRES: f64 = 500.0;
+
+// Parses waypoints written as `lon,lat lon,lat ...`.
+pub fn parse_waypoints(text: &str) -> Result<Vec<(f64, f64)>, String> {
+    let waypoints = text
+        .split_whitespace()
+        .map(|pair| {
+            let mut parts = pair.split(',').map(|part| part.trim().parse::<f64>());
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(Ok(lon)), Some(Ok(lat)), None) => Ok((lon, lat)),
+                _ => Err(format!("expected `lon,lat`, found `{}`", pair)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if waypoints.len() < 2 {
+        return Err("a route needs at least two waypoints".into());
+    }
+    Ok(waypoints)
+}
+
+// The usable node nearest to `point`, if there is one close enough.
+fn snap(
+    network: &Network<Access>,
+    point: Point,
+    mode: Mode,
+    units_per_metre: f64,
+) -> Option<usize> {
+    let mut usable = vec![false; network.nodes.len()];
+    for edge in &network.edges {
+        if edge.data.allows(mode, true) || edge.data.allows(mode, false) {
+            usable[edge.from] = true;
+            usable[edge.to] = true;
+        }
+    }
+    let distance = |node: usize| {
+        let p = network.nodes[node].point;
+        (p.x() - point.x()).hypot(p.y() - point.y())
+    };
+    (0..network.nodes.len())
+        .filter(|&node| usable[node])
+        .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+        .filter(|&node| distance(node) / units_per_metre <= MAX_SNAP_METRES)
+}
+
+#[derive(PartialEq)]
+struct Visit {
+    metres: f64,
+    node: usize,
+}
+
+impl Eq for Visit {}
+
+// Reversed, so that the heap pops the nearest node first.
+impl Ord for Visit {
+    fn cmp(&self, other: &Visit) -> Ordering {
+        other.metres.total_cmp(&self.metres)
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Visit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Dijkstra's algorithm, returning the edges taken from `from` to `to` and
+// whether each was followed forwards.
+fn shortest_path(
+    network: &Network<Access>,
+    adjacency: &[Vec<(usize, usize)>],
+    from: usize,
+    to: usize,
+    mode: Mode,
+) -> Option<Vec<(usize, bool)>> {
+    let mut best = vec![f64::INFINITY; network.nodes.len()];
+    let mut came_by: Vec<Option<(usize, usize)>> = vec![None; network.nodes.len()];
+    let mut queue = BinaryHeap::new();
+    best[from] = 0.0;
+    queue.push(Visit {
+        metres: 0.0,
+        node: from,
+    });
+
+    while let Some(Visit { metres, node }) = queue.pop() {
+        if node == to {
+            break;
+        }
+        if metres > best[node] {
+            continue;
+        }
+        for &(edge, next) in &adjacency[node] {
+            let edge_data = &network.edges[edge];
+            let forward = edge_data.from == node;
+            if !edge_data.data.allows(mode, forward) {
+                continue;
+            }
+            let metres = metres + edge_data.length;
+            if metres < best[next] {
+                best[next] = metres;
+                came_by[next] = Some((edge, node));
+                queue.push(Visit { metres, node: next });
+            }
+        }
+    }
+
+    if best[to].is_infinite() {
+        return None;
+    }
+    let mut path = vec![];
+    let mut node = to;
+    while let Some((edge, previous)) = came_by[node] {
+        path.push((edge, network.edges[edge].from == previous));
+        node = previous;
+    }
+    path.reverse();
+    Some(path)
+}
+
+// The shortest route for `mode` through each of `waypoints` in turn, as a
+// polyline, and its length in metres.
+pub fn route(
+    network: &Network<Access>,
+    waypoints: &[Point],
+    mode: Mode,
+    units_per_metre: f64,
+) -> Result<(Vec<Point>, f64), String> {
+    let nodes = waypoints
+        .iter()
+        .enumerate()
+        .map(|(i, &point)| {
+            snap(network, point, mode, units_per_metre).ok_or_else(|| {
+                format!(
+                    "waypoint {} is more than {} m from a road usable by {:?}",
+                    i + 1,
+                    MAX_SNAP_METRES,
+                    mode
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let adjacency = network.adjacency();
+    let mut line = vec![network.nodes[nodes[0]].point];
+    let mut metres = 0.0;
+    for (i, pair) in nodes.windows(2).enumerate() {
+        let path = shortest_path(network, &adjacency, pair[0], pair[1], mode)
+            .ok_or_else(|| format!("no {:?} route from waypoint {} to {}", mode, i + 1, i + 2))?;
+        for (edge, forward) in path {
+            let edge = &network.edges[edge];
+            metres += edge.length;
+            if forward {
+                line.extend(edge.points.iter().skip(1));
+            } else {
+                line.extend(edge.points.iter().rev().skip(1));
+            }
+        }
+    }
+    Ok((line, metres))
+}
+
+#[cfg(test)]
+fn test_network(oneway: &[bool]) -> Network<Access> {
+    use network::test_geometry;
+    use roads::Oneway;
+
+    // A block with corners 0 (0, 0), 3 (3, 0), 5 (5, 2) and 1 (1, 1), and a
+    // footpath across it from 0 to 5.
+    let geometry = test_geometry(&[&[0, 3], &[3, 5], &[5, 1], &[1, 0], &[0, 5]]);
+    let road = |oneway| Access {
+        foot: true,
+        bicycle: true,
+        car: true,
+        oneway: if oneway { Oneway::Forward } else { Oneway::No },
+        contraflow: false,
+    };
+    let mut ways: Vec<_> = oneway.iter().map(|&o| road(o)).enumerate().collect();
+    ways.push((
+        4,
+        Access {
+            car: false,
+            ..road(false)
+        },
+    ));
+    Network::build(&geometry, ways)
+}
+
+#[cfg(test)]
+fn node_ids(network: &Network<Access>, line: &[Point]) -> Vec<i64> {
+    line.iter()
+        .map(|&p| network.nodes.iter().find(|n| n.point == p).unwrap().id)
+        .collect()
+}
+
+#[test]
+fn walkers_take_the_footpath() {
+    let network = test_network(&[false, false, false, false]);
+    let ends = [Point::new(0.0, 0.0), Point::new(5.0, 2.0)];
+    let (line, _) = route(&network, &ends, Mode::Foot, 1.0).unwrap();
+    assert_eq!(vec![0, 5], node_ids(&network, &line));
+    let (line, _) = route(&network, &ends, Mode::Car, 1.0).unwrap();
+    assert_eq!(vec![0, 1, 5], node_ids(&network, &line));
+}
+
+#[test]
+fn cars_go_around_oneway_streets() {
+    // 1 -> 0 may only be driven forwards.
+    let network = test_network(&[false, false, false, true]);
+    let waypoints = [Point::new(0.0, 0.0), Point::new(1.0, 1.0)];
+    let (line, metres) = route(&network, &waypoints, Mode::Car, 1.0).unwrap();
+    assert_eq!(vec![0, 3, 5, 1], node_ids(&network, &line));
+    assert!(metres > 9.0);
+    let (line, _) = route(&network, &waypoints, Mode::Foot, 1.0).unwrap();
+    assert_eq!(vec![0, 1], node_ids(&network, &line));
+}
+
+#[test]
+fn waypoints_must_be_near_a_road() {
+    let network = test_network(&[false, false, false, false]);
+    let far = [Point::new(0.0, 0.0), Point::new(5000.0, 1.0)];
+    assert!(route(&network, &far, Mode::Car, 1.0).is_err());
+    assert!(parse_waypoints("-74.0,40.7 -73.9,40.8").is_ok());
+    assert!(parse_waypoints("-74.0,40.7").is_err());
+    assert!(parse_waypoints("-74.0 40.7").is_err());
+}
@@ -0,0 +1,509 @@
+// Tag matching rules, loaded from a rules file.
+//
+// Each non-empty line that isn't a `#` comment is a rule:
+//
+//     <layer> [priority <n>] [on <node|way|relation>,...] : <condition>
+//
+// A condition is made of tag patterns combined with `&` (and), `|` (or), `!`
+// (not) and parentheses.  A pattern is `key`, `key=value` or `key!=value`;
+// keys and values may use `*` as a wildcard, so `highway` and `highway=*`
+// both mean "has a highway tag".  For example:
+//
+//     road priority 10 : highway=* & !area=yes
+//     park : leisure=park | leisure=garden
+//     station on node : railway=station | public_transport=station
+//
+// Rules apply to ways and relations unless they say otherwise.  When several
+// rules match a feature, the one with the highest priority wins, and ties go to
+// the rule which comes first in the file.  A way reached through a relation may
+// match on the relation's tags too, and those win ties with its own.
+//
+// A comment starts at a `#` which begins a line or follows whitespace, so
+// values such as `colour=#0039A6` can still be written.
+
+use osm_load::{ElementType, Feature, Tag};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Pattern {
+    Any,
+    Exact(String),
+    // The literal pieces between `*`s.
+    Glob(Vec<String>),
+}
+
+impl Pattern {
+    fn new(text: &str) -> Pattern {
+        if text == "*" {
+            Pattern::Any
+        } else if text.contains('*') {
+            Pattern::Glob(text.split('*').map(String::from).collect())
+        } else {
+            Pattern::Exact(text.into())
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Exact(exact) => exact == text,
+            Pattern::Glob(pieces) => {
+                let (first, last) = (&pieces[0], &pieces[pieces.len() - 1]);
+                if !text.starts_with(first.as_str()) || text.len() < first.len() + last.len() {
+                    return false;
+                }
+                let mut rest = &text[first.len()..];
+                for piece in &pieces[1..pieces.len() - 1] {
+                    match rest.find(piece.as_str()) {
+                        Some(idx) => rest = &rest[idx + piece.len()..],
+                        None => return false,
+                    }
+                }
+                rest.ends_with(last.as_str())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Tag { key: Pattern, value: Pattern },
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    fn matches(&self, tags: &[Tag]) -> bool {
+        match self {
+            Condition::Tag { key, value } => tags
+                .iter()
+                .any(|tag| key.matches(&tag.key) && value.matches(&tag.val)),
+            Condition::Not(inner) => !inner.matches(tags),
+            Condition::And(all) => all.iter().all(|c| c.matches(tags)),
+            Condition::Or(any) => any.iter().any(|c| c.matches(tags)),
+        }
+    }
+
+    // Keys of which at least one must be present for the condition to match, or
+    // None if it could match without any particular key.
+    fn required_keys(&self) -> Option<Vec<String>> {
+        match self {
+            Condition::Tag {
+                key: Pattern::Exact(key),
+                ..
+            } => Some(vec![key.clone()]),
+            Condition::Tag { .. } | Condition::Not(_) => None,
+            Condition::And(all) => all
+                .iter()
+                .filter_map(|c| c.required_keys())
+                .min_by_key(|keys| keys.len()),
+            Condition::Or(any) => {
+                let mut keys = vec![];
+                for c in any {
+                    keys.extend(c.required_keys()?);
+                }
+                Some(keys)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    // Where the rule came from, e.g. `default.rules:12`.
+    pub name: String,
+    pub layer: String,
+    pub priority: i64,
+    elements: Vec<ElementType>,
+    condition: Condition,
+}
+
+pub struct RuleSet {
+    // Sorted by priority (highest first), then by position in the file.
+    rules: Vec<Rule>,
+    by_key: HashMap<String, Vec<usize>>,
+    unindexed: Vec<usize>,
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+}
+
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let is_operator = c == '(' || c == ')' || c == '&' || c == '|';
+        let is_not = c == '!' && !text[i..].starts_with("!=");
+        let after_bang = i > 0 && text[..i].ends_with('!') && c == '=';
+        if (c.is_whitespace() || is_operator || is_not) && !after_bang {
+            if let Some(s) = start.take() {
+                tokens.push(&text[s..i]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&text[i..i + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&text[s..]);
+    }
+    tokens
+}
+
+fn strip_comment(line: &str) -> &str {
+    let comment = line
+        .char_indices()
+        .find(|&(i, c)| c == '#' && (i == 0 || line[..i].ends_with(char::is_whitespace)));
+    match comment {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut any = vec![self.and()?];
+        while self.peek() == Some("|") {
+            self.next();
+            any.push(self.and()?);
+        }
+        Ok(if any.len() == 1 {
+            any.pop().unwrap()
+        } else {
+            Condition::Or(any)
+        })
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut all = vec![self.not()?];
+        while self.peek() == Some("&") {
+            self.next();
+            all.push(self.not()?);
+        }
+        Ok(if all.len() == 1 {
+            all.pop().unwrap()
+        } else {
+            Condition::And(all)
+        })
+    }
+
+    fn not(&mut self) -> Result<Condition, String> {
+        match self.next() {
+            Some("!") => Ok(Condition::Not(Box::new(self.not()?))),
+            Some("(") => {
+                let inner = self.or()?;
+                match self.next() {
+                    Some(")") => Ok(inner),
+                    _ => Err("expected `)`".into()),
+                }
+            }
+            Some(token) if token == ")" || token == "&" || token == "|" => {
+                Err(format!("unexpected `{}`", token))
+            }
+            Some(token) => tag_pattern(token),
+            None => Err("unexpected end of condition".into()),
+        }
+    }
+}
+
+fn tag_pattern(token: &str) -> Result<Condition, String> {
+    let (key, value, negated) = if let Some(idx) = token.find("!=") {
+        (&token[..idx], &token[idx + 2..], true)
+    } else if let Some(idx) = token.find('=') {
+        (&token[..idx], &token[idx + 1..], false)
+    } else {
+        (token, "*", false)
+    };
+    if key.is_empty() || value.is_empty() {
+        return Err(format!("invalid tag pattern `{}`", token));
+    }
+    let tag = Condition::Tag {
+        key: Pattern::new(key),
+        value: Pattern::new(value),
+    };
+    Ok(if negated {
+        Condition::Not(Box::new(tag))
+    } else {
+        tag
+    })
+}
+
+fn parse_rule(source: &str, line: &str) -> Result<Rule, String> {
+    let colon = line.find(':').ok_or("expected `:` before the condition")?;
+    let mut header = line[..colon].split_whitespace();
+    let layer = header.next().ok_or("missing layer name")?.to_string();
+    let mut priority = 0;
+    let mut elements = vec![ElementType::Way, ElementType::Relation];
+    while let Some(word) = header.next() {
+        let argument = header
+            .next()
+            .ok_or_else(|| format!("`{}` needs an argument", word))?;
+        match word {
+            "priority" => {
+                priority = argument
+                    .parse()
+                    .map_err(|_| format!("invalid priority `{}`", argument))?
+            }
+            "on" => {
+                elements = argument
+                    .split(',')
+                    .map(|element| match element {
+                        "node" => Ok(ElementType::Node),
+                        "way" => Ok(ElementType::Way),
+                        "relation" => Ok(ElementType::Relation),
+                        other => Err(format!("unknown element type `{}`", other)),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            other => return Err(format!("unknown option `{}`", other)),
+        }
+    }
+
+    let mut parser = Parser {
+        tokens: tokenize(&line[colon + 1..]),
+        position: 0,
+    };
+    let condition = parser.or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected `{}`", token));
+    }
+
+    Ok(Rule {
+        name: source.into(),
+        layer,
+        priority,
+        elements,
+        condition,
+    })
+}
+
+impl RuleSet {
+    // `name` identifies the rules in error messages and `Rule::name`.
+    pub fn parse(name: &str, text: &str) -> Result<RuleSet, String> {
+        let mut rules = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let source = format!("{}:{}", name, i + 1);
+            let rule = parse_rule(&source, line).map_err(|e| format!("{}: {}", source, e))?;
+            rules.push(rule);
+        }
+        // A stable sort keeps rules with equal priority in file order.
+        rules.sort_by_key(|rule| -rule.priority);
+
+        let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut unindexed = vec![];
+        for (i, rule) in rules.iter().enumerate() {
+            match rule.condition.required_keys() {
+                Some(keys) => {
+                    for key in keys {
+                        by_key.entry(key).or_default().push(i);
+                    }
+                }
+                None => unindexed.push(i),
+            }
+        }
+
+        Ok(RuleSet {
+            rules,
+            by_key,
+            unindexed,
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    // The best rule for one set of tags, as an index into `rules`.
+    fn best(&self, element: ElementType, tags: &[Tag]) -> Option<usize> {
+        let mut candidates = self.unindexed.clone();
+        for tag in tags {
+            if let Some(rules) = self.by_key.get(&tag.key) {
+                candidates.extend(rules);
+            }
+        }
+        candidates.sort();
+        candidates.dedup();
+        candidates.into_iter().find(|&i| {
+            let rule = &self.rules[i];
+            rule.elements.contains(&element) && rule.condition.matches(tags)
+        })
+    }
+
+    // The highest priority rule matching either the feature or the relation it
+    // was reached through.  Between equal priorities the relation wins, as its
+    // members are also offered on their own: a multipolygon's outer way tagged
+    // `highway` is a road by itself, but through the relation it's a park.
+    pub fn find(&self, feature: &Feature) -> Option<&Rule> {
+        let own = self.best(feature.element, feature.tags);
+        let relation = feature
+            .relation
+            .and_then(|(_, tags)| self.best(ElementType::Relation, tags));
+        let best = match (own, relation) {
+            (Some(a), Some(b)) if self.rules[a].priority > self.rules[b].priority => Some(a),
+            (a, b) => b.or(a),
+        };
+        best.map(|i| &self.rules[i])
+    }
+}
+
+#[cfg(test)]
+fn tags(pairs: &[(&str, &str)]) -> Vec<Tag> {
+    pairs
+        .iter()
+        .map(|&(key, val)| Tag {
+            key: key.into(),
+            val: val.into(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn way(tags: &[Tag]) -> Feature<'_> {
+    Feature {
+        element: ElementType::Way,
+        id: 1,
+        tags,
+        relation: None,
+    }
+}
+
+#[test]
+fn glob_patterns() {
+    assert!(Pattern::new("*").matches("anything"));
+    assert!(Pattern::new("addr:*").matches("addr:street"));
+    assert!(!Pattern::new("addr:*").matches("name"));
+    assert!(Pattern::new("*_link").matches("motorway_link"));
+    assert!(Pattern::new("a*b*c").matches("a-b-c"));
+    assert!(!Pattern::new("a*b*c").matches("a-c"));
+    assert!(!Pattern::new("ab*ba").matches("aba"));
+}
+
+#[test]
+fn keys_are_not_ignored() {
+    let rules = RuleSet::parse("test", "park : leisure=park").unwrap();
+    assert_eq!(
+        "park",
+        rules
+            .find(&way(&tags(&[("leisure", "park")])))
+            .unwrap()
+            .layer
+    );
+    assert!(rules.find(&way(&tags(&[("disused", "park")]))).is_none());
+}
+
+#[test]
+fn priority_beats_tag_order() {
+    let text = "
+        road : highway=*
+        park priority 5 : leisure=park
+    ";
+    let rules = RuleSet::parse("test", text).unwrap();
+    let feature = tags(&[("highway", "footway"), ("leisure", "park")]);
+    assert_eq!("park", rules.find(&way(&feature)).unwrap().layer);
+}
+
+#[test]
+fn ties_go_to_the_first_rule() {
+    let text = "
+        a : highway=*
+        b : highway=residential
+    ";
+    let rules = RuleSet::parse("test", text).unwrap();
+    let rule = rules
+        .find(&way(&tags(&[("highway", "residential")])))
+        .unwrap();
+    assert_eq!("a", rule.layer);
+    assert_eq!("test:2", rule.name);
+}
+
+#[test]
+fn negation_and_combinations() {
+    let text = "road : highway & !area=yes & (lanes!=1 | oneway=no)";
+    let rules = RuleSet::parse("test", text).unwrap();
+    assert!(rules.find(&way(&tags(&[("highway", "primary")]))).is_some());
+    assert!(rules
+        .find(&way(&tags(&[("highway", "primary"), ("area", "yes")])))
+        .is_none());
+    assert!(rules
+        .find(&way(&tags(&[("highway", "primary"), ("lanes", "1")])))
+        .is_none());
+    assert!(rules
+        .find(&way(&tags(&[
+            ("highway", "primary"),
+            ("lanes", "1"),
+            ("oneway", "no")
+        ])))
+        .is_some());
+}
+
+#[test]
+fn unindexed_rules_still_match() {
+    let rules = RuleSet::parse("test", "untagged : !name").unwrap();
+    assert!(rules.find(&way(&tags(&[("highway", "primary")]))).is_some());
+    assert!(rules.find(&way(&tags(&[("name", "Main St")]))).is_none());
+}
+
+#[test]
+fn element_types() {
+    let rules = RuleSet::parse("test", "station on node : railway=station").unwrap();
+    let station = tags(&[("railway", "station")]);
+    assert!(rules.find(&way(&station)).is_none());
+    let node = Feature {
+        element: ElementType::Node,
+        ..way(&station)
+    };
+    assert!(rules.find(&node).is_some());
+}
+
+#[test]
+fn relation_tags_are_considered() {
+    let rules = RuleSet::parse("test", "water : natural=water\nroad : highway").unwrap();
+    let relation = tags(&[("type", "multipolygon"), ("natural", "water")]);
+    let member = tags(&[("highway", "footway")]);
+    let feature = Feature {
+        relation: Some((2, &relation)),
+        ..way(&member)
+    };
+    assert_eq!("water", rules.find(&feature).unwrap().layer);
+    // On its own, the member is still a road.
+    assert_eq!("road", rules.find(&way(&member)).unwrap().layer);
+}
+
+#[test]
+fn hashes_inside_values_are_not_comments() {
+    let text = "# Subway lines\nsubway : colour=#0039A6 # the blue line\n  # indented";
+    let rules = RuleSet::parse("test", text).unwrap();
+    assert_eq!(1, rules.rules().len());
+    assert!(rules.find(&way(&tags(&[("colour", "#0039A6")]))).is_some());
+    assert!(rules.find(&way(&tags(&[("colour", "#0039A6 ")]))).is_none());
+}
+
+#[test]
+fn parse_errors_name_the_line() {
+    let err = RuleSet::parse("test", "# comment\nroad : highway & (")
+        .err()
+        .unwrap();
+    assert!(err.starts_with("test:2:"), "{}", err);
+    assert!(RuleSet::parse("test", "road highway").is_err());
+    assert!(RuleSet::parse("test", "road priority x : highway").is_err());
+    assert!(RuleSet::parse("test", "road on area : highway").is_err());
+}
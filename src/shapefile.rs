@@ -0,0 +1,437 @@
+// ESRI Shapefiles, as much municipal open data ships: shapes from `.shp`
+// (found through the `.shx` index when there is one), attributes from `.dbf`
+// and the coordinate system from `.prj`.  Records come out as GeoJSON
+// features, with the attributes as their properties.
+
+use crs::Crs;
+use geojson::{Coordinate, Feature, Geojson, Geometry};
+use serde_json::{Map, Number, Value};
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A file next to `shp` with another extension, in either case.
+fn sibling(shp: &Path, extension: &str) -> Option<PathBuf> {
+    [extension.to_lowercase(), extension.to_uppercase()]
+        .iter()
+        .map(|extension| shp.with_extension(extension))
+        .find(|path| path.exists())
+}
+
+fn invalid(path: &Path, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    )
+}
+
+// The features of a Shapefile, and the coordinate system its `.prj` declares.
+pub fn from_file(path: &str) -> io::Result<(Geojson, Option<Crs>)> {
+    let shp_path = Path::new(path);
+    let read_sibling = |extension| sibling(shp_path, extension).map(fs::read).transpose();
+    let shp = fs::read(shp_path)?;
+    let shx = read_sibling("shx")?;
+    let dbf = read_sibling("dbf")?;
+    let crs = match read_sibling("prj")? {
+        Some(prj) => Some(
+            Crs::from_wkt(&String::from_utf8_lossy(&prj))
+                .map_err(|message| invalid(&shp_path.with_extension("prj"), message))?,
+        ),
+        None => None,
+    };
+
+    let features =
+        read(&shp, shx.as_deref(), dbf.as_deref()).map_err(|message| invalid(shp_path, message))?;
+    println!("Loaded {}: {} features", path, features.len());
+    Ok((Geojson::new(features), crs))
+}
+
+// Turns the contents of the three files into features.  Null shapes and
+// deleted rows are skipped.
+pub fn read(shp: &[u8], shx: Option<&[u8]>, dbf: Option<&[u8]>) -> Result<Vec<Feature>, String> {
+    if be_i32(shp, 0)? != 9994 {
+        return Err("not a shapefile".into());
+    }
+    let records = match shx {
+        Some(shx) => index_records(shx)?,
+        None => scan_records(shp)?,
+    };
+    let attributes = match dbf {
+        Some(dbf) => read_dbf(dbf)?,
+        None => vec![Some(Map::new()); records.len()],
+    };
+    if attributes.len() != records.len() {
+        return Err(format!(
+            "{} shapes but {} attribute rows",
+            records.len(),
+            attributes.len()
+        ));
+    }
+
+    let mut features = vec![];
+    for ((offset, len), properties) in records.into_iter().zip(attributes) {
+        let content = shp
+            .get(offset + 8..offset + 8 + len)
+            .ok_or("record runs past the end of the file")?;
+        if let (Some(geometry), Some(properties)) = (read_shape(content)?, properties) {
+            features.push(Feature {
+                geometry,
+                properties: Value::Object(properties),
+            });
+        }
+    }
+    Ok(features)
+}
+
+fn bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], String> {
+    data.get(offset..offset + N)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or_else(|| format!("truncated at byte {}", offset))
+}
+
+fn be_i32(data: &[u8], offset: usize) -> Result<i32, String> {
+    bytes(data, offset).map(i32::from_be_bytes)
+}
+
+fn le_i32(data: &[u8], offset: usize) -> Result<i32, String> {
+    bytes(data, offset).map(i32::from_le_bytes)
+}
+
+fn le_f64(data: &[u8], offset: usize) -> Result<f64, String> {
+    bytes(data, offset).map(f64::from_le_bytes)
+}
+
+fn count(data: &[u8], offset: usize) -> Result<usize, String> {
+    usize::try_from(le_i32(data, offset)?).map_err(|_| format!("negative count at byte {}", offset))
+}
+
+// A size in bytes, from one stored as a number of 16-bit words.
+fn words(data: &[u8], offset: usize) -> Result<usize, String> {
+    usize::try_from(be_i32(data, offset)?)
+        .ok()
+        .and_then(|words| words.checked_mul(2))
+        .ok_or_else(|| format!("invalid offset or length at byte {}", offset))
+}
+
+// Record offsets and content lengths in bytes, from the index...
+fn index_records(shx: &[u8]) -> Result<Vec<(usize, usize)>, String> {
+    (100..shx.len())
+        .step_by(8)
+        .map(|entry| Ok((words(shx, entry)?, words(shx, entry + 4)?)))
+        .collect()
+}
+
+// ...or by walking the records when there's no index.
+fn scan_records(shp: &[u8]) -> Result<Vec<(usize, usize)>, String> {
+    let mut records = vec![];
+    let mut offset = 100;
+    while offset + 8 <= shp.len() {
+        let len = words(shp, offset + 4)?;
+        records.push((offset, len));
+        offset += 8 + len;
+    }
+    Ok(records)
+}
+
+fn read_points(content: &[u8], offset: usize, n: usize) -> Result<Vec<Coordinate>, String> {
+    (0..n)
+        .map(|i| {
+            let at = offset + 16 * i;
+            Ok([le_f64(content, at)?, le_f64(content, at + 8)?])
+        })
+        .collect()
+}
+
+// The points of each part of a polyline or polygon.
+fn read_parts(content: &[u8]) -> Result<Vec<Vec<Coordinate>>, String> {
+    let num_parts = count(content, 36)?;
+    let num_points = count(content, 40)?;
+    let starts = (0..num_parts)
+        .map(|i| count(content, 44 + 4 * i))
+        .collect::<Result<Vec<_>, _>>()?;
+    let points = read_points(content, 44 + 4 * num_parts, num_points)?;
+    let mut parts = vec![];
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).cloned().unwrap_or(num_points);
+        parts.push(points.get(start..end).ok_or("bad part index")?.to_vec());
+    }
+    Ok(parts)
+}
+
+fn signed_area(ring: &[Coordinate]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0][0] * w[1][1] - w[1][0] * w[0][1])
+        .sum::<f64>()
+        / 2.0
+}
+
+fn contains(ring: &[Coordinate], [x, y]: Coordinate) -> bool {
+    let mut inside = false;
+    for w in ring.windows(2) {
+        let ([x1, y1], [x2, y2]) = (w[0], w[1]);
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Shapefile polygons are a flat list of rings: clockwise outer rings and
+// counter-clockwise holes, each hole inside one of the outer rings.
+fn group_rings(rings: Vec<Vec<Coordinate>>) -> Vec<Vec<Vec<Coordinate>>> {
+    let (outers, holes): (Vec<_>, Vec<_>) = rings
+        .into_iter()
+        .filter(|ring| ring.len() > 2)
+        .partition(|ring| signed_area(ring) <= 0.0);
+    let mut polygons: Vec<Vec<Vec<Coordinate>>> = outers.into_iter().map(|r| vec![r]).collect();
+    for hole in holes {
+        match polygons.iter_mut().find(|p| contains(&p[0], hole[0])) {
+            Some(polygon) => polygon.push(hole),
+            // A hole in nothing is more likely an outer ring drawn the wrong way.
+            None => polygons.push(vec![hole]),
+        }
+    }
+    polygons
+}
+
+fn read_shape(content: &[u8]) -> Result<Option<Geometry>, String> {
+    // The Z and M variants of each type add measurements after the X and Y
+    // values, which are laid out the same.
+    Ok(Some(match le_i32(content, 0)? {
+        0 => return Ok(None),
+        1 | 11 | 21 => Geometry::Point {
+            coordinates: [le_f64(content, 4)?, le_f64(content, 12)?],
+        },
+        8 | 18 | 28 => Geometry::MultiPoint {
+            coordinates: read_points(content, 40, count(content, 36)?)?,
+        },
+        3 | 13 | 23 => {
+            let mut parts = read_parts(content)?;
+            if parts.len() == 1 {
+                Geometry::LineString {
+                    coordinates: parts.remove(0),
+                }
+            } else {
+                Geometry::MultiLineString { coordinates: parts }
+            }
+        }
+        5 | 15 | 25 => {
+            let mut polygons = group_rings(read_parts(content)?);
+            if polygons.len() == 1 {
+                Geometry::Polygon {
+                    coordinates: polygons.remove(0),
+                }
+            } else {
+                Geometry::MultiPolygon {
+                    coordinates: polygons,
+                }
+            }
+        }
+        other => return Err(format!("unsupported shape type {}", other)),
+    }))
+}
+
+// DBF text is usually Latin-1, but newer files are often UTF-8.
+fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn read_value(kind: u8, raw: &[u8]) -> Value {
+    let text = decode(raw);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    match kind {
+        b'N' | b'F' => {
+            if let Ok(n) = text.parse::<i64>() {
+                Value::from(n)
+            } else {
+                text.parse::<f64>()
+                    .ok()
+                    .and_then(Number::from_f64)
+                    .map_or(Value::Null, Value::Number)
+            }
+        }
+        b'L' => match text {
+            "T" | "t" | "Y" | "y" => Value::Bool(true),
+            "F" | "f" | "N" | "n" => Value::Bool(false),
+            _ => Value::Null,
+        },
+        // `YYYYMMDD`.
+        b'D' if text.len() == 8 && text.bytes().all(|b| b.is_ascii_digit()) => {
+            Value::String(format!("{}-{}-{}", &text[..4], &text[4..6], &text[6..]))
+        }
+        _ if text.is_empty() => Value::Null,
+        _ => Value::String(text.to_string()),
+    }
+}
+
+// The rows of a dBase table, or None for deleted rows.
+fn read_dbf(dbf: &[u8]) -> Result<Vec<Option<Map<String, Value>>>, String> {
+    let num_records = u32::from_le_bytes(bytes(dbf, 4)?) as usize;
+    let header_len = u16::from_le_bytes(bytes(dbf, 8)?) as usize;
+    let record_len = u16::from_le_bytes(bytes(dbf, 10)?) as usize;
+    // Every row starts with its deletion flag.
+    if record_len == 0 {
+        return Err("attribute table rows are empty".into());
+    }
+
+    // (name, type, length) of each field.
+    let mut fields = vec![];
+    let mut at = 32;
+    while at + 32 <= header_len && dbf.get(at) != Some(&0x0d) {
+        let field = dbf
+            .get(at..at + 32)
+            .ok_or("attribute table header is truncated")?;
+        let name_len = field[..11].iter().position(|&b| b == 0).unwrap_or(11);
+        fields.push((decode(&field[..name_len]), field[11], field[16] as usize));
+        at += 32;
+    }
+
+    (0..num_records)
+        .map(|i| {
+            let start = header_len + i * record_len;
+            let record = dbf
+                .get(start..start + record_len)
+                .ok_or("attribute table is truncated")?;
+            if record[0] == b'*' {
+                return Ok(None);
+            }
+            let mut row = Map::new();
+            let mut offset = 1;
+            for (name, kind, len) in &fields {
+                let raw = record
+                    .get(offset..offset + len)
+                    .ok_or("field runs past its row")?;
+                row.insert(name.clone(), read_value(*kind, raw));
+                offset += len;
+            }
+            Ok(Some(row))
+        })
+        .collect()
+}
+
+// Builders for small test files.
+#[cfg(test)]
+fn test_shp(shapes: &[Vec<u8>]) -> Vec<u8> {
+    let mut shp = vec![0; 100];
+    shp[..4].copy_from_slice(&9994i32.to_be_bytes());
+    for (i, content) in shapes.iter().enumerate() {
+        shp.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+        shp.extend_from_slice(&(content.len() as i32 / 2).to_be_bytes());
+        shp.extend_from_slice(content);
+    }
+    let len = shp.len() as i32 / 2;
+    shp[24..28].copy_from_slice(&len.to_be_bytes());
+    shp
+}
+
+#[cfg(test)]
+fn test_shape(kind: i32, parts: &[&[(f64, f64)]]) -> Vec<u8> {
+    let mut content = kind.to_le_bytes().to_vec();
+    content.extend_from_slice(&[0; 32]);
+    content.extend_from_slice(&(parts.len() as i32).to_le_bytes());
+    let num_points: usize = parts.iter().map(|p| p.len()).sum();
+    content.extend_from_slice(&(num_points as i32).to_le_bytes());
+    let mut start = 0;
+    for part in parts {
+        content.extend_from_slice(&(start as i32).to_le_bytes());
+        start += part.len();
+    }
+    for &(x, y) in parts.iter().flat_map(|p| p.iter()) {
+        content.extend_from_slice(&x.to_le_bytes());
+        content.extend_from_slice(&y.to_le_bytes());
+    }
+    content
+}
+
+#[cfg(test)]
+fn test_dbf(fields: &[(&str, u8, usize)], rows: &[&[&str]]) -> Vec<u8> {
+    let record_len = 1 + fields.iter().map(|f| f.2).sum::<usize>();
+    let header_len = 32 + 32 * fields.len() + 1;
+    let mut dbf = vec![3];
+    dbf.extend_from_slice(&[0; 3]);
+    dbf.extend_from_slice(&(rows.len() as u32).to_le_bytes());
+    dbf.extend_from_slice(&(header_len as u16).to_le_bytes());
+    dbf.extend_from_slice(&(record_len as u16).to_le_bytes());
+    dbf.resize(32, 0);
+    for &(name, kind, len) in fields {
+        let mut field = [0u8; 32];
+        field[..name.len()].copy_from_slice(name.as_bytes());
+        field[11] = kind;
+        field[16] = len as u8;
+        dbf.extend_from_slice(&field);
+    }
+    dbf.push(0x0d);
+    for row in rows {
+        dbf.push(b' ');
+        for (value, &(_, _, len)) in row.iter().zip(fields) {
+            dbf.extend_from_slice(format!("{:<width$}", value, width = len).as_bytes());
+        }
+    }
+    dbf
+}
+
+#[test]
+fn polygons_lines_and_attributes() {
+    let square = [
+        (0.0, 0.0),
+        (0.0, 10.0),
+        (10.0, 10.0),
+        (10.0, 0.0),
+        (0.0, 0.0),
+    ];
+    let hole = [(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0), (4.0, 4.0)];
+    let shp = test_shp(&[
+        test_shape(5, &[&square, &hole]),
+        test_shape(3, &[&[(0.0, 0.0), (1.0, 1.0)], &[(2.0, 2.0), (3.0, 3.0)]]),
+        0i32.to_le_bytes().to_vec(),
+    ]);
+    let dbf = test_dbf(
+        &[("NAME", b'C', 8), ("FLOORS", b'N', 4), ("BUILT", b'D', 8)],
+        &[
+            &["Pier 17", "3", "20180725"],
+            &["Ramp", "", ""],
+            &["", "", ""],
+        ],
+    );
+
+    let features = read(&shp, None, Some(&dbf)).unwrap();
+    assert_eq!(2, features.len());
+    match &features[0].geometry {
+        Geometry::Polygon { coordinates } => assert_eq!(2, coordinates.len()),
+        _ => panic!("expected a polygon"),
+    }
+    match &features[1].geometry {
+        Geometry::MultiLineString { coordinates } => assert_eq!(2, coordinates.len()),
+        _ => panic!("expected lines"),
+    }
+    let properties = &features[0].properties;
+    assert_eq!("Pier 17", properties["NAME"]);
+    assert_eq!(3, properties["FLOORS"]);
+    assert_eq!("2018-07-25", properties["BUILT"]);
+    assert_eq!(Value::Null, features[1].properties["FLOORS"]);
+}
+
+#[test]
+fn corrupt_files_are_errors() {
+    let shp = test_shp(&[test_shape(3, &[&[(0.0, 0.0), (1.0, 1.0)]])]);
+    let mut shx = shp[..100].to_vec();
+    shx.extend_from_slice(&(-50i32).to_be_bytes());
+    shx.extend_from_slice(&i32::MIN.to_be_bytes());
+    assert!(read(&shp, Some(&shx), None).is_err());
+
+    let mut negative = shp.clone();
+    negative[104..108].copy_from_slice(&(-1i32).to_be_bytes());
+    assert!(read(&negative, None, None).is_err());
+
+    // A header which claims more fields than the file holds.
+    let dbf = test_dbf(&[("NAME", b'C', 8)], &[&["Pier 17"]]);
+    assert!(read(&shp, None, Some(&dbf[..40])).is_err());
+
+    let mut empty_rows = dbf.clone();
+    empty_rows[10..12].copy_from_slice(&0u16.to_le_bytes());
+    assert!(read(&shp, None, Some(&empty_rows)).is_err());
+}
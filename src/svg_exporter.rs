@@ -1,19 +1,52 @@
+use linemath::Point;
 use osm_load::Bounds;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::io::Write;
 
 enum Element {
-    LineSegment { points: Vec<(f64, f64)> },
-    Polygon { points: Vec<(f64, f64)> },
+    LineSegment {
+        points: Vec<Point>,
+    },
+    Polygon {
+        points: Vec<Point>,
+    },
+    // An outer ring followed by its holes.
+    PolygonWithHoles {
+        rings: Vec<Vec<Point>>,
+    },
+    Point {
+        center: Point,
+        radius: f64,
+    },
+    // Rotated anticlockwise by `angle` degrees about its position.
+    Text {
+        position: Point,
+        angle: f64,
+        text: String,
+    },
+    // Stretched over the whole map.
+    Image {
+        href: String,
+    },
+}
+
+// An element and any attributes written alongside its class.
+struct Drawn {
+    element: Element,
+    attributes: Vec<(String, String)>,
 }
 
 pub struct Svg<T: Hash + Eq> {
     bounds: Bounds,
-    layers: HashMap<T, Vec<Element>>,
+    layers: HashMap<T, Vec<Drawn>>,
     styles: HashMap<T, (String, String)>,
-    clippings: HashMap<T, T>,
+    patterns: Vec<String>,
+    // Drawn over the map in SVG units, such as a legend or scale bar.
+    furniture: Vec<String>,
     background_color: Option<String>,
+    // Rendered width and height, when they differ from the map units.
+    size: Option<(f64, f64)>,
 }
 
 impl<T: Hash + Eq> Svg<T> {
@@ -22,8 +55,10 @@ impl<T: Hash + Eq> Svg<T> {
             bounds,
             layers: HashMap::new(),
             styles: HashMap::new(),
-            clippings: HashMap::new(),
+            patterns: vec![],
+            furniture: vec![],
             background_color: None,
+            size: None,
         }
     }
 
@@ -31,44 +66,157 @@ impl<T: Hash + Eq> Svg<T> {
         self.background_color = Some(color.into());
     }
 
+    // Sets the rendered height, keeping the map's aspect ratio.
+    pub fn set_height(&mut self, height: f64) {
+        let width = self.bounds.width * height / self.bounds.height;
+        self.size = Some((width, height));
+    }
+
     pub fn set_style(&mut self, layer: T, classname: &str, style: &str) {
         self.styles.insert(layer, (classname.into(), style.into()));
     }
 
-    pub fn set_clippings_layer(&mut self, layer: T, clipped_by: T) {
-        self.clippings.insert(layer, clipped_by);
+    // The class a layer's style is written for, so that elements drawn on
+    // another layer can be styled like it with a `class` attribute.
+    pub fn class_of(&self, layer: &T) -> Option<String> {
+        self.styles
+            .get(layer)
+            .map(|(classname, _)| classname.clone())
+    }
+
+    // Whether anything has been drawn on a layer.
+    pub fn is_drawn(&self, layer: &T) -> bool {
+        self.layers
+            .get(layer)
+            .is_some_and(|drawn| !drawn.is_empty())
+    }
+
+    // Adds markup drawn over every layer, in SVG units rather than projected
+    // coordinates.
+    pub fn add_furniture(&mut self, markup: &str) {
+        self.furniture.push(markup.into());
+    }
+
+    // Defines a tile which styles can use with `fill:url(#id)`.  `content` is
+    // drawn in a `width` x `height` box in map units.
+    pub fn add_pattern(&mut self, id: &str, width: f64, height: f64, content: &str) {
+        let pattern = format!(
+            r#"<pattern id="{}" patternUnits="userSpaceOnUse" width="{}" height="{}">{}</pattern>"#,
+            id, width, height, content
+        );
+        self.patterns.push(pattern);
     }
 
-    pub fn draw_polyline(&mut self, layer: T, polyline: &[(f64, f64)]) {
+    pub fn draw_polyline(&mut self, layer: T, polyline: &[Point]) {
+        self.draw_polyline_with(layer, polyline, &[]);
+    }
+
+    // Like `draw_polyline`, but with extra attributes (such as an inline
+    // `style`) on the element.
+    pub fn draw_polyline_with(
+        &mut self,
+        layer: T,
+        polyline: &[Point],
+        attributes: &[(&str, &str)],
+    ) {
         let len = polyline.len();
         if len == 0 || len == 1 {
             return;
         }
 
-        let transformed = polyline
+        let points = self.transform(polyline);
+        let element = if polyline[0] == polyline[len - 1] {
+            Element::Polygon { points }
+        } else {
+            Element::LineSegment { points }
+        };
+        self.push(layer, element, attributes);
+    }
+
+    pub fn draw_polygon(&mut self, layer: T, rings: &[Vec<Point>]) {
+        self.draw_polygon_with(layer, rings, &[]);
+    }
+
+    pub fn draw_polygon_with(
+        &mut self,
+        layer: T,
+        rings: &[Vec<Point>],
+        attributes: &[(&str, &str)],
+    ) {
+        let rings: Vec<_> = rings
             .iter()
-            .map(|(lon, lat)| {
-                self.bounds
-                    .transform_lat_lon_to_screen_coordinate((*lon, *lat))
-            })
+            .filter(|ring| ring.len() > 2)
+            .map(|ring| self.transform(ring))
             .collect();
-        let layer = self.layers.entry(layer).or_insert_with(|| vec![]);
-        if polyline[0] == polyline[len - 1] {
-            layer.push(Element::Polygon {
-                points: transformed,
-            })
-        } else {
-            layer.push(Element::LineSegment {
-                points: transformed,
-            })
+        if rings.is_empty() {
+            return;
         }
+        self.push(layer, Element::PolygonWithHoles { rings }, attributes);
+    }
+
+    // Draws a circle whose radius is in map units.
+    pub fn draw_point(&mut self, layer: T, point: Point, radius: f64) {
+        self.draw_point_with(layer, point, radius, &[]);
+    }
+
+    pub fn draw_point_with(
+        &mut self,
+        layer: T,
+        point: Point,
+        radius: f64,
+        attributes: &[(&str, &str)],
+    ) {
+        let center = self.bounds.transform_lat_lon_to_screen_coordinate(point);
+        self.push(layer, Element::Point { center, radius }, attributes);
     }
+
+    // Draws `text` centred on `point`, along a line `angle` degrees
+    // anticlockwise from east.
+    pub fn draw_text(&mut self, layer: T, point: Point, angle: f64, text: &str) {
+        let position = self.bounds.transform_lat_lon_to_screen_coordinate(point);
+        let text = escape(text);
+        self.push(
+            layer,
+            Element::Text {
+                position,
+                angle,
+                text,
+            },
+            &[],
+        );
+    }
+
+    // Draws an image (such as a `data:` URI) stretched over the whole map.
+    pub fn draw_image(&mut self, layer: T, href: &str) {
+        let href = escape(href);
+        self.push(layer, Element::Image { href }, &[]);
+    }
+
+    fn push(&mut self, layer: T, element: Element, attributes: &[(&str, &str)]) {
+        let attributes = attributes
+            .iter()
+            .map(|&(k, v)| (k.into(), escape(v)))
+            .collect();
+        self.layers.entry(layer).or_default().push(Drawn {
+            element,
+            attributes,
+        });
+    }
+
+    fn transform(&self, points: &[Point]) -> Vec<Point> {
+        points
+            .iter()
+            .map(|&point| self.bounds.transform_lat_lon_to_screen_coordinate(point))
+            .collect()
+    }
+
     fn draw_element<W: Write>(
         &self,
         style_class: Option<String>,
         out: &mut W,
-        element: &Element,
+        drawn: &Drawn,
     ) -> std::io::Result<()> {
+        let element = &drawn.element;
         match element {
             Element::LineSegment { points } | Element::Polygon { points } if points.is_empty() => {
                 return Ok(())
@@ -76,16 +224,69 @@ impl<T: Hash + Eq> Svg<T> {
             _ => (),
         }
 
-        let draw_polyline = |polyline: &[(f64, f64)], out: &mut W| -> std::io::Result<()> {
-            match style_class {
-                Some(class) => write!(out, r#"<path class="{}" d=""#, class)?,
-                None => write!(out, r#"<path d=""#)?,
-            }
+        let tag = match element {
+            Element::Point { .. } => "circle",
+            Element::Text { .. } => "text",
+            Element::Image { .. } => "image",
+            _ => "path",
+        };
+        // A `class` attribute replaces the layer's.
+        let own_class = drawn.attributes.iter().any(|(key, _)| key == "class");
+        match style_class {
+            Some(class) if !own_class => write!(out, r#"<{} class="{}" "#, tag, class)?,
+            _ => write!(out, "<{} ", tag)?,
+        }
+        for (key, value) in &drawn.attributes {
+            write!(out, r#"{}="{}" "#, key, value)?;
+        }
+
+        if let Element::Point { center, radius } = element {
+            writeln!(
+                out,
+                r#"cx="{:.2}" cy="{:.2}" r="{:.2}" />"#,
+                center.x(),
+                self.bounds.height - center.y(),
+                radius
+            )?;
+            return Ok(());
+        }
+
+        if let Element::Text {
+            position,
+            angle,
+            text,
+        } = element
+        {
+            let (x, y) = (position.x(), self.bounds.height - position.y());
+            writeln!(
+                out,
+                r#"x="{:.2}" y="{:.2}" transform="rotate({:.1} {:.2} {:.2})">{}</text>"#,
+                x, y, -angle, x, y, text
+            )?;
+            return Ok(());
+        }
+
+        if let Element::Image { href } = element {
+            writeln!(
+                out,
+                r#"x="0" y="0" width="{}" height="{}" preserveAspectRatio="none" href="{}" />"#,
+                self.bounds.width, self.bounds.height, href
+            )?;
+            return Ok(());
+        }
+
+        if let Element::PolygonWithHoles { .. } = element {
+            write!(out, r#"fill-rule="evenodd" "#)?;
+        }
+        write!(out, r#"d=""#)?;
+
+        let draw_polyline = |polyline: &[Point], out: &mut W| -> std::io::Result<()> {
             let mut first = true;
-            for (x, y) in polyline {
+            for point in polyline {
                 let movement = if first { "M" } else { "L" };
                 first = false;
-                write!(out, "{}{:.2},{:.2} ", movement, x, self.bounds.height - y)?;
+                let (x, y) = (point.x(), self.bounds.height - point.y());
+                write!(out, "{}{:.2},{:.2} ", movement, x, y)?;
             }
             Ok(())
         };
@@ -96,59 +297,63 @@ impl<T: Hash + Eq> Svg<T> {
                 draw_polyline(points, out)?;
                 write!(out, "z")?;
             }
+            Element::PolygonWithHoles { rings } => {
+                for ring in rings {
+                    draw_polyline(ring, out)?;
+                    write!(out, "z ")?;
+                }
+            }
+            Element::Point { .. } | Element::Text { .. } | Element::Image { .. } => {
+                unreachable!()
+            }
         }
 
         writeln!(out, r#"" />"#)?;
         Ok(())
     }
 
-    #[flame]
-    fn export_layer<W: Write>(
-        &self,
-        layer: &T,
-        should_print_group: bool,
-        file: &mut W,
-    ) -> std::io::Result<()> {
-        let additional_info = if let Some(clipped_by) = self.clippings.get(layer) {
-            let id = get_unique_id();
-            writeln!(file, r#"<clipPath id="{}">"#, id)?;
-            self.export_layer(clipped_by, false, file)?;
-            writeln!(file, "</clipPath>")?;
-            format!(r#"clip-path="url(#{})""#, id)
-        } else {
-            "".into()
-        };
-
-        if should_print_group {
-            writeln!(file, "<g {}>", additional_info)?;
+    // Each layer is a group, named after its style's class when it has one.
+    #[cfg_attr(feature = "profiling", flame)]
+    fn export_layer<W: Write>(&self, layer: &T, file: &mut W) -> std::io::Result<()> {
+        let style = self.class_of(layer);
+        match &style {
+            Some(class) => writeln!(file, r#"<g data-layer="{}">"#, class)?,
+            None => writeln!(file, "<g>")?,
         }
         if let Some(elements) = self.layers.get(layer) {
-            for element in elements {
-                let style = self.styles.get(layer).cloned().map(|(a, _)| a);
-                self.draw_element(style, file, element)?;
+            for drawn in elements {
+                self.draw_element(style.clone(), file, drawn)?;
             }
         }
-        if should_print_group {
-            writeln!(file, "</g>")?;
-        }
+        writeln!(file, "</g>")?;
         Ok(())
     }
 
-    /*fn draw_clipped_elements(draw: &[Element], clip: &[Element]) -> std::io::Result<()> {
-        //let aabb = aabb_quadtree::QuadTree::default(unimplemented!(), clip.len());
-        unimplemented!();
-    }*/
-
-    #[flame]
+    #[cfg_attr(feature = "profiling", flame)]
     pub fn export_to_file(&self, file: &str, layer_order: &[T]) -> std::io::Result<()> {
         let file = std::fs::File::create(file)?;
-        let mut file = std::io::BufWriter::new(file);
+        self.write(&mut std::io::BufWriter::new(file), layer_order)
+    }
 
-        writeln!(
+    // Writes the whole `<svg>` element, with the layers in `layer_order`.
+    pub fn write<W: Write>(&self, file: &mut W, layer_order: &[T]) -> std::io::Result<()> {
+        write!(
             file,
-            r#"<svg viewBox="0 0 {} {}" xmlns="http://www.w3.org/2000/svg">"#,
+            r#"<svg viewBox="0 0 {} {}" "#,
             self.bounds.width, self.bounds.height
         )?;
+        if let Some((width, height)) = self.size {
+            write!(file, r#"width="{:.0}" height="{:.0}" "#, width, height)?;
+        }
+        writeln!(file, r#"xmlns="http://www.w3.org/2000/svg">"#)?;
+
+        if !self.patterns.is_empty() {
+            writeln!(file, "<defs>")?;
+            for pattern in &self.patterns {
+                writeln!(file, "{}", pattern)?;
+            }
+            writeln!(file, "</defs>")?;
+        }
 
         writeln!(file, "<style>")?;
         if let Some(background_color) = &self.background_color {
@@ -169,7 +374,15 @@ impl<T: Hash + Eq> Svg<T> {
         }
 
         for layer in layer_order {
-            self.export_layer(layer, true, &mut file)?;
+            self.export_layer(layer, file)?;
+        }
+
+        if !self.furniture.is_empty() {
+            writeln!(file, r#"<g data-layer="furniture">"#)?;
+            for markup in &self.furniture {
+                writeln!(file, "{}", markup)?;
+            }
+            writeln!(file, "</g>")?;
         }
 
         writeln!(file, "</svg>")?;
@@ -177,10 +390,10 @@ impl<T: Hash + Eq> Svg<T> {
     }
 }
 
-use std::sync::atomic::{AtomicU32, Ordering};
-static mut ID: AtomicU32 = AtomicU32::new(0);
-
-fn get_unique_id() -> String {
-    let id = unsafe { ID.fetch_add(1, Ordering::Relaxed) };
-    return format!("a_{}", id);
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
 }
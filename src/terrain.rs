@@ -0,0 +1,505 @@
+// Terrain from SRTM `.hgt` tiles: contour lines traced with marching squares,
+// and a hillshade rendered to an image.
+//
+// A tile is named after its south-west corner (`N38W078.hgt` covers 38-39°N,
+// 77-78°W) and holds a square grid of big-endian 16-bit heights in metres,
+// rows from the north, sharing their edges with the neighbouring tiles.
+
+use linemath::Point;
+use osm_load::{self, Bounds};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+// Marks a gap in the data.
+const VOID: i16 = -32768;
+
+// Every fifth contour is an index contour, drawn heavier and labelled.
+pub const INDEX_EVERY: i64 = 5;
+
+// Light from the north-west, 45° above the horizon.
+const SUN_AZIMUTH: f64 = 315.0;
+const SUN_ALTITUDE: f64 = 45.0;
+
+// The hillshade image is no wider than this, in pixels.
+const MAX_IMAGE_WIDTH: usize = 1500;
+
+// What terrain to draw, for `--terrain` or a batch job.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TerrainOptions {
+    // Where to find the `.hgt` tiles.
+    pub dir: String,
+    // Metres between contours.
+    #[serde(default = "default_interval")]
+    pub interval: f64,
+    #[serde(default)]
+    pub hillshade: bool,
+}
+
+pub fn default_interval() -> f64 {
+    10.0
+}
+
+// A grid of heights, from however many tiles cover an area.
+pub struct Dem {
+    // Longitude and latitude of the first (north-west) sample, and the
+    // spacing of samples, in degrees.
+    west: f64,
+    north: f64,
+    step: f64,
+    columns: usize,
+    rows: usize,
+    // In metres, row by row from the north, or None where there's no data.
+    heights: Vec<Option<f64>>,
+}
+
+// A contour line, as longitude and latitude.
+pub struct Contour {
+    pub height: f64,
+    pub index: bool,
+    pub points: Vec<(f64, f64)>,
+}
+
+pub fn tile_name(lat: i32, lon: i32) -> String {
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if lat < 0 { 'S' } else { 'N' },
+        lat.abs(),
+        if lon < 0 { 'W' } else { 'E' },
+        lon.abs()
+    )
+}
+
+// The number of samples along each side of a tile.
+fn tile_size(bytes: &[u8]) -> Result<usize, String> {
+    let size = ((bytes.len() / 2) as f64).sqrt().round() as usize;
+    if size < 2 || size * size * 2 != bytes.len() {
+        return Err(format!(
+            "{} bytes isn't a square grid of heights",
+            bytes.len()
+        ));
+    }
+    Ok(size)
+}
+
+impl Dem {
+    // The heights covering `west..east` and `south..north` (in degrees), with
+    // a sample to spare all round.  Missing tiles are left empty, but at least
+    // one must be found.
+    pub fn load(dir: &str, (west, south, east, north): (f64, f64, f64, f64)) -> io::Result<Dem> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut tiles = vec![];
+        for lat in south.floor() as i32..=north.floor() as i32 {
+            for lon in west.floor() as i32..=east.floor() as i32 {
+                let path = Path::new(dir).join(tile_name(lat, lon));
+                if path.exists() {
+                    let bytes = std::fs::read(&path)?;
+                    let size = tile_size(&bytes)
+                        .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+                    tiles.push((lat, lon, size, bytes));
+                }
+            }
+        }
+        let per_degree = match tiles.first() {
+            Some(&(_, _, size, _)) => size - 1,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no .hgt tiles for the map in {}", dir),
+                ))
+            }
+        };
+        if tiles.iter().any(|&(_, _, size, _)| size - 1 != per_degree) {
+            return Err(invalid(format!("{}: tiles of different resolutions", dir)));
+        }
+
+        // Samples are numbered from 0°, west to east and south to north.
+        let per_degree_f = per_degree as f64;
+        let first_x = (west * per_degree_f).floor() as i64 - 1;
+        let last_x = (east * per_degree_f).ceil() as i64 + 1;
+        let first_y = (south * per_degree_f).floor() as i64 - 1;
+        let last_y = (north * per_degree_f).ceil() as i64 + 1;
+        let columns = (last_x - first_x + 1) as usize;
+        let rows = (last_y - first_y + 1) as usize;
+        let mut heights = vec![None; columns * rows];
+        for (lat, lon, size, bytes) in tiles {
+            for r in 0..size {
+                let y = (lat as i64 + 1) * per_degree as i64 - r as i64;
+                if y < first_y || y > last_y {
+                    continue;
+                }
+                for c in 0..size {
+                    let x = lon as i64 * per_degree as i64 + c as i64;
+                    if x < first_x || x > last_x {
+                        continue;
+                    }
+                    let at = 2 * (r * size + c);
+                    let height = i16::from_be_bytes([bytes[at], bytes[at + 1]]);
+                    if height != VOID {
+                        let row = (last_y - y) as usize;
+                        heights[row * columns + (x - first_x) as usize] = Some(height as f64);
+                    }
+                }
+            }
+        }
+        Ok(Dem {
+            west: first_x as f64 / per_degree_f,
+            north: last_y as f64 / per_degree_f,
+            step: 1.0 / per_degree_f,
+            columns,
+            rows,
+            heights,
+        })
+    }
+
+    fn height(&self, row: usize, column: usize) -> Option<f64> {
+        self.heights[row * self.columns + column]
+    }
+
+    fn lon_lat(&self, (column, row): (f64, f64)) -> (f64, f64) {
+        (self.west + column * self.step, self.north - row * self.step)
+    }
+
+    // Contour lines every `interval` metres.
+    #[cfg_attr(feature = "profiling", flame)]
+    pub fn contours(&self, interval: f64) -> Vec<Contour> {
+        let known = self.heights.iter().flatten();
+        let min = known.clone().cloned().fold(f64::INFINITY, f64::min);
+        let max = known.cloned().fold(f64::NEG_INFINITY, f64::max);
+        if min > max || interval <= 0.0 {
+            return vec![];
+        }
+        let mut contours = vec![];
+        for k in (min / interval).ceil() as i64..=(max / interval).floor() as i64 {
+            let height = k as f64 * interval;
+            // Skip lines which shrink to a point, as on a summit exactly
+            // at the contour's height.
+            let lines = self.trace(height).into_iter();
+            for line in lines.filter(|line| line.windows(2).any(|w| w[0] != w[1])) {
+                contours.push(Contour {
+                    height,
+                    index: k % INDEX_EVERY == 0,
+                    points: line.into_iter().map(|p| self.lon_lat(p)).collect(),
+                });
+            }
+        }
+        contours
+    }
+
+    // Marching squares: the lines at `level`, in (column, row) coordinates.
+    fn trace(&self, level: f64) -> Vec<Vec<(f64, f64)>> {
+        // A crossing is identified by the grid edge it's on: the edge from
+        // (row, column) to the right (false) or downwards (true).
+        type Crossing = (usize, usize, bool);
+        let mut points: HashMap<Crossing, (f64, f64)> = HashMap::new();
+        let mut segments: Vec<[Crossing; 2]> = vec![];
+
+        for row in 0..self.rows - 1 {
+            for column in 0..self.columns - 1 {
+                let corners = [
+                    self.height(row, column),
+                    self.height(row, column + 1),
+                    self.height(row + 1, column + 1),
+                    self.height(row + 1, column),
+                ];
+                let [tl, tr, br, bl] = match corners {
+                    [Some(tl), Some(tr), Some(br), Some(bl)] => [tl, tr, br, bl],
+                    _ => continue,
+                };
+                let top = (row, column, false);
+                let right = (row, column + 1, true);
+                let bottom = (row + 1, column, false);
+                let left = (row, column, true);
+                let high = |h: f64| (h >= level) as usize;
+                let case = high(tl) << 3 | high(tr) << 2 | high(br) << 1 | high(bl);
+                let centre_high = (tl + tr + br + bl) / 4.0 >= level;
+                let pairs: &[[Crossing; 2]] = match case {
+                    1 | 14 => &[[left, bottom]],
+                    2 | 13 => &[[bottom, right]],
+                    3 | 12 => &[[left, right]],
+                    4 | 11 => &[[top, right]],
+                    6 | 9 => &[[top, bottom]],
+                    7 | 8 => &[[left, top]],
+                    // Saddles, joined the way the middle of the cell goes.
+                    5 if centre_high => &[[left, top], [bottom, right]],
+                    5 => &[[top, right], [left, bottom]],
+                    10 if centre_high => &[[top, right], [left, bottom]],
+                    10 => &[[left, top], [bottom, right]],
+                    _ => &[],
+                };
+                for pair in pairs {
+                    for &crossing in pair {
+                        points
+                            .entry(crossing)
+                            .or_insert_with(|| self.crossing(crossing, level));
+                    }
+                    segments.push(*pair);
+                }
+            }
+        }
+
+        // Each crossing is shared by the segments of the (at most two) cells
+        // either side of its edge.
+        let mut at: HashMap<Crossing, Vec<usize>> = HashMap::new();
+        for (i, segment) in segments.iter().enumerate() {
+            for crossing in segment {
+                at.entry(*crossing).or_default().push(i);
+            }
+        }
+        let mut used = vec![false; segments.len()];
+        let mut lines = vec![];
+        for first in 0..segments.len() {
+            if used[first] {
+                continue;
+            }
+            used[first] = true;
+            let mut line = segments[first].to_vec();
+            // Extend from the end, then turn round and extend from the start.
+            for _ in 0..2 {
+                loop {
+                    let end = line[line.len() - 1];
+                    let next = at[&end].iter().cloned().find(|&i| !used[i]);
+                    match next {
+                        Some(i) => {
+                            used[i] = true;
+                            let [a, b] = segments[i];
+                            line.push(if a == end { b } else { a });
+                        }
+                        None => break,
+                    }
+                }
+                line.reverse();
+            }
+            lines.push(line.iter().map(|crossing| points[crossing]).collect());
+        }
+        lines
+    }
+
+    // Where `level` is reached along the edge of a crossing.
+    fn crossing(&self, (row, column, down): (usize, usize, bool), level: f64) -> (f64, f64) {
+        let (row_2, column_2) = if down {
+            (row + 1, column)
+        } else {
+            (row, column + 1)
+        };
+        let a = self.height(row, column).unwrap();
+        let b = self.height(row_2, column_2).unwrap();
+        let t = if a == b { 0.5 } else { (level - a) / (b - a) };
+        (
+            column as f64 + t * (column_2 - column) as f64,
+            row as f64 + t * (row_2 - row) as f64,
+        )
+    }
+
+    // How lit each sample is, from 0 (facing away from the sun) to 1 (flat
+    // ground or better), using Horn's slope formula.
+    fn shade(&self) -> Vec<f64> {
+        let zenith = (90.0 - SUN_ALTITUDE).to_radians();
+        let azimuth = (360.0 - SUN_AZIMUTH + 90.0).to_radians();
+        let mut shade = vec![1.0; self.heights.len()];
+        for row in 1..self.rows - 1 {
+            let lat = self.north - row as f64 * self.step;
+            let dy = self.step * 110_574.0;
+            let dx = self.step * 111_320.0 * lat.to_radians().cos();
+            for column in 1..self.columns - 1 {
+                let mut window = [0.0; 9];
+                let mut complete = true;
+                for (i, value) in window.iter_mut().enumerate() {
+                    match self.height(row + i / 3 - 1, column + i % 3 - 1) {
+                        Some(height) => *value = height,
+                        None => complete = false,
+                    }
+                }
+                if !complete {
+                    continue;
+                }
+                let [a, b, c, d, _, f, g, h, i] = window;
+                let dz_dx = ((c + 2.0 * f + i) - (a + 2.0 * d + g)) / (8.0 * dx);
+                let dz_dy = ((g + 2.0 * h + i) - (a + 2.0 * b + c)) / (8.0 * dy);
+                let slope = dz_dx.hypot(dz_dy).atan();
+                let aspect = dz_dy.atan2(-dz_dx);
+                let lit = zenith.cos() * slope.cos()
+                    + zenith.sin() * slope.sin() * (azimuth - aspect).cos();
+                shade[row * self.columns + column] = (lit / zenith.cos()).clamp(0.0, 1.0);
+            }
+        }
+        shade
+    }
+
+    // A hillshade of the map as a grayscale PNG `data:` URI, white where the
+    // ground is flat or faces the sun, to be multiplied over the map.
+    #[cfg_attr(feature = "profiling", flame)]
+    pub fn hillshade(&self, bounds: &Bounds) -> String {
+        let shade = self.shade();
+        let corner = |x, y| {
+            let point = bounds.transform_screen_coordinate_to_lat_lon(Point::new(x, y));
+            osm_load::unproject(point)
+        };
+        let (west, _) = corner(0.0, 0.0);
+        let (east, _) = corner(bounds.width, 0.0);
+        let width = (((east - west) / self.step).ceil() as usize).clamp(1, MAX_IMAGE_WIDTH);
+        let height = ((width as f64 * bounds.height / bounds.width).round() as usize).max(1);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (lon, lat) = corner(
+                    (x as f64 + 0.5) * bounds.width / width as f64,
+                    bounds.height - (y as f64 + 0.5) * bounds.height / height as f64,
+                );
+                let column = ((lon - self.west) / self.step).clamp(0.0, (self.columns - 1) as f64);
+                let row = ((self.north - lat) / self.step).clamp(0.0, (self.rows - 1) as f64);
+                pixels.push((bilinear(&shade, self.columns, column, row) * 255.0).round() as u8);
+            }
+        }
+        format!(
+            "data:image/png;base64,{}",
+            base64(&grayscale_png(width, height, &pixels))
+        )
+    }
+}
+
+fn bilinear(grid: &[f64], columns: usize, column: f64, row: f64) -> f64 {
+    let (c, r) = (column.floor() as usize, row.floor() as usize);
+    let (fc, fr) = (column - c as f64, row - r as f64);
+    let rows = grid.len() / columns;
+    let at = |r: usize, c: usize| grid[r.min(rows - 1) * columns + c.min(columns - 1)];
+    let top = at(r, c) * (1.0 - fc) + at(r, c + 1) * fc;
+    let bottom = at(r + 1, c) * (1.0 - fc) + at(r + 1, c + 1) * fc;
+    top * (1.0 - fr) + bottom * fr
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+// An 8-bit grayscale PNG.  The image data is stored without compression,
+// which keeps this short; the hillshade is smooth enough that an SVG viewer's
+// gzip would do most of the work anyway.
+fn grayscale_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        // No filter.
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(0xffff).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push((i == blocks.len() - 1) as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut chunk = |kind: &[u8], data: &[u8]| {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    };
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits of gray, default compression, filtering and no interlacing.
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+    chunk(b"IHDR", &header);
+    chunk(b"IDAT", &zlib);
+    chunk(b"IEND", &[]);
+    png
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+fn test_dem(heights: &[&[f64]]) -> Dem {
+    Dem {
+        west: 0.0,
+        north: 1.0,
+        step: 0.1,
+        columns: heights[0].len(),
+        rows: heights.len(),
+        heights: heights
+            .iter()
+            .flat_map(|row| row.iter().map(|&h| Some(h)))
+            .collect(),
+    }
+}
+
+#[test]
+fn a_hill_has_closed_contours() {
+    let dem = test_dem(&[
+        &[0.0, 0.0, 0.0, 0.0, 0.0],
+        &[0.0, 10.0, 10.0, 10.0, 0.0],
+        &[0.0, 10.0, 20.0, 10.0, 0.0],
+        &[0.0, 10.0, 10.0, 10.0, 0.0],
+        &[0.0, 0.0, 0.0, 0.0, 0.0],
+    ]);
+    let contours = dem.contours(5.0);
+    let heights: Vec<f64> = contours.iter().map(|c| c.height).collect();
+    // Nothing at 0 m, where the whole edge is, or at 20 m, which is only the
+    // summit.
+    assert_eq!(vec![5.0, 10.0, 15.0], heights);
+    for contour in &contours {
+        assert_eq!(contour.points[0], contour.points[contour.points.len() - 1]);
+    }
+    // Halfway between the edge and the first ring.
+    let near = |(lon, lat): (f64, f64)| (lon - 0.05).abs() < 1e-9 && (lat - 0.9).abs() < 1e-9;
+    assert!(contours[0].points.iter().any(|&point| near(point)));
+    assert!(!contours[0].index && !contours[2].index);
+    assert!(dem.contours(25.0).is_empty());
+}
+
+#[test]
+fn tiles_and_images() {
+    assert_eq!("N38W078.hgt", tile_name(38, -78));
+    assert_eq!("S01E005.hgt", tile_name(-1, 5));
+    assert_eq!(Ok(3), tile_size(&[0; 18]));
+    assert!(tile_size(&[0; 20]).is_err());
+    assert_eq!("TWFu", base64(b"Man"));
+    assert_eq!("TWE=", base64(b"Ma"));
+    // The check value of CRC-32.
+    assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    assert_eq!(0x11e6_0398, adler32(b"Wikipedia"));
+}
@@ -1,20 +1,57 @@
 use crate::svg_exporter::*;
 use crate::*;
 
+pub type Theme = fn(&mut Svg<Layer>);
+
+pub fn by_name(name: &str) -> Option<Theme> {
+    match name {
+        "gray" => Some(gray_theme),
+        "puke" => Some(puke_theme),
+        _ => None,
+    }
+}
+
 pub fn puke_theme(svg: &mut Svg<Layer>) {
     svg.set_background_color("#1f2345");
 
-    svg.set_style(
-        Layer::Road,
-        "road",
-        "fill:none; stroke:#8b8ca9; stroke-width:0.07%; stroke-linecap:round",
-    );
+    svg.set_style(Layer::Road, "road", "fill:#8b8ca9; stroke:none");
 
     svg.set_style(
         Layer::Building,
         "building",
         "fill:#dc9433; stroke:#000; stroke-width:0.01px",
     );
+    svg.set_style(
+        Layer::Grid,
+        "grid",
+        "fill:none; stroke:#e8e8f0; stroke-width:0.4px; stroke-opacity:0.8",
+    );
+    svg.set_style(
+        Layer::GridLabel,
+        "grid-label",
+        "fill:#e8e8f0; font:7px sans-serif; text-anchor:middle; dominant-baseline:middle; paint-order:stroke; stroke:#1f2345; stroke-width:2px",
+    );
+    svg.set_style(
+        Layer::Furniture,
+        "furniture",
+        "fill:#e8e8f0; font-family:sans-serif",
+    );
+    svg.set_style(
+        Layer::FurniturePanel,
+        "furniture-panel",
+        "fill:#1f2345; fill-opacity:0.85; stroke:#e8e8f0; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::BuildingShadow,
+        "building-shadow",
+        "fill:#14172e; stroke:none",
+    );
+    svg.set_style(
+        Layer::BuildingWall,
+        "building-wall",
+        "fill:#a86f22; stroke:#14172e; stroke-width:0.1px",
+    );
+    element_styles(svg);
 
     svg.set_style(
         Layer::ParkBuilding,
@@ -41,18 +78,182 @@ pub fn puke_theme(svg: &mut Svg<Layer>) {
     );
 
     svg.set_style(Layer::Park, "park", "fill:#42f442; stroke:none;");
+    svg.set_style(Layer::Water, "water", "fill:#3a4a8f; stroke:none;");
+    svg.set_style(Layer::Waterway, "waterway", "fill:#3a4a8f; stroke:none;");
+    svg.set_style(Layer::Wetland, "wetland", "fill:#2f6b5a; stroke:none;");
+
+    svg.set_style(
+        Layer::Rail,
+        "rail",
+        "fill:none; stroke:#8b8ca9; stroke-width:0.6px",
+    );
+    svg.set_style(
+        Layer::RailHatching,
+        "rail-hatching",
+        "fill:none; stroke:#8b8ca9; stroke-width:2.4px; stroke-dasharray:0.4 3",
+    );
+    svg.set_style(
+        Layer::LightRail,
+        "light-rail",
+        "fill:none; stroke:#b0b1cf; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::TransitRoute,
+        "transit-route",
+        "fill:none; stroke:#ff0000; stroke-width:0.3%; stroke-linecap:round",
+    );
+    svg.set_style(
+        Layer::Station,
+        "station",
+        "fill:#fff; stroke:#1f2345; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::Hillshade,
+        "hillshade",
+        "opacity:0.5; mix-blend-mode:multiply",
+    );
+    svg.set_style(
+        Layer::Contour,
+        "contour",
+        "fill:none; stroke:#b0b1cf; stroke-width:0.3px; stroke-opacity:0.6",
+    );
+    svg.set_style(
+        Layer::IndexContour,
+        "index-contour",
+        "fill:none; stroke:#b0b1cf; stroke-width:0.7px; stroke-opacity:0.7",
+    );
+    svg.set_style(
+        Layer::ContourLabel,
+        "contour-label",
+        "fill:#b0b1cf; font:6px sans-serif; text-anchor:middle; dominant-baseline:middle; paint-order:stroke; stroke:#1f2345; stroke-width:2px",
+    );
+    svg.set_style(
+        Layer::OverlayArea,
+        "overlay-area",
+        "fill:#4fc3f7; fill-opacity:0.3; stroke:#4fc3f7; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::OverlayLine,
+        "overlay-line",
+        "fill:none; stroke:#4fc3f7; stroke-width:0.2%; stroke-linecap:round; stroke-linejoin:round",
+    );
+    svg.set_style(
+        Layer::OverlayPoint,
+        "overlay-point",
+        "fill:#4fc3f7; stroke:#fff; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::GpxRoute,
+        "gpx-route",
+        "fill:none; stroke:#ffeb3b; stroke-width:0.3%; stroke-dasharray:4 3; stroke-linecap:round",
+    );
+    svg.set_style(
+        Layer::GpxTrack,
+        "gpx-track",
+        "fill:none; stroke:#ffeb3b; stroke-width:0.4%; stroke-linecap:round; stroke-linejoin:round",
+    );
+    svg.set_style(
+        Layer::GpxWaypoint,
+        "gpx-waypoint",
+        "fill:#ffeb3b; stroke:#fff; stroke-width:0.8px",
+    );
+    svg.set_style(
+        Layer::Itinerary,
+        "itinerary",
+        "fill:none; stroke:#ff6d00; stroke-width:0.5%; stroke-linecap:round; stroke-linejoin:round; stroke-opacity:0.85",
+    );
+    svg.set_style(
+        Layer::ItineraryStart,
+        "itinerary-start",
+        "fill:#00c853; stroke:#fff; stroke-width:1px",
+    );
+    svg.set_style(
+        Layer::ItineraryEnd,
+        "itinerary-end",
+        "fill:#d50000; stroke:#fff; stroke-width:1px",
+    );
+
+    svg.add_pattern(
+        "puke-cemetery",
+        6.0,
+        6.0,
+        r##"<rect width="6" height="6" fill="#4a3f6b"/><path d="M3,1 V5 M1.8,2.4 H4.2" stroke="#8b8ca9" stroke-width="0.4"/>"##,
+    );
+    svg.add_pattern(
+        "puke-forest",
+        4.0,
+        4.0,
+        r##"<rect width="4" height="4" fill="#2e7d32"/><circle cx="1" cy="1" r="0.5" fill="#66bb6a"/><circle cx="3" cy="3" r="0.5" fill="#66bb6a"/>"##,
+    );
+    svg.set_style(
+        Layer::Residential,
+        "residential",
+        "fill:#2a2f5a; stroke:none;",
+    );
+    svg.set_style(
+        Layer::Commercial,
+        "commercial",
+        "fill:#4a2f5a; stroke:none;",
+    );
+    svg.set_style(
+        Layer::Industrial,
+        "industrial",
+        "fill:#5a3f2f; stroke:none;",
+    );
+    svg.set_style(Layer::Railway, "railway-land", "fill:#3f3f4f; stroke:none;");
+    svg.set_style(Layer::Farmland, "farmland", "fill:#7a6f2f; stroke:none;");
+    svg.set_style(Layer::Scrub, "scrub", "fill:#6b8e23; stroke:none;");
+    svg.set_style(Layer::Sand, "sand", "fill:#e0c068; stroke:none;");
+    svg.set_style(Layer::Beach, "beach", "fill:#f0d890; stroke:none;");
+    svg.set_style(
+        Layer::Cemetery,
+        "cemetery",
+        "fill:url(#puke-cemetery); stroke:none;",
+    );
+    svg.set_style(
+        Layer::Forest,
+        "forest",
+        "fill:url(#puke-forest); stroke:none;",
+    );
 }
 
 pub fn gray_theme(svg: &mut Svg<Layer>) {
     svg.set_background_color("#fff");
 
-    svg.set_style(
-        Layer::Road,
-        "road",
-        "fill:none; stroke:#bbb; stroke-width:0.07%; stroke-linecap:round",
-    );
+    svg.set_style(Layer::Road, "road", "fill:#bbb; stroke:none");
 
     svg.set_style(Layer::Building, "building", "fill:#fff; stroke:none;");
+    svg.set_style(
+        Layer::Grid,
+        "grid",
+        "fill:none; stroke:#5c6bc0; stroke-width:0.4px; stroke-opacity:0.8",
+    );
+    svg.set_style(
+        Layer::GridLabel,
+        "grid-label",
+        "fill:#3949ab; font:7px sans-serif; text-anchor:middle; dominant-baseline:middle; paint-order:stroke; stroke:#fff; stroke-width:2px",
+    );
+    svg.set_style(
+        Layer::Furniture,
+        "furniture",
+        "fill:#333; font-family:sans-serif",
+    );
+    svg.set_style(
+        Layer::FurniturePanel,
+        "furniture-panel",
+        "fill:#fff; fill-opacity:0.85; stroke:#999; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::BuildingShadow,
+        "building-shadow",
+        "fill:#999; stroke:none",
+    );
+    svg.set_style(
+        Layer::BuildingWall,
+        "building-wall",
+        "fill:#ddd; stroke:#999; stroke-width:0.1px",
+    );
+    element_styles(svg);
 
     svg.set_style(
         Layer::ParkBuilding,
@@ -74,4 +275,156 @@ pub fn gray_theme(svg: &mut Svg<Layer>) {
 
     svg.set_style(Layer::Coastline, "coastline", "fill:#777; stroke:none;");
     svg.set_style(Layer::Park, "park", "fill:#777; stroke:none;");
+    svg.set_style(Layer::Water, "water", "fill:#fff; stroke:none;");
+    svg.set_style(Layer::Waterway, "waterway", "fill:#fff; stroke:none;");
+    svg.set_style(Layer::Wetland, "wetland", "fill:#999; stroke:none;");
+
+    svg.set_style(
+        Layer::Rail,
+        "rail",
+        "fill:none; stroke:#888; stroke-width:0.6px",
+    );
+    svg.set_style(
+        Layer::RailHatching,
+        "rail-hatching",
+        "fill:none; stroke:#888; stroke-width:2.4px; stroke-dasharray:0.4 3",
+    );
+    svg.set_style(
+        Layer::LightRail,
+        "light-rail",
+        "fill:none; stroke:#999; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::TransitRoute,
+        "transit-route",
+        "fill:none; stroke:#ff0000; stroke-width:0.3%; stroke-linecap:round",
+    );
+    svg.set_style(
+        Layer::Station,
+        "station",
+        "fill:#fff; stroke:#000; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::Hillshade,
+        "hillshade",
+        "opacity:0.35; mix-blend-mode:multiply",
+    );
+    svg.set_style(
+        Layer::Contour,
+        "contour",
+        "fill:none; stroke:#999; stroke-width:0.3px; stroke-opacity:0.6",
+    );
+    svg.set_style(
+        Layer::IndexContour,
+        "index-contour",
+        "fill:none; stroke:#888; stroke-width:0.7px; stroke-opacity:0.7",
+    );
+    svg.set_style(
+        Layer::ContourLabel,
+        "contour-label",
+        "fill:#777; font:6px sans-serif; text-anchor:middle; dominant-baseline:middle; paint-order:stroke; stroke:#fff; stroke-width:2px",
+    );
+    svg.set_style(
+        Layer::OverlayArea,
+        "overlay-area",
+        "fill:#00897b; fill-opacity:0.3; stroke:#00897b; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::OverlayLine,
+        "overlay-line",
+        "fill:none; stroke:#00897b; stroke-width:0.2%; stroke-linecap:round; stroke-linejoin:round",
+    );
+    svg.set_style(
+        Layer::OverlayPoint,
+        "overlay-point",
+        "fill:#00897b; stroke:#fff; stroke-width:0.5px",
+    );
+    svg.set_style(
+        Layer::GpxRoute,
+        "gpx-route",
+        "fill:none; stroke:#e91e63; stroke-width:0.3%; stroke-dasharray:4 3; stroke-linecap:round",
+    );
+    svg.set_style(
+        Layer::GpxTrack,
+        "gpx-track",
+        "fill:none; stroke:#e91e63; stroke-width:0.4%; stroke-linecap:round; stroke-linejoin:round",
+    );
+    svg.set_style(
+        Layer::GpxWaypoint,
+        "gpx-waypoint",
+        "fill:#e91e63; stroke:#fff; stroke-width:0.8px",
+    );
+    svg.set_style(
+        Layer::Itinerary,
+        "itinerary",
+        "fill:none; stroke:#2962ff; stroke-width:0.5%; stroke-linecap:round; stroke-linejoin:round; stroke-opacity:0.85",
+    );
+    svg.set_style(
+        Layer::ItineraryStart,
+        "itinerary-start",
+        "fill:#00c853; stroke:#fff; stroke-width:1px",
+    );
+    svg.set_style(
+        Layer::ItineraryEnd,
+        "itinerary-end",
+        "fill:#d50000; stroke:#fff; stroke-width:1px",
+    );
+
+    svg.add_pattern(
+        "gray-cemetery",
+        6.0,
+        6.0,
+        r##"<rect width="6" height="6" fill="#eee"/><path d="M3,1 V5 M1.8,2.4 H4.2" stroke="#aaa" stroke-width="0.4"/>"##,
+    );
+    svg.add_pattern(
+        "gray-forest",
+        4.0,
+        4.0,
+        r##"<rect width="4" height="4" fill="#ddd"/><circle cx="1" cy="1" r="0.5" fill="#aaa"/><circle cx="3" cy="3" r="0.5" fill="#aaa"/>"##,
+    );
+    svg.set_style(
+        Layer::Residential,
+        "residential",
+        "fill:#f7f7f7; stroke:none;",
+    );
+    svg.set_style(
+        Layer::Commercial,
+        "commercial",
+        "fill:#f0f0f0; stroke:none;",
+    );
+    svg.set_style(
+        Layer::Industrial,
+        "industrial",
+        "fill:#e8e8e8; stroke:none;",
+    );
+    svg.set_style(Layer::Railway, "railway-land", "fill:#e4e4e4; stroke:none;");
+    svg.set_style(Layer::Farmland, "farmland", "fill:#f2f2f2; stroke:none;");
+    svg.set_style(Layer::Scrub, "scrub", "fill:#e0e0e0; stroke:none;");
+    svg.set_style(Layer::Sand, "sand", "fill:#f4f4f4; stroke:none;");
+    svg.set_style(Layer::Beach, "beach", "fill:#f4f4f4; stroke:none;");
+    svg.set_style(
+        Layer::Cemetery,
+        "cemetery",
+        "fill:url(#gray-cemetery); stroke:none;",
+    );
+    svg.set_style(
+        Layer::Forest,
+        "forest",
+        "fill:url(#gray-forest); stroke:none;",
+    );
+}
+
+// Elements drawn only for their OSM ids and tags are invisible, but can still
+// be hovered over in an interactive page.
+fn element_styles(svg: &mut Svg<Layer>) {
+    svg.set_style(
+        Layer::ElementArea,
+        "element-area",
+        "fill:transparent; stroke:none",
+    );
+    svg.set_style(
+        Layer::ElementLine,
+        "element-line",
+        "fill:none; stroke:transparent; stroke-width:3px",
+    );
 }
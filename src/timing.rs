@@ -0,0 +1,82 @@
+// Timing of the pipeline's stages.  Stages are always timed (it costs one clock
+// read each) and can be reported with `--timings` or `--timings-json FILE`;
+// `span_of` additionally records a flame span when built with `profiling`.
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::time::Instant;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Stage {
+    pub name: &'static str,
+    pub seconds: f64,
+    // How many elements the stage produced.
+    pub count: usize,
+}
+
+thread_local! {
+    static STAGES: RefCell<Vec<Stage>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(feature = "profiling")]
+pub fn span_of<R, F: FnOnce() -> R>(name: &'static str, f: F) -> R {
+    ::flame::span_of(name, f)
+}
+
+#[cfg(not(feature = "profiling"))]
+pub fn span_of<R, F: FnOnce() -> R>(_name: &'static str, f: F) -> R {
+    f()
+}
+
+// Runs one stage of the pipeline, recording how long it took and how many
+// elements `count` says it produced.
+pub fn stage<R, F, C>(name: &'static str, f: F, count: C) -> R
+where
+    F: FnOnce() -> R,
+    C: FnOnce(&R) -> usize,
+{
+    let start = Instant::now();
+    let result = span_of(name, f);
+    let stage = Stage {
+        name,
+        seconds: start.elapsed().as_secs_f64(),
+        count: count(&result),
+    };
+    STAGES.with(|stages| stages.borrow_mut().push(stage));
+    result
+}
+
+// The stages recorded on this thread so far, clearing the record.
+pub fn take() -> Vec<Stage> {
+    STAGES.with(|stages| stages.borrow_mut().split_off(0))
+}
+
+pub fn write_summary<W: Write>(stages: &[Stage], out: &mut W) -> io::Result<()> {
+    let width = stages.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    for stage in stages {
+        writeln!(
+            out,
+            "{:width$}  {:>9.3}s  {:>9}",
+            stage.name,
+            stage.seconds,
+            stage.count,
+            width = width
+        )?;
+    }
+    let total: f64 = stages.iter().map(|s| s.seconds).sum();
+    writeln!(out, "{:width$}  {:>9.3}s", "total", total, width = width)
+}
+
+#[test]
+fn stages_are_recorded_in_order() {
+    take();
+    let doubled = stage("double", || vec![1, 2, 3], |v| v.len() * 2);
+    stage("empty", || (), |_| 0);
+    assert_eq!(vec![1, 2, 3], doubled);
+
+    let stages = take();
+    let summary: Vec<_> = stages.iter().map(|s| (s.name, s.count)).collect();
+    assert_eq!(vec![("double", 6), ("empty", 0)], summary);
+    assert!(take().is_empty());
+}
@@ -0,0 +1,46 @@
+use osm_load::{find_tag, Tag};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    // The line's `ref` (e.g. "A" or "Red"), falling back to its name.
+    pub line: String,
+    pub colour: Option<String>,
+}
+
+// Colours end up in an SVG style attribute, so only allow hex codes and names.
+fn sanitize_colour(colour: &str) -> Option<String> {
+    let colour = colour.trim();
+    let valid = !colour.is_empty()
+        && colour
+            .chars()
+            .enumerate()
+            .all(|(i, c)| c.is_ascii_alphanumeric() || (i == 0 && c == '#'));
+    if valid {
+        Some(colour.into())
+    } else {
+        None
+    }
+}
+
+// The line served by a `route=subway|light_rail|tram|train` relation.
+pub fn route(relationship_tags: &[Tag]) -> Option<Route> {
+    match find_tag(relationship_tags, "route")? {
+        "subway" | "light_rail" | "tram" | "train" => (),
+        _ => return None,
+    }
+    let line = find_tag(relationship_tags, "ref")
+        .or_else(|| find_tag(relationship_tags, "name"))
+        .unwrap_or("")
+        .to_string();
+    let colour = find_tag(relationship_tags, "colour").and_then(sanitize_colour);
+    Some(Route { line, colour })
+}
+
+// Route relations also list platforms and stops; only their tracks are drawn.
+pub fn is_track(way_tags: &[Tag]) -> bool {
+    matches!(
+        find_tag(way_tags, "railway"),
+        Some("rail") | Some("subway") | Some("light_rail") | Some("tram") | Some("narrow_gauge")
+    )
+}
@@ -0,0 +1,181 @@
+// `mapfun watch`: keeps an extract in memory and re-renders it whenever the
+// rules or overlays it was drawn with change, optionally serving a preview page
+// which reloads itself.  Overlays are given as for a single render.
+//
+// Themes are compiled in, so the map is always drawn with the gray theme and
+// restyling it still means rebuilding.
+
+use osm_load::{self, Feature, Geometry, RangeIdx};
+use osm_xml::OSM;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use theme;
+use timing;
+use {kind_for, load_rules, process, render, Kind, Overlays, RenderOptions, TARGET_HEIGHT};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+const PREVIEW_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>mapfun</title></head>
+<body style="margin:0">
+<img id="map" src="/map.svg?v=0" style="width:100%">
+<script>
+var version = "0";
+setInterval(function () {
+  fetch("/version").then(function (r) { return r.text(); }).then(function (v) {
+    if (v !== version) {
+      version = v;
+      document.getElementById("map").src = "/map.svg?v=" + v;
+    }
+  });
+}, 1000);
+</script>
+</body>
+</html>
+"#;
+
+fn respond(mut stream: TcpStream, svg_file: &str, version: &AtomicUsize) -> std::io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap();
+
+    let (status, content_type, body) = match path {
+        "/" => ("200 OK", "text/html", PREVIEW_PAGE.as_bytes().to_vec()),
+        "/version" => (
+            "200 OK",
+            "text/plain",
+            version.load(Ordering::SeqCst).to_string().into_bytes(),
+        ),
+        "/map.svg" => match std::fs::read(svg_file) {
+            Ok(svg) => ("200 OK", "image/svg+xml", svg),
+            Err(_) => ("404 Not Found", "text/plain", b"not rendered yet".to_vec()),
+        },
+        _ => ("404 Not Found", "text/plain", b"not found".to_vec()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+fn serve(port: u16, svg_file: String, version: Arc<AtomicUsize>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("previewing at http://127.0.0.1:{}/", port);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &svg_file, &version) {
+                eprintln!("preview request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn classify(doc: &OSM, rules_path: Option<&str>) -> std::io::Result<(Geometry, Vec<Kind>)> {
+    let (rules, _) = load_rules(rules_path)?;
+    let filter = |feature: &Feature, range: RangeIdx| {
+        let rule = rules.find(feature)?;
+        kind_for(&rule.layer, feature, range)
+    };
+    Geometry::from_osm(doc, &filter, TARGET_HEIGHT)
+}
+
+pub fn watch(
+    name: &str,
+    rules_path: Option<&str>,
+    overlays: Overlays,
+    port: Option<u16>,
+) -> std::io::Result<()> {
+    let osm_file = format!("./data/osm/{}.osm", name);
+    let svg_file = format!("./data/svg/{}.svg", name);
+
+    // The document is only parsed once; changing the rules re-runs
+    // classification and processing, changing overlays only re-runs export.
+    let doc = osm_load::read_osm(&osm_file)?;
+    let (mut geometry, results) = classify(&doc, rules_path)?;
+    let mut processed = process(results, &geometry);
+    let render = |geometry: &Geometry, processed: &[Kind]| {
+        let options = RenderOptions::new(theme::gray_theme);
+        let rendered = render(geometry, processed, &overlays, &options, &svg_file);
+        // Timings aren't reported while watching, so don't keep them.
+        timing::take();
+        rendered
+    };
+    render(&geometry, &processed)?;
+    println!("wrote {}", svg_file);
+
+    let version = Arc::new(AtomicUsize::new(1));
+    if let Some(port) = port {
+        serve(port, svg_file.clone(), version.clone())?;
+    }
+
+    let mut rules_modified = rules_path.and_then(modified);
+    // Every overlay is watched, not just the defaults.
+    let overlay_times = || -> Vec<_> { overlays.files.iter().map(|f| modified(f)).collect() };
+    let mut overlays_modified = overlay_times();
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let rules_now = rules_path.and_then(modified);
+        let overlays_now = overlay_times();
+        let rules_changed = rules_now != rules_modified;
+        if !rules_changed && overlays_now == overlays_modified {
+            continue;
+        }
+        rules_modified = rules_now;
+        overlays_modified = overlays_now;
+
+        // A broken file is reported and the last good map is kept, so that
+        // saving a half-finished edit doesn't end the session.
+        if rules_changed {
+            match classify(&doc, rules_path) {
+                Ok((new_geometry, results)) => {
+                    processed = process(results, &new_geometry);
+                    geometry = new_geometry;
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            }
+        }
+        if let Err(e) = render(&geometry, &processed) {
+            eprintln!("{}", e);
+            continue;
+        }
+        version.fetch_add(1, Ordering::SeqCst);
+        println!("wrote {}", svg_file);
+    }
+}
+
+#[test]
+fn half_written_overlays_are_errors() {
+    let dir = std::env::temp_dir().join(format!("mapfun-watch-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let overlay = dir.join("subway.geojson");
+    std::fs::write(&overlay, r#"{"type": "FeatureCollection", "features": [{"#).unwrap();
+
+    let geometry = ::network::test_geometry(&[&[1, 2]]);
+    let overlays = Overlays::new(vec![overlay.to_str().unwrap().into()]);
+    let options = RenderOptions::new(theme::gray_theme);
+    let out = dir.join("map.svg");
+    let rendered = render(&geometry, &[], &overlays, &options, out.to_str().unwrap());
+    assert!(rendered.is_err());
+    assert!(!out.exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
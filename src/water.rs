@@ -0,0 +1,25 @@
+use osm_load::{find_tag, Tag};
+use roads::parse_width;
+
+fn default_width(class: &str) -> f64 {
+    match class {
+        "river" => 30.0,
+        "canal" => 12.0,
+        "stream" => 4.0,
+        "ditch" | "drain" => 2.0,
+        _ => 3.0,
+    }
+}
+
+// Width of a linear waterway in metres, or None if the tags don't describe one.
+pub fn waterway_width(tags: &[Tag]) -> Option<f64> {
+    let class = find_tag(tags, "waterway")?;
+    match class {
+        "river" | "canal" | "stream" | "ditch" | "drain" => (),
+        _ => return None,
+    }
+    match find_tag(tags, "width").and_then(parse_width) {
+        Some(width) if width > 0.0 => Some(width),
+        _ => Some(default_width(class)),
+    }
+}